@@ -0,0 +1,15 @@
+CREATE TABLE media_metadata (
+    media_id BIGINT PRIMARY KEY NOT NULL REFERENCES media(id),
+    captured_at TEXT,
+    camera_make TEXT,
+    camera_model TEXT,
+    lens TEXT,
+    exposure_time TEXT,
+    f_number DOUBLE,
+    iso INTEGER,
+    focal_length DOUBLE,
+    orientation SMALLINT,
+    gps_latitude DOUBLE,
+    gps_longitude DOUBLE,
+    gps_altitude DOUBLE
+);
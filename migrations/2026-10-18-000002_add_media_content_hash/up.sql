@@ -0,0 +1,2 @@
+ALTER TABLE media ADD COLUMN content_hash TEXT;
+CREATE INDEX media_content_hash_idx ON media(content_hash);
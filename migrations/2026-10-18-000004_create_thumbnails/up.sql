@@ -0,0 +1,13 @@
+CREATE TABLE thumbnails (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    media_id BIGINT NOT NULL REFERENCES media(id),
+    size INTEGER NOT NULL,
+    file_name TEXT NOT NULL,
+    source_size BIGINT NOT NULL,
+    source_modified BIGINT NOT NULL,
+    source_hash TEXT,
+    bytes BIGINT NOT NULL,
+    last_accessed BIGINT NOT NULL,
+    UNIQUE (media_id, size)
+);
+CREATE INDEX thumbnails_last_accessed_idx ON thumbnails(last_accessed);
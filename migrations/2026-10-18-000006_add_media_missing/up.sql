@@ -0,0 +1 @@
+ALTER TABLE media ADD COLUMN missing BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+DROP INDEX base_paths_marker;
+
+ALTER TABLE base_paths DROP COLUMN marker;
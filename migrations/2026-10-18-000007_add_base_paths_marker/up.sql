@@ -0,0 +1,3 @@
+ALTER TABLE base_paths ADD COLUMN marker TEXT;
+
+CREATE UNIQUE INDEX base_paths_marker ON base_paths (marker);
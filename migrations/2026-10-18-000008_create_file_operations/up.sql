@@ -0,0 +1,10 @@
+CREATE TABLE file_operations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    media_id BIGINT NOT NULL,
+    operation TEXT NOT NULL,
+    source TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    base_path_id INTEGER,
+    relative_path TEXT,
+    started_at BIGINT NOT NULL
+);
@@ -0,0 +1 @@
+DROP TABLE organizer_moves;
@@ -0,0 +1,10 @@
+CREATE TABLE organizer_moves (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    run_id TEXT NOT NULL,
+    media_id BIGINT NOT NULL,
+    base_path_id INTEGER NOT NULL,
+    from_path TEXT NOT NULL,
+    to_path TEXT NOT NULL,
+    undone BOOLEAN NOT NULL DEFAULT 0
+);
+CREATE INDEX organizer_moves_run_id_idx ON organizer_moves(run_id);
@@ -0,0 +1,2 @@
+DROP TABLE scrub_cursors;
+DROP TABLE media_verifications;
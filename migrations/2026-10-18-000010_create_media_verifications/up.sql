@@ -0,0 +1,9 @@
+CREATE TABLE media_verifications (
+    media_id BIGINT PRIMARY KEY NOT NULL REFERENCES media(id),
+    verified_at BIGINT NOT NULL,
+    result TEXT NOT NULL
+);
+CREATE TABLE scrub_cursors (
+    base_path_id INTEGER PRIMARY KEY NOT NULL REFERENCES base_paths(id),
+    last_media_id BIGINT NOT NULL
+);
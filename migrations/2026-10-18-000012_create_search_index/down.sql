@@ -0,0 +1,14 @@
+DROP TRIGGER base_paths_search_delete;
+DROP TRIGGER base_paths_search_update;
+DROP TRIGGER base_paths_search_insert;
+DROP TRIGGER tag_categories_search_delete;
+DROP TRIGGER tag_categories_search_update;
+DROP TRIGGER tag_categories_search_insert;
+DROP TRIGGER tags_search_delete;
+DROP TRIGGER tags_search_update;
+DROP TRIGGER tags_search_insert;
+DROP TRIGGER media_search_delete;
+DROP TRIGGER media_search_update;
+DROP TRIGGER media_search_insert;
+DROP VIEW search_documents;
+DROP TABLE search_index;
@@ -0,0 +1,66 @@
+-- The rowid encodes the entity: id * 4 + 0 for media, 1 for tags, 2 for
+-- tag categories and 3 for base paths.
+CREATE VIRTUAL TABLE search_index USING fts5(
+    title,
+    body,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+-- The entries of the index, from which it is filled here and rebuilt by the
+-- application.
+CREATE VIEW search_documents (id, title, body) AS
+SELECT id * 4, relative_path || COALESCE('/' || archive_entry, ''), description FROM media
+UNION ALL
+SELECT id * 4 + 1, name, description FROM tags
+UNION ALL
+SELECT id * 4 + 2, name, description FROM tag_categories
+UNION ALL
+SELECT id * 4 + 3, base_path, description FROM base_paths;
+
+INSERT INTO search_index (rowid, title, body) SELECT id, title, body FROM search_documents;
+
+CREATE TRIGGER media_search_insert AFTER INSERT ON media BEGIN
+    INSERT INTO search_index (rowid, title, body)
+    VALUES (new.id * 4, new.relative_path || COALESCE('/' || new.archive_entry, ''), new.description);
+END;
+CREATE TRIGGER media_search_update AFTER UPDATE OF relative_path, archive_entry, description ON media BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 4;
+    INSERT INTO search_index (rowid, title, body)
+    VALUES (new.id * 4, new.relative_path || COALESCE('/' || new.archive_entry, ''), new.description);
+END;
+CREATE TRIGGER media_search_delete AFTER DELETE ON media BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 4;
+END;
+
+CREATE TRIGGER tags_search_insert AFTER INSERT ON tags BEGIN
+    INSERT INTO search_index (rowid, title, body) VALUES (new.id * 4 + 1, new.name, new.description);
+END;
+CREATE TRIGGER tags_search_update AFTER UPDATE OF name, description ON tags BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 4 + 1;
+    INSERT INTO search_index (rowid, title, body) VALUES (new.id * 4 + 1, new.name, new.description);
+END;
+CREATE TRIGGER tags_search_delete AFTER DELETE ON tags BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 4 + 1;
+END;
+
+CREATE TRIGGER tag_categories_search_insert AFTER INSERT ON tag_categories BEGIN
+    INSERT INTO search_index (rowid, title, body) VALUES (new.id * 4 + 2, new.name, new.description);
+END;
+CREATE TRIGGER tag_categories_search_update AFTER UPDATE OF name, description ON tag_categories BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 4 + 2;
+    INSERT INTO search_index (rowid, title, body) VALUES (new.id * 4 + 2, new.name, new.description);
+END;
+CREATE TRIGGER tag_categories_search_delete AFTER DELETE ON tag_categories BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 4 + 2;
+END;
+
+CREATE TRIGGER base_paths_search_insert AFTER INSERT ON base_paths BEGIN
+    INSERT INTO search_index (rowid, title, body) VALUES (new.id * 4 + 3, new.base_path, new.description);
+END;
+CREATE TRIGGER base_paths_search_update AFTER UPDATE OF base_path, description ON base_paths BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 4 + 3;
+    INSERT INTO search_index (rowid, title, body) VALUES (new.id * 4 + 3, new.base_path, new.description);
+END;
+CREATE TRIGGER base_paths_search_delete AFTER DELETE ON base_paths BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 4 + 3;
+END;
@@ -0,0 +1,17 @@
+DROP INDEX media_tags_tag_id;
+DROP TRIGGER tag_categories_words_delete;
+DROP TRIGGER tag_categories_words_update;
+DROP TRIGGER tag_categories_words_insert;
+DROP TABLE category_name_words;
+DROP TRIGGER tags_words_delete;
+DROP TRIGGER tags_words_update;
+DROP TRIGGER tags_words_insert;
+DROP TABLE tag_name_words;
+DROP TRIGGER tag_categories_trigrams_delete;
+DROP TRIGGER tag_categories_trigrams_update;
+DROP TRIGGER tag_categories_trigrams_insert;
+DROP TABLE category_name_trigrams;
+DROP TRIGGER tags_trigrams_delete;
+DROP TRIGGER tags_trigrams_update;
+DROP TRIGGER tags_trigrams_insert;
+DROP TABLE tag_name_trigrams;
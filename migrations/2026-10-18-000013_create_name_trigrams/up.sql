@@ -0,0 +1,63 @@
+-- Trigram indexes of the names of tags and categories, whose rowid is the ID
+-- of the tag or category, used to match names anywhere and with typos.
+CREATE VIRTUAL TABLE tag_name_trigrams USING fts5(name, tokenize = 'trigram');
+INSERT INTO tag_name_trigrams (rowid, name) SELECT id, name FROM tags;
+
+CREATE TRIGGER tags_trigrams_insert AFTER INSERT ON tags BEGIN
+    INSERT INTO tag_name_trigrams (rowid, name) VALUES (new.id, new.name);
+END;
+CREATE TRIGGER tags_trigrams_update AFTER UPDATE OF name ON tags BEGIN
+    DELETE FROM tag_name_trigrams WHERE rowid = old.id;
+    INSERT INTO tag_name_trigrams (rowid, name) VALUES (new.id, new.name);
+END;
+CREATE TRIGGER tags_trigrams_delete AFTER DELETE ON tags BEGIN
+    DELETE FROM tag_name_trigrams WHERE rowid = old.id;
+END;
+
+CREATE VIRTUAL TABLE category_name_trigrams USING fts5(name, tokenize = 'trigram');
+INSERT INTO category_name_trigrams (rowid, name) SELECT id, name FROM tag_categories;
+
+CREATE TRIGGER tag_categories_trigrams_insert AFTER INSERT ON tag_categories BEGIN
+    INSERT INTO category_name_trigrams (rowid, name) VALUES (new.id, new.name);
+END;
+CREATE TRIGGER tag_categories_trigrams_update AFTER UPDATE OF name ON tag_categories BEGIN
+    DELETE FROM category_name_trigrams WHERE rowid = old.id;
+    INSERT INTO category_name_trigrams (rowid, name) VALUES (new.id, new.name);
+END;
+CREATE TRIGGER tag_categories_trigrams_delete AFTER DELETE ON tag_categories BEGIN
+    DELETE FROM category_name_trigrams WHERE rowid = old.id;
+END;
+
+-- Word indexes of the names of tags and categories, whose rowid is the ID of
+-- the tag or category, used for prefix matches of texts shorter than a
+-- trigram. Unlike lower(), they ignore the case of non-ASCII letters too.
+CREATE VIRTUAL TABLE tag_name_words USING fts5(name, tokenize = 'unicode61 remove_diacritics 0');
+INSERT INTO tag_name_words (rowid, name) SELECT id, name FROM tags;
+
+CREATE TRIGGER tags_words_insert AFTER INSERT ON tags BEGIN
+    INSERT INTO tag_name_words (rowid, name) VALUES (new.id, new.name);
+END;
+CREATE TRIGGER tags_words_update AFTER UPDATE OF name ON tags BEGIN
+    DELETE FROM tag_name_words WHERE rowid = old.id;
+    INSERT INTO tag_name_words (rowid, name) VALUES (new.id, new.name);
+END;
+CREATE TRIGGER tags_words_delete AFTER DELETE ON tags BEGIN
+    DELETE FROM tag_name_words WHERE rowid = old.id;
+END;
+
+CREATE VIRTUAL TABLE category_name_words USING fts5(name, tokenize = 'unicode61 remove_diacritics 0');
+INSERT INTO category_name_words (rowid, name) SELECT id, name FROM tag_categories;
+
+CREATE TRIGGER tag_categories_words_insert AFTER INSERT ON tag_categories BEGIN
+    INSERT INTO category_name_words (rowid, name) VALUES (new.id, new.name);
+END;
+CREATE TRIGGER tag_categories_words_update AFTER UPDATE OF name ON tag_categories BEGIN
+    DELETE FROM category_name_words WHERE rowid = old.id;
+    INSERT INTO category_name_words (rowid, name) VALUES (new.id, new.name);
+END;
+CREATE TRIGGER tag_categories_words_delete AFTER DELETE ON tag_categories BEGIN
+    DELETE FROM category_name_words WHERE rowid = old.id;
+END;
+
+-- Counting how many media use each tag.
+CREATE INDEX media_tags_tag_id ON media_tags (tag_id);
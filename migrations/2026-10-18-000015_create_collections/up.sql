@@ -0,0 +1,21 @@
+CREATE TABLE collections (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    parent_id INTEGER REFERENCES collections(id),
+    cover_media_id BIGINT REFERENCES media(id),
+    created_at BIGINT NOT NULL
+);
+CREATE INDEX collections_parent_id ON collections(parent_id);
+-- Names are unique among the collections with the same parent, ignoring the
+-- case; top-level collections have no parent.
+CREATE UNIQUE INDEX collections_parent_name ON collections(COALESCE(parent_id, 0), name COLLATE NOCASE);
+
+CREATE TABLE collection_media (
+    collection_id INTEGER NOT NULL REFERENCES collections(id),
+    media_id BIGINT NOT NULL REFERENCES media(id),
+    position INTEGER NOT NULL,
+    PRIMARY KEY (collection_id, media_id)
+);
+CREATE INDEX collection_media_media_id ON collection_media(media_id);
+CREATE INDEX collection_media_position ON collection_media(collection_id, position);
@@ -10,4 +10,7 @@ pub struct BasePath {
     pub base_path: String,
     // Description for this base path.
     pub description: String,
+    /// The UUID written in the marker file at the root of a portable base
+    /// path, used to find it again when mounted elsewhere.
+    pub marker: Option<String>,
 }
@@ -0,0 +1,19 @@
+use diesel::Queryable;
+use serde::Serialize;
+
+/// A collection of media in a chosen order, e.g. an album or a slideshow.
+#[derive(Debug, Clone, Queryable, Serialize)]
+pub struct Collection {
+    /// The ID of the collection.
+    pub id: i32,
+    /// The name, unique among the collections with the same parent.
+    pub name: String,
+    /// A description for this collection.
+    pub description: String,
+    /// The collection containing this one, if any.
+    pub parent_id: Option<i32>,
+    /// The media shown for the collection, one of its media.
+    pub cover_media_id: Option<i64>,
+    /// When it was created, in seconds since the Unix epoch.
+    pub created_at: i64,
+}
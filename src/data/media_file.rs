@@ -6,6 +6,7 @@ use diesel::{
     AsChangeset, Queryable,
 };
 use serde::Serialize;
+use std::path::Path;
 
 /// This represents a media type.
 #[derive(Debug, Serialize)]
@@ -38,6 +39,31 @@ impl From<MediaType> for String {
     }
 }
 
+impl MediaType {
+    /// Guesses the media type from the extension of the provided path.
+    ///
+    /// It returns `Unknown` in case the extension is missing or is not a
+    /// known image, video or sound extension.
+    pub fn from_path(path: impl AsRef<Path>) -> MediaType {
+        let extension = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            None => return MediaType::Unknown,
+            Some(ext) => ext.to_ascii_lowercase(),
+        };
+
+        match extension.as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "bmp" | "tif" | "tiff" | "webp" | "heic" | "heif"
+            | "avif" => MediaType::Image,
+            "mp4" | "m4v" | "mov" | "avi" | "mkv" | "webm" | "wmv" | "mpg" | "mpeg" | "3gp" => {
+                MediaType::Video
+            }
+            "mp3" | "wav" | "flac" | "ogg" | "oga" | "opus" | "m4a" | "aac" | "wma" => {
+                MediaType::Sound
+            }
+            _ => MediaType::Unknown,
+        }
+    }
+}
+
 // Needed for a good deserialization for [`MediaType`].
 impl<DB> Queryable<Text, DB> for MediaType
 where
@@ -75,4 +101,18 @@ pub struct MediaFile {
     /// See [`MediaType`]
     #[diesel(serialize_as = String)]
     pub media_type: MediaType,
+    /// The BLAKE3 hash of the content of the file, as a hex string.
+    pub content_hash: Option<String>,
+    /// The perceptual hash, if an image.
+    /// See [`perceptual_hash`](crate::media::hash::perceptual_hash).
+    pub perceptual_hash: Option<i64>,
+    /// The modification time of the file, as a UNIX timestamp, when it was
+    /// last seen on disk.
+    pub modified: Option<i64>,
+    /// Whether the file has been found missing on disk and the media file is
+    /// kept for review.
+    pub missing: bool,
+    /// The path of the entry inside the archive at `relative_path`, if the
+    /// media file is stored in an archive, e.g. a page of a `.cbz`.
+    pub archive_entry: Option<String>,
 }
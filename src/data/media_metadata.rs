@@ -0,0 +1,36 @@
+use diesel::{AsChangeset, Insertable, Queryable};
+use serde::Serialize;
+
+use crate::database::schema::media_metadata;
+
+/// This represents the metadata, e.g. EXIF, extracted from a media file.
+#[derive(Debug, Default, Queryable, Serialize, Insertable, AsChangeset)]
+#[diesel(table_name = media_metadata)]
+pub struct MediaMetadata {
+    /// The ID of the media file this metadata belongs to.
+    pub media_id: i64,
+    /// When the media was captured, formatted as `YYYY-MM-DD HH:MM:SS`.
+    pub captured_at: Option<String>,
+    /// The manufacturer of the camera.
+    pub camera_make: Option<String>,
+    /// The model of the camera.
+    pub camera_model: Option<String>,
+    /// The lens used to capture the media.
+    pub lens: Option<String>,
+    /// The exposure time in seconds, e.g. `1/250`.
+    pub exposure_time: Option<String>,
+    /// The F number, e.g. `2.8`.
+    pub f_number: Option<f64>,
+    /// The ISO sensitivity.
+    pub iso: Option<i32>,
+    /// The focal length in millimeters.
+    pub focal_length: Option<f64>,
+    /// The EXIF orientation, from 1 to 8.
+    pub orientation: Option<i16>,
+    /// The latitude in decimal degrees, negative if south.
+    pub gps_latitude: Option<f64>,
+    /// The longitude in decimal degrees, negative if west.
+    pub gps_longitude: Option<f64>,
+    /// The altitude in meters, negative if below sea level.
+    pub gps_altitude: Option<f64>,
+}
@@ -1,4 +1,7 @@
 pub mod base_path;
+pub mod collection;
 pub mod media_file;
+pub mod media_metadata;
+pub mod saved_search;
 pub mod tag;
 pub mod tag_category;
@@ -0,0 +1,21 @@
+use serde::Serialize;
+
+use crate::media::query::MediaQuery;
+
+/// A named query, evaluated against the current library each time, like a
+/// smart album.
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedSearch {
+    /// The ID of the saved search.
+    pub id: i32,
+    /// The name, unique ignoring the case.
+    pub name: String,
+    /// A description for this saved search.
+    pub description: String,
+    /// The filters and sort order of the saved search.
+    pub query: MediaQuery,
+    /// When it was created, in seconds since the Unix epoch.
+    pub created_at: i64,
+    /// When it was last modified, in seconds since the Unix epoch.
+    pub updated_at: i64,
+}
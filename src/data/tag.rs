@@ -4,7 +4,7 @@ use serde::Serialize;
 use crate::database::schema::tags;
 
 /// This represents a tag.
-#[derive(Debug, Queryable, Serialize, AsChangeset)]
+#[derive(Debug, Clone, Queryable, Serialize, AsChangeset)]
 #[diesel(table_name = tags)]
 pub struct Tag {
     /// The ID of the tag in the database.
@@ -4,7 +4,7 @@ use serde::Serialize;
 use crate::database::schema::tag_categories;
 
 /// This represents a tag category.
-#[derive(Debug, Queryable, Serialize, AsChangeset)]
+#[derive(Debug, Clone, Queryable, Serialize, AsChangeset)]
 #[diesel(table_name = tag_categories)]
 pub struct Category {
     /// The id of this category.
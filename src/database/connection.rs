@@ -1,5 +1,5 @@
 use diesel::{sqlite::SqliteConnection, Connection};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 const MAIN_DATABASE_FILE_NAME: &str = "main.db";
@@ -36,6 +36,7 @@ pub enum DatabaseLocation<'a> {
 /// This represents a database connection.
 pub struct DatabaseConnection {
     database_location: String,
+    data_directory: Option<PathBuf>,
 }
 
 impl DatabaseConnection {
@@ -48,7 +49,7 @@ impl DatabaseConnection {
     ///
     /// It returns an error in case the path is invalid, is not a directory.
     pub fn new(location: DatabaseLocation) -> Result<Self, Error> {
-        let database_location = match location {
+        let (database_location, data_directory) = match location {
             DatabaseLocation::Path(dir, name) => {
                 if dir.is_empty() {
                     return Err(Error::InvalidPath);
@@ -70,17 +71,37 @@ impl DatabaseConnection {
                     }
                 };
 
-                database_dir
-                    .join(database_name)
-                    .to_str()
-                    .unwrap()
-                    .to_owned()
+                (
+                    database_dir
+                        .join(database_name)
+                        .to_str()
+                        .unwrap()
+                        .to_owned(),
+                    Some(database_dir.to_path_buf()),
+                )
             }
             // TODO: this needs validation too
-            DatabaseLocation::URL(url) => url.into(),
+            DatabaseLocation::URL(url) => (
+                url.into(),
+                Path::new(url.trim_start_matches("file:"))
+                    .parent()
+                    .filter(|dir| dir.is_dir())
+                    .map(|dir| dir.to_path_buf()),
+            ),
         };
 
-        Ok(Self { database_location })
+        Ok(Self {
+            database_location,
+            data_directory,
+        })
+    }
+
+    /// Returns the directory that contains the database file, where other
+    /// data, e.g. caches, can be stored.
+    ///
+    /// It is `None` in case the database is not a file on this computer.
+    pub(crate) fn data_directory(&self) -> Option<&Path> {
+        self.data_directory.as_deref()
     }
 
     pub(crate) fn establish_connection(&self) -> Result<SqliteConnection, Error> {
@@ -95,6 +116,7 @@ impl Clone for DatabaseConnection {
     fn clone(&self) -> Self {
         Self {
             database_location: self.database_location.clone(),
+            data_directory: self.data_directory.clone(),
         }
     }
 }
@@ -5,6 +5,39 @@ diesel::table! {
         id -> Integer,
         base_path -> Text,
         description -> Text,
+        marker -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    collection_media (collection_id, media_id) {
+        collection_id -> Integer,
+        media_id -> BigInt,
+        position -> Integer,
+    }
+}
+
+diesel::table! {
+    collections (id) {
+        id -> Integer,
+        name -> Text,
+        description -> Text,
+        parent_id -> Nullable<Integer>,
+        cover_media_id -> Nullable<BigInt>,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    file_operations (id) {
+        id -> Integer,
+        media_id -> BigInt,
+        operation -> Text,
+        source -> Text,
+        destination -> Text,
+        base_path_id -> Nullable<Integer>,
+        relative_path -> Nullable<Text>,
+        started_at -> BigInt,
     }
 }
 
@@ -19,6 +52,29 @@ diesel::table! {
         mark -> Nullable<SmallInt>,
         description -> Text,
         media_type -> Text,
+        content_hash -> Nullable<Text>,
+        perceptual_hash -> Nullable<BigInt>,
+        modified -> Nullable<BigInt>,
+        missing -> Bool,
+        archive_entry -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    media_metadata (media_id) {
+        media_id -> BigInt,
+        captured_at -> Nullable<Text>,
+        camera_make -> Nullable<Text>,
+        camera_model -> Nullable<Text>,
+        lens -> Nullable<Text>,
+        exposure_time -> Nullable<Text>,
+        f_number -> Nullable<Double>,
+        iso -> Nullable<Integer>,
+        focal_length -> Nullable<Double>,
+        orientation -> Nullable<SmallInt>,
+        gps_latitude -> Nullable<Double>,
+        gps_longitude -> Nullable<Double>,
+        gps_altitude -> Nullable<Double>,
     }
 }
 
@@ -30,6 +86,44 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    media_verifications (media_id) {
+        media_id -> BigInt,
+        verified_at -> BigInt,
+        result -> Text,
+    }
+}
+
+diesel::table! {
+    organizer_moves (id) {
+        id -> Integer,
+        run_id -> Text,
+        media_id -> BigInt,
+        base_path_id -> Integer,
+        from_path -> Text,
+        to_path -> Text,
+        undone -> Bool,
+    }
+}
+
+diesel::table! {
+    saved_searches (id) {
+        id -> Integer,
+        name -> Text,
+        description -> Text,
+        query -> Text,
+        created_at -> BigInt,
+        updated_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    scrub_cursors (base_path_id) {
+        base_path_id -> Integer,
+        last_media_id -> BigInt,
+    }
+}
+
 diesel::table! {
     tag_categories (id) {
         id -> Integer,
@@ -48,9 +142,44 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    thumbnails (id) {
+        id -> BigInt,
+        media_id -> BigInt,
+        size -> Integer,
+        file_name -> Text,
+        source_size -> BigInt,
+        source_modified -> BigInt,
+        source_hash -> Nullable<Text>,
+        bytes -> BigInt,
+        last_accessed -> BigInt,
+    }
+}
+
+diesel::joinable!(collection_media -> collections (collection_id));
+diesel::joinable!(collection_media -> media (media_id));
 diesel::joinable!(media -> base_paths (base_path_id));
+diesel::joinable!(media_metadata -> media (media_id));
 diesel::joinable!(media_tags -> media (media_id));
 diesel::joinable!(media_tags -> tags (tag_id));
+diesel::joinable!(media_verifications -> media (media_id));
+diesel::joinable!(scrub_cursors -> base_paths (base_path_id));
 diesel::joinable!(tags -> tag_categories (category_id));
+diesel::joinable!(thumbnails -> media (media_id));
 
-diesel::allow_tables_to_appear_in_same_query!(base_paths, media, media_tags, tag_categories, tags,);
+diesel::allow_tables_to_appear_in_same_query!(
+    base_paths,
+    collection_media,
+    collections,
+    file_operations,
+    media,
+    media_metadata,
+    media_tags,
+    media_verifications,
+    organizer_moves,
+    saved_searches,
+    scrub_cursors,
+    tag_categories,
+    tags,
+    thumbnails,
+);
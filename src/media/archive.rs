@@ -0,0 +1,191 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use flate2::read::GzDecoder;
+use thiserror::Error;
+
+use crate::data::media_file::MediaType;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The archive could not be opened or read.
+    #[error("cannot read archive: {0}")]
+    IOError(#[from] io::Error),
+    /// The zip archive is corrupted.
+    #[error("cannot read zip archive: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+    /// The archive does not contain the requested entry.
+    #[error("entry not found")]
+    EntryNotFound,
+    /// The file is not an archive of a supported format.
+    #[error("unsupported archive format")]
+    UnsupportedFormat,
+}
+
+/// The archive formats whose entries can be registered as media.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Format> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") || name.ends_with(".cbz") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar") || name.ends_with(".cbt") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// A media file found inside an archive.
+#[derive(Debug)]
+pub struct ArchiveEntry {
+    /// The path of the entry inside the archive, using `/` as separator.
+    pub name: String,
+    /// The media type, guessed from the extension.
+    pub media_type: MediaType,
+    /// The uncompressed size in bytes.
+    pub size: u64,
+}
+
+/// Returns whether the file at the provided path is an archive whose entries
+/// can be registered as media, i.e. a zip, cbz, tar, cbt or gzipped tar,
+/// guessed from the extension.
+pub fn is_archive(path: impl AsRef<Path>) -> bool {
+    Format::from_path(path.as_ref()).is_some()
+}
+
+/// Lists the media files inside the archive at the provided path, ordered by
+/// name, without extracting them.
+///
+/// Directories and entries whose extension is not a known media type are
+/// skipped, as well as hidden entries, e.g. `__MACOSX/` folders.
+pub fn list_entries(path: impl AsRef<Path>) -> Result<Vec<ArchiveEntry>, Error> {
+    let path = path.as_ref();
+    let mut entries = vec![];
+    let mut push = |name: &str, size: u64| {
+        let name = normalize_name(name);
+        if name.is_empty() || is_hidden(name) {
+            return;
+        }
+
+        let media_type = MediaType::from_path(name);
+        if !matches!(media_type, MediaType::Unknown) {
+            entries.push(ArchiveEntry {
+                name: name.to_string(),
+                media_type,
+                size,
+            });
+        }
+    };
+
+    match Format::from_path(path).ok_or(Error::UnsupportedFormat)? {
+        Format::Zip => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+            for i in 0..archive.len() {
+                let entry = archive.by_index_raw(i)?;
+                // Names escaping the archive, e.g. with `..`, are ignored.
+                if entry.is_file() && entry.enclosed_name().is_some() {
+                    push(entry.name(), entry.size());
+                }
+            }
+        }
+        format => {
+            let mut archive = tar::Archive::new(tar_reader(path, format)?);
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    if let Some(name) = entry.path()?.to_str() {
+                        push(name, entry.size());
+                    }
+                }
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Reads the content of the entry with the provided name from the archive at
+/// the provided path, without extracting it to disk.
+///
+/// It returns an error in case the archive cannot be read or does not
+/// contain the entry.
+pub fn read_entry(path: impl AsRef<Path>, name: impl AsRef<str>) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+    let name = normalize_name(name.as_ref());
+    let mut content = vec![];
+
+    match Format::from_path(path).ok_or(Error::UnsupportedFormat)? {
+        Format::Zip => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+            // The entry may be stored with a prefix that was normalized
+            // away when listing, e.g. `./`.
+            let index = match archive.index_for_name(name) {
+                Some(index) => index,
+                None => archive
+                    .file_names()
+                    .find(|stored| normalize_name(stored) == name)
+                    .and_then(|stored| archive.index_for_name(stored))
+                    .ok_or(Error::EntryNotFound)?,
+            };
+            let mut entry = archive.by_index(index)?;
+            entry.read_to_end(&mut content)?;
+        }
+        format => {
+            // Entries of a tar archive can only be reached sequentially.
+            let mut archive = tar::Archive::new(tar_reader(path, format)?);
+            let mut entry = archive
+                .entries()?
+                .filter_map(Result::ok)
+                .find(|entry| {
+                    entry.header().entry_type().is_file()
+                        && entry
+                            .path()
+                            .ok()
+                            .and_then(|p| p.to_str().map(|p| normalize_name(p) == name))
+                            .unwrap_or_default()
+                })
+                .ok_or(Error::EntryNotFound)?;
+            entry.read_to_end(&mut content)?;
+        }
+    }
+
+    Ok(content)
+}
+
+fn tar_reader(path: &Path, format: Format) -> io::Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(match format {
+        Format::TarGz => Box::new(GzDecoder::new(file)),
+        _ => Box::new(file),
+    })
+}
+
+/// Returns the name of an entry without its leading and trailing `/` and its
+/// leading `./`, e.g. as stored by `tar -czf archive.tgz .`.
+fn normalize_name(name: &str) -> &str {
+    let mut name = name.trim_matches('/');
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest.trim_start_matches('/');
+    }
+    name
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.split('/')
+        .any(|component| component.starts_with('.') || component == "__MACOSX")
+}
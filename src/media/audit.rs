@@ -0,0 +1,317 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl, SqliteConnection};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    data::media_file::MediaFile,
+    database::{self, connection::DatabaseConnection, schema::media::dsl::media as media_table},
+    media::{
+        archive::{self, ArchiveEntry},
+        base_paths::{self, base_paths, Status},
+        collections,
+        media::{MAX_BOUND_IDS, SIZE_TOLERANCE_KB},
+        thumbnails,
+    },
+};
+
+/// Audit contains code that checks whether the files of the media are still
+/// on disk, and that cleans up the media whose files are gone.
+pub struct Audit {
+    connection: DatabaseConnection,
+}
+
+/// This returns a new instance of the `Audit` struct.
+pub fn audit(connection: DatabaseConnection) -> Audit {
+    Audit { connection }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The base paths could not be loaded.
+    #[error("base path error: {0}")]
+    BasePathsError(#[from] base_paths::Error),
+    /// The thumbnails of a forgotten media could not be removed.
+    #[error("thumbnail error: {0}")]
+    ThumbnailError(#[from] thumbnails::Error),
+}
+
+/// The result of auditing all the media files of a base path.
+#[derive(Debug, Serialize)]
+pub struct BasePathAudit {
+    pub base_path_id: i32,
+    /// When offline the files are not checked at all.
+    pub status: Status,
+    /// How many media files have been checked.
+    pub checked: usize,
+    /// How many media files were found with the expected size.
+    pub ok: usize,
+    /// IDs of the media whose file does not exist, or whose entry is not in
+    /// its archive anymore.
+    pub missing: Vec<i64>,
+    pub size_mismatches: Vec<SizeMismatch>,
+    pub unreadable: Vec<Unreadable>,
+}
+
+/// A media file whose size on disk differs from the stored one.
+#[derive(Debug, Serialize)]
+pub struct SizeMismatch {
+    pub media_id: i64,
+    /// The stored size, in kB.
+    pub expected: f64,
+    /// The size on disk, in kB.
+    pub actual: f64,
+}
+
+/// A media file that exists but cannot be read, e.g. due to permissions.
+#[derive(Debug, Serialize)]
+pub struct Unreadable {
+    pub media_id: i64,
+    pub error: String,
+}
+
+impl Audit {
+    /// Checks the files of all the media of the provided base paths, or of
+    /// all base paths if `base_path_ids` is `None` or empty, returning one
+    /// report per base path.
+    ///
+    /// Only the metadata of the files is read, except for opening them to
+    /// make sure they are readable: their content is not hashed.
+    pub fn run(
+        &self,
+        base_path_ids: Option<impl IntoIterator<Item = i32>>,
+    ) -> Result<Vec<BasePathAudit>, Error> {
+        let mut reports = vec![];
+        for bp in base_paths(self.connection.clone()).list(base_path_ids)? {
+            let status = base_paths::status(&bp);
+            let mut report = BasePathAudit {
+                base_path_id: bp.id,
+                status,
+                checked: 0,
+                ok: 0,
+                missing: vec![],
+                size_mismatches: vec![],
+                unreadable: vec![],
+            };
+
+            if status == Status::Offline {
+                reports.push(report);
+                continue;
+            }
+
+            let files = {
+                use database::schema::media::dsl::{base_path_id, id};
+                let conn = &mut self.connection.establish_connection()?;
+                media_table
+                    .filter(base_path_id.eq(bp.id))
+                    .order(id.asc())
+                    .load::<MediaFile>(conn)?
+            };
+
+            // Archives are listed once, however many of their entries are
+            // registered.
+            let mut archives: HashMap<String, Result<Vec<ArchiveEntry>, String>> = HashMap::new();
+            for file in files {
+                report.checked += 1;
+                let path = Path::new(&bp.base_path).join(&file.relative_path);
+                let metadata = match fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => metadata,
+                    Ok(_) => {
+                        report.missing.push(file.id);
+                        continue;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        report.missing.push(file.id);
+                        continue;
+                    }
+                    Err(err) => {
+                        report.unreadable.push(Unreadable {
+                            media_id: file.id,
+                            error: err.to_string(),
+                        });
+                        continue;
+                    }
+                };
+
+                if let Err(err) = File::open(&path) {
+                    report.unreadable.push(Unreadable {
+                        media_id: file.id,
+                        error: err.to_string(),
+                    });
+                    continue;
+                }
+
+                let actual_bytes = match &file.archive_entry {
+                    None => metadata.len(),
+                    Some(name) => {
+                        let entries =
+                            archives
+                                .entry(file.relative_path.clone())
+                                .or_insert_with(|| {
+                                    archive::list_entries(&path).map_err(|err| err.to_string())
+                                });
+                        match entries {
+                            Err(err) => {
+                                report.unreadable.push(Unreadable {
+                                    media_id: file.id,
+                                    error: err.clone(),
+                                });
+                                continue;
+                            }
+                            Ok(entries) => match entries.iter().find(|e| &e.name == name) {
+                                Some(entry) => entry.size,
+                                None => {
+                                    report.missing.push(file.id);
+                                    continue;
+                                }
+                            },
+                        }
+                    }
+                };
+
+                let actual = actual_bytes as f64 / 1024.0;
+                if (actual - file.size).abs() >= SIZE_TOLERANCE_KB {
+                    report.size_mismatches.push(SizeMismatch {
+                        media_id: file.id,
+                        expected: file.size,
+                        actual,
+                    });
+                    continue;
+                }
+
+                report.ok += 1;
+            }
+
+            reports.push(report);
+        }
+
+        Ok(reports)
+    }
+
+    /// Deletes the media with the provided IDs, along with their tags,
+    /// metadata, collection memberships and thumbnails, e.g. the `missing`
+    /// ones of an audit.
+    ///
+    /// Media whose file is back on disk, or whose base path is offline, are
+    /// skipped. It returns the number of media that have been deleted.
+    pub fn forget(&self, media_ids: &[i64]) -> Result<usize, Error> {
+        let ids = self.still_missing(media_ids)?;
+
+        let thumbnails = thumbnails::thumbnails(self.connection.clone());
+        for media_id in &ids {
+            thumbnails.remove(*media_id)?;
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| delete_media_rows(conn, &ids))
+            .map_err(Error::DatabaseError)
+    }
+
+    /// Marks the media with the provided IDs as missing, so that they can be
+    /// reviewed later, e.g. the `missing` ones of an audit.
+    ///
+    /// Media whose file is back on disk, or whose base path is offline, are
+    /// skipped. It returns the number of media that have been flagged.
+    pub fn flag(&self, media_ids: &[i64]) -> Result<usize, Error> {
+        let ids = self.still_missing(media_ids)?;
+
+        use database::schema::media::dsl::{id, missing};
+        let conn = &mut self.connection.establish_connection()?;
+        let mut flagged = 0;
+        for chunk in ids.chunks(MAX_BOUND_IDS) {
+            flagged += diesel::update(media_table.filter(id.eq_any(chunk)))
+                .set(missing.eq(true))
+                .execute(conn)?;
+        }
+        Ok(flagged)
+    }
+
+    /// Returns the IDs among the provided ones whose file is still missing
+    /// from an online base path.
+    fn still_missing(&self, media_ids: &[i64]) -> Result<Vec<i64>, Error> {
+        let files = {
+            use database::schema::media::dsl::id;
+            let conn = &mut self.connection.establish_connection()?;
+            let mut files = vec![];
+            for chunk in media_ids.chunks(MAX_BOUND_IDS) {
+                files.extend(
+                    media_table
+                        .filter(id.eq_any(chunk))
+                        .load::<MediaFile>(conn)?,
+                );
+            }
+            files
+        };
+
+        let online: Vec<_> = base_paths(self.connection.clone())
+            .list_with_status(None::<Vec<_>>)?
+            .into_iter()
+            .filter(|(_, status)| *status == Status::Online)
+            .map(|(bp, _)| bp)
+            .collect();
+
+        Ok(files
+            .into_iter()
+            .filter(|file| {
+                online
+                    .iter()
+                    .find(|bp| bp.id == file.base_path_id)
+                    .is_some_and(|bp| {
+                        let path = Path::new(&bp.base_path).join(&file.relative_path);
+                        match &file.archive_entry {
+                            None => !path.exists(),
+                            Some(name) => !archive::list_entries(path)
+                                .is_ok_and(|entries| entries.iter().any(|e| &e.name == name)),
+                        }
+                    })
+            })
+            .map(|file| file.id)
+            .collect())
+    }
+}
+
+/// Deletes the media with the provided IDs along with their tags, metadata,
+/// verifications and collection memberships, but not their thumbnails,
+/// returning how many were deleted.
+///
+/// It should be called inside a transaction.
+pub(crate) fn delete_media_rows(
+    conn: &mut SqliteConnection,
+    ids: &[i64],
+) -> diesel::QueryResult<usize> {
+    let mut deleted = 0;
+    for chunk in ids.chunks(MAX_BOUND_IDS) {
+        {
+            use database::schema::media_tags::dsl::{media_id, media_tags};
+            diesel::delete(media_tags.filter(media_id.eq_any(chunk))).execute(conn)?;
+        }
+        {
+            use database::schema::media_metadata::dsl::{media_id, media_metadata};
+            diesel::delete(media_metadata.filter(media_id.eq_any(chunk))).execute(conn)?;
+        }
+        {
+            use database::schema::media_verifications::dsl::{media_id, media_verifications};
+            diesel::delete(media_verifications.filter(media_id.eq_any(chunk))).execute(conn)?;
+        }
+        collections::remove_media_rows(conn, chunk)?;
+
+        use database::schema::media::dsl::id;
+        deleted += diesel::delete(media_table.filter(id.eq_any(chunk))).execute(conn)?;
+    }
+
+    Ok(deleted)
+}
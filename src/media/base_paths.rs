@@ -1,15 +1,32 @@
-use std::path;
+use std::{
+    collections::HashMap,
+    env, fs, path,
+    path::{Path, PathBuf},
+};
 
 use crate::{
     data::{self, base_path::BasePath},
     database::{self, connection::DatabaseConnection, schema::base_paths},
 };
-use diesel::{ExpressionMethods, Insertable, QueryDsl, RunQueryDsl};
+use diesel::{
+    sql_types::{Bool, Text},
+    Connection, ExpressionMethods, Insertable, IntoSql, QueryDsl, RunQueryDsl, SqliteConnection,
+    TextExpressionMethods,
+};
+use serde::Serialize;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
 
 const MAX_DESCRIPTION_LENGTH: usize = 300;
 
+/// The name of the file written at the root of portable base paths, that
+/// contains their UUID.
+pub const MARKER_FILE_NAME: &str = ".tag-media-id";
+
+/// How deep inside the mount roots markers are searched for.
+const MAX_DISCOVERY_DEPTH: usize = 4;
+
 /// BasePaths contains code and data that performs operations on base paths
 /// on the database.
 pub struct BasePaths {
@@ -44,6 +61,9 @@ pub enum Error {
     /// The path is invalid, e.g. is empty.
     #[error("invalid path")]
     InvalidPath,
+    /// The provided path could not be resolved.
+    #[error("cannot resolve path: {0}")]
+    IOError(#[from] std::io::Error),
     /// The provided path does not exist.
     #[error("not exists")]
     NotExists,
@@ -53,16 +73,47 @@ pub enum Error {
     /// The provided path is not an absolute path.
     #[error("not an absolute path")]
     NotAbsolute,
-    /// The provided path is already registered on the database.
-    #[error("already exists")]
-    AlreadyExists,
-    /// The provided path is a sub path of an existing path.
-    #[error("is sub path")]
-    IsSubPath,
+    /// The provided path is already registered on the database, possibly
+    /// through a symbolic link or a bind mount, as the contained base path.
+    #[error("already exists as {}", .0.base_path)]
+    AlreadyExists(BasePath),
+    /// The provided path is a sub path of the contained base path.
+    #[error("is sub path of {}", .0.base_path)]
+    IsSubPath(BasePath),
+    /// The provided path contains the contained base path.
+    #[error("is parent path of {}", .0.base_path)]
+    IsParentPath(BasePath),
+    /// The base path is not contained in another base path.
+    #[error("not a sub path")]
+    NotSubPath,
     /// The base path cannot be deleted because it is still referenced
     /// somewhere.
     #[error("cannot be deleted because in use")]
     InUse,
+    /// Some of the sampled media files do not exist under the new path, which
+    /// probably is not the same directory.
+    #[error("{missing} of {checked} sampled files not found under the new path")]
+    SpotCheckFailed { checked: usize, missing: usize },
+}
+
+/// Whether the directory of a base path is currently available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Status {
+    /// The directory exists and, for portable base paths, contains the
+    /// expected marker.
+    Online,
+    /// The directory does not exist, e.g. the drive is not mounted, or it is
+    /// another drive.
+    Offline,
+}
+
+/// A portable base path found at a new location by
+/// [`BasePaths::discover`].
+#[derive(Debug, Serialize)]
+pub struct Discovered {
+    pub base_path_id: i32,
+    pub from: String,
+    pub to: String,
 }
 
 #[derive(Insertable)]
@@ -70,6 +121,7 @@ pub enum Error {
 pub struct NewBasePath<'a> {
     base_path: &'a str,
     description: &'a str,
+    marker: Option<&'a str>,
 }
 
 impl BasePaths {
@@ -78,53 +130,266 @@ impl BasePaths {
         base_path: impl AsRef<str>,
         description: impl AsRef<str>,
     ) -> Result<BasePath, Error> {
-        let bp = base_path.as_ref().trim().trim_end_matches('/');
-        if bp.len() == 0 {
-            return Err(Error::InvalidPath);
-        }
+        self.insert(base_path.as_ref(), description.as_ref(), false)
+    }
 
-        let desc = description.as_ref().trim();
+    /// Creates a portable base path, e.g. on a removable drive: a marker file
+    /// containing a UUID is written at its root, or reused if already
+    /// present, so that [`BasePaths::discover`] can find it when it is
+    /// mounted at a different path.
+    ///
+    /// It returns the same errors as [`BasePaths::create`], or an error in
+    /// case the marker file cannot be written.
+    pub fn create_portable(
+        &self,
+        base_path: impl AsRef<str>,
+        description: impl AsRef<str>,
+    ) -> Result<BasePath, Error> {
+        self.insert(base_path.as_ref(), description.as_ref(), true)
+    }
+
+    fn insert(
+        &self,
+        base_path: &str,
+        description: &str,
+        portable: bool,
+    ) -> Result<BasePath, Error> {
+        let bp = validate_path(base_path)?;
+        let bp = bp.as_str();
+
+        let desc = description.trim();
         if desc.graphemes(true).count() > MAX_DESCRIPTION_LENGTH {
             return Err(Error::DescriptionTooLong);
         }
 
-        let p = path::Path::new(bp);
-        if !p.exists() {
-            return Err(Error::NotExists);
+        // A path inside existing base paths becomes a sub path of the
+        // deepest one, i.e. the one with the shortest relative sub path.
+        let mut parent: Option<(i32, String)> = None;
+        for (basepath, overlap) in self.overlaps(bp, None)? {
+            match overlap {
+                Overlap::Same => return Err(Error::AlreadyExists(basepath)),
+                Overlap::Parent => return Err(Error::IsParentPath(basepath)),
+                Overlap::Child(sub_path) => {
+                    if parent
+                        .as_ref()
+                        .is_none_or(|(_, p)| sub_path.len() < p.len())
+                    {
+                        parent = Some((basepath.id, sub_path));
+                    }
+                }
+            }
         }
 
-        if !p.is_dir() {
-            return Err(Error::NotADirectory);
+        let marker_path = Path::new(bp).join(MARKER_FILE_NAME);
+        let (marker, is_new_marker) = match (portable, read_marker(bp)) {
+            (false, _) => (None, false),
+            (true, Some(existing)) => (Some(existing), false),
+            (true, None) => {
+                let marker = Uuid::new_v4().to_string();
+                fs::write(&marker_path, format!("{}\n", marker))?;
+                (Some(marker), true)
+            }
+        };
+
+        let conn = &mut self.connection.establish_connection()?;
+        let result = conn.transaction(|conn| {
+            use database::schema::base_paths::dsl::base_paths;
+            let created: BasePath = diesel::insert_into(base_paths)
+                .values(NewBasePath {
+                    base_path: bp,
+                    description: desc,
+                    marker: marker.as_deref(),
+                })
+                .get_result(conn)?;
+
+            // Media files of the parent base path that are inside the new
+            // sub path now belong to the latter.
+            if let Some((parent_id, sub_path)) = parent {
+                reparent_media(conn, parent_id, created.id, &format!("{}/", sub_path), "")?;
+            }
+
+            Ok(created)
+        });
+
+        if result.is_err() && is_new_marker {
+            let _ = fs::remove_file(marker_path);
         }
-        if !p.is_absolute() {
-            return Err(Error::NotAbsolute);
+
+        result.map_err(Error::DatabaseError)
+    }
+
+    /// Returns whether the base path with the provided ID is online, i.e.
+    /// its directory exists and, for portable base paths, contains the
+    /// expected marker.
+    ///
+    /// It returns an error in case the ID is not valid, it was not found, or
+    /// if there was an error on the database.
+    pub fn status(&self, id: i32) -> Result<Status, Error> {
+        Ok(status(&self.get(id)?))
+    }
+
+    /// Same as [`BasePaths::list`], along with the status of each base
+    /// path.
+    pub fn list_with_status(
+        &self,
+        ids: Option<impl IntoIterator<Item = i32>>,
+    ) -> Result<Vec<(BasePath, Status)>, Error> {
+        Ok(self
+            .list(ids)?
+            .into_iter()
+            .map(|bp| {
+                let bp_status = status(&bp);
+                (bp, bp_status)
+            })
+            .collect())
+    }
+
+    /// Searches the provided mount roots for the markers of portable base
+    /// paths that are offline, and relocates each one that is found to its
+    /// new location. Take a look at [`default_mount_roots`] for the usual
+    /// ones.
+    ///
+    /// Markers are searched up to four directories deep, without following
+    /// symbolic links. Directories that cannot be read are skipped.
+    ///
+    /// It returns the base paths that have been relocated, or an error in
+    /// case there was an error on the database or a new location overlaps
+    /// with another base path.
+    pub fn discover(
+        &self,
+        mount_roots: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> Result<Vec<Discovered>, Error> {
+        let offline: Vec<BasePath> = self
+            .list(None::<Vec<_>>)?
+            .into_iter()
+            .filter(|bp| bp.marker.is_some() && status(bp) == Status::Offline)
+            .collect();
+        if offline.is_empty() {
+            return Ok(vec![]);
         }
 
-        let list = self.list(None::<Vec<_>>)?;
-        for basepath in list {
-            if basepath.base_path == bp {
-                return Err(Error::AlreadyExists);
-            }
+        let mut found: HashMap<String, PathBuf> = HashMap::new();
+        for root in mount_roots {
+            let mut directories = vec![(root.as_ref().to_path_buf(), 0)];
+            while let Some((directory, depth)) = directories.pop() {
+                if let Some(marker) = read_marker(&directory) {
+                    found.entry(marker).or_insert_with(|| directory.clone());
+                }
 
-            if bp.starts_with(&basepath.base_path) {
-                // TODO: on future this will change all existing paths to this new
-                // sub path: e.g. if `/this/that/` exists and you are adding
-                // `/this/that/another`, then all media that starts with
-                // `another` and belongs to `this/that/` will be changed.
-                // TODO: this means that this will become a transaction.
-                return Err(Error::IsSubPath);
+                if depth == MAX_DISCOVERY_DEPTH {
+                    continue;
+                }
+
+                let Ok(entries) = fs::read_dir(&directory) else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+                    if is_dir && !entry.file_name().to_string_lossy().starts_with('.') {
+                        directories.push((entry.path(), depth + 1));
+                    }
+                }
             }
         }
 
-        let conn = &mut self.connection.establish_connection()?;
-        use database::schema::base_paths::dsl::base_paths;
-        diesel::insert_into(base_paths)
-            .values(NewBasePath {
-                base_path: bp,
-                description: desc,
+        let mut discovered = vec![];
+        for bp in offline {
+            let Some(directory) = bp.marker.as_ref().and_then(|m| found.get(m)) else {
+                continue;
+            };
+            let Some(to) = directory.to_str() else {
+                continue;
+            };
+
+            let relocated = self.relocate(bp.id, to, None)?;
+            discovered.push(Discovered {
+                base_path_id: bp.id,
+                from: bp.base_path,
+                to: relocated.base_path,
+            });
+        }
+
+        Ok(discovered)
+    }
+
+    /// Merges a base path back into the base path that contains it, i.e.
+    /// the reverse of creating a sub path: its media files are moved to the
+    /// parent base path, prefixing their relative paths, and the base path
+    /// is deleted.
+    ///
+    /// It returns the parent base path, or an error in case the ID is not
+    /// valid, it was not found, it is not contained in another base path or
+    /// if there was an error on the database.
+    pub fn merge_into_parent(&self, id: i32) -> Result<BasePath, Error> {
+        let sub = self.get(id)?;
+        let (parent, sub_path) = self
+            .overlaps(&sub.base_path, Some(id))?
+            .into_iter()
+            .filter_map(|(basepath, overlap)| match overlap {
+                Overlap::Child(sub_path) => Some((basepath, sub_path)),
+                _ => None,
             })
-            .get_result(conn)
-            .map_err(|err| Error::DatabaseError(err))
+            .min_by_key(|(_, sub_path)| sub_path.len())
+            .ok_or(Error::NotSubPath)?;
+
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| {
+            reparent_media(conn, id, parent.id, "", &format!("{}/", sub_path))?;
+
+            use database::schema::base_paths::dsl::{base_paths, id as bp_id};
+            diesel::delete(base_paths.filter(bp_id.eq(id))).execute(conn)?;
+
+            Ok(())
+        })
+        .map_err(Error::DatabaseError)?;
+
+        Ok(parent)
+    }
+
+    /// Returns the base paths that overlap with the provided path, except the
+    /// one with ID `except_id`, if any.
+    ///
+    /// Paths are compared component by component after resolving symbolic
+    /// links, so that `/photos2` does not overlap with `/photos`. On Unix
+    /// directories are also compared by device and inode, to detect aliases
+    /// such as bind mounts. Base paths that are not mounted are compared by
+    /// their stored path only.
+    fn overlaps(
+        &self,
+        base_path: &str,
+        except_id: Option<i32>,
+    ) -> Result<Vec<(BasePath, Overlap)>, Error> {
+        let p = path::Path::new(base_path);
+        let mut overlaps = vec![];
+        for basepath in self.list(None::<Vec<_>>)? {
+            if Some(basepath.id) == except_id {
+                continue;
+            }
+
+            let existing = fs::canonicalize(&basepath.base_path)
+                .unwrap_or_else(|_| path::PathBuf::from(&basepath.base_path));
+
+            let overlap = if same_directory(p, &existing) {
+                Some(Overlap::Same)
+            } else if let Some(ancestor) =
+                p.ancestors().skip(1).find(|a| same_directory(a, &existing))
+            {
+                p.strip_prefix(ancestor)
+                    .ok()
+                    .and_then(|sub_path| sub_path.to_str())
+                    .map(|sub_path| Overlap::Child(sub_path.to_string()))
+            } else if existing.ancestors().skip(1).any(|a| same_directory(a, p)) {
+                Some(Overlap::Parent)
+            } else {
+                None
+            };
+
+            if let Some(overlap) = overlap {
+                overlaps.push((basepath, overlap));
+            }
+        }
+
+        Ok(overlaps)
     }
 
     /// Gets a single base path by using its ID.
@@ -175,6 +440,71 @@ impl BasePaths {
         }
     }
 
+    /// Changes the path of a base path, e.g. because the drive or the folder
+    /// containing it has been moved. All its media files keep their IDs, tags
+    /// and relative paths.
+    ///
+    /// If `spot_check` is `Some(n)`, up to `n` randomly chosen media files
+    /// that are not marked as missing must exist under the new path,
+    /// otherwise the base path is not changed and
+    /// [`Error::SpotCheckFailed`] is returned.
+    ///
+    /// It returns an error in case the ID is not valid, it was not found, the
+    /// new path is not valid or overlaps with another base path, or if there
+    /// was an error on the database.
+    pub fn relocate(
+        &self,
+        id: i32,
+        new_path: impl AsRef<str>,
+        spot_check: Option<usize>,
+    ) -> Result<BasePath, Error> {
+        let current = self.get(id)?;
+        let bp = validate_path(new_path.as_ref())?;
+        let bp = bp.as_str();
+        if bp == current.base_path {
+            return Ok(current);
+        }
+
+        if let Some((basepath, overlap)) = self.overlaps(bp, Some(id))?.into_iter().next() {
+            return Err(match overlap {
+                Overlap::Same => Error::AlreadyExists(basepath),
+                Overlap::Child(_) => Error::IsSubPath(basepath),
+                Overlap::Parent => Error::IsParentPath(basepath),
+            });
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        if let Some(sample_size) = spot_check {
+            use database::schema::media::dsl::{base_path_id, media, missing, relative_path};
+
+            let sample: Vec<String> = media
+                .select(relative_path)
+                .filter(base_path_id.eq(id))
+                .filter(missing.eq(false))
+                .order(diesel::dsl::sql::<diesel::sql_types::Integer>("RANDOM()"))
+                .limit(sample_size as i64)
+                .load(conn)
+                .map_err(Error::DatabaseError)?;
+
+            let missing_files = sample
+                .iter()
+                .filter(|rp| !path::Path::new(bp).join(rp).exists())
+                .count();
+            if missing_files > 0 {
+                return Err(Error::SpotCheckFailed {
+                    checked: sample.len(),
+                    missing: missing_files,
+                });
+            }
+        }
+
+        use database::schema::base_paths::dsl::{base_path, base_paths, id as bp_id};
+        diesel::update(base_paths.filter(bp_id.eq(id)))
+            .set(base_path.eq(bp))
+            .get_result(conn)
+            .map_err(Error::DatabaseError)
+    }
+
     /// List all base paths that are currently being saved on the database.
     ///
     /// Optionally, you can list only some specific IDs with `ids`.
@@ -238,3 +568,124 @@ impl BasePaths {
         }
     }
 }
+
+/// Moves the media files of base path `from_id` whose relative path starts
+/// with `strip_prefix` to base path `to_id`, replacing that prefix with
+/// `add_prefix`.
+fn reparent_media(
+    conn: &mut SqliteConnection,
+    from_id: i32,
+    to_id: i32,
+    strip_prefix: &str,
+    add_prefix: &str,
+) -> diesel::QueryResult<usize> {
+    use database::schema::media::dsl::{base_path_id, media, relative_path};
+
+    // `LIKE` ignores the case of ASCII letters, which would also move the
+    // media of sibling directories differing only by case.
+    let prefix_length = strip_prefix.chars().count();
+    diesel::update(
+        media.filter(base_path_id.eq(from_id)).filter(
+            diesel::dsl::sql::<Bool>(&format!("substr(relative_path, 1, {prefix_length}) = "))
+                .bind::<Text, _>(strip_prefix),
+        ),
+    )
+    .set((
+        base_path_id.eq(to_id),
+        relative_path.eq(add_prefix
+            .into_sql::<Text>()
+            .concat(diesel::dsl::sql::<Text>(&format!(
+                "substr(relative_path, {})",
+                prefix_length + 1
+            )))),
+    ))
+    .execute(conn)
+}
+
+/// Returns whether the provided base path is online. Take a look at
+/// [`BasePaths::status`].
+pub fn status(base_path: &BasePath) -> Status {
+    let is_online = match &base_path.marker {
+        None => Path::new(&base_path.base_path).is_dir(),
+        Some(marker) => read_marker(&base_path.base_path).as_ref() == Some(marker),
+    };
+
+    if is_online {
+        Status::Online
+    } else {
+        Status::Offline
+    }
+}
+
+/// Returns the directories where removable drives are usually mounted:
+/// `/media`, `/mnt`, `/run/media/$USER` and `/Volumes`.
+pub fn default_mount_roots() -> Vec<PathBuf> {
+    let mut roots = vec![PathBuf::from("/media"), PathBuf::from("/mnt")];
+    if let Ok(user) = env::var("USER") {
+        roots.push(Path::new("/run/media").join(user));
+    }
+    roots.push(PathBuf::from("/Volumes"));
+
+    roots
+}
+
+/// Reads the UUID in the marker file inside the provided directory, if any.
+fn read_marker(directory: impl AsRef<Path>) -> Option<String> {
+    let content = fs::read_to_string(directory.as_ref().join(MARKER_FILE_NAME)).ok()?;
+    Uuid::parse_str(content.trim())
+        .ok()
+        .map(|uuid| uuid.to_string())
+}
+
+/// How a path overlaps with an existing base path.
+enum Overlap {
+    /// Both are the same directory.
+    Same,
+    /// The path is inside the base path, at the contained relative path.
+    Child(String),
+    /// The path contains the base path.
+    Parent,
+}
+
+/// Returns whether both paths point to the same directory.
+#[cfg(unix)]
+fn same_directory(first: &path::Path, second: &path::Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(first), fs::metadata(second)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => first == second,
+    }
+}
+
+/// Returns whether both paths point to the same directory.
+#[cfg(not(unix))]
+fn same_directory(first: &path::Path, second: &path::Path) -> bool {
+    first == second
+}
+
+/// Trims the provided path and checks that it is an absolute path to an
+/// existing directory, returning it with symbolic links resolved.
+fn validate_path(base_path: &str) -> Result<String, Error> {
+    let bp = base_path.trim().trim_end_matches('/');
+    if bp.is_empty() {
+        return Err(Error::InvalidPath);
+    }
+
+    let p = path::Path::new(bp);
+    if !p.exists() {
+        return Err(Error::NotExists);
+    }
+
+    if !p.is_dir() {
+        return Err(Error::NotADirectory);
+    }
+    if !p.is_absolute() {
+        return Err(Error::NotAbsolute);
+    }
+
+    match fs::canonicalize(p)?.to_str() {
+        Some(canonical) => Ok(canonical.to_string()),
+        None => Err(Error::InvalidPath),
+    }
+}
@@ -0,0 +1,782 @@
+use std::{collections::HashSet, time::SystemTime};
+
+use diesel::{
+    dsl::count_star, result::DatabaseErrorKind, Connection, ExpressionMethods, Insertable,
+    OptionalExtension, QueryDsl, RunQueryDsl, SqliteConnection,
+};
+use thiserror::Error;
+
+use crate::{
+    data::{collection::Collection, media_file::MediaFile},
+    database::{
+        self,
+        connection::DatabaseConnection,
+        schema::{
+            collection_media,
+            collections::{self, dsl::collections as collections_table},
+        },
+    },
+    media::{
+        media::{self, media, MAX_BOUND_IDS},
+        naming,
+        scanner::unix_timestamp,
+    },
+};
+
+/// Collections group media in a chosen order, e.g. the photos of a
+/// slideshow, which tags cannot express.
+///
+/// Collections can contain other collections. A media can belong to many
+/// collections, but at most once to each. It cannot be deleted while it
+/// belongs to one, but trashing or forgetting it removes it from them.
+pub struct Collections {
+    connection: DatabaseConnection,
+}
+
+pub fn collections(connection: DatabaseConnection) -> Collections {
+    Collections { connection }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The provided ID is invalid, e.g. it is <= 0.
+    #[error("invalid id")]
+    InvalidID,
+    /// The collection was not found.
+    #[error("not found")]
+    NotFound,
+    /// The provided name is invalid, e.g. it is empty.
+    #[error("invalid name")]
+    InvalidName,
+    /// The provided name is longer than 50 characters.
+    #[error("name too long")]
+    NameTooLong,
+    /// The provided description is longer than 300 characters.
+    #[error("description too long")]
+    DescriptionTooLong,
+    /// Another collection with the same parent has the same name, ignoring
+    /// the case.
+    #[error("already exists")]
+    AlreadyExists,
+    /// The parent collection was not found.
+    #[error("parent not found")]
+    ParentNotFound,
+    /// The collection cannot be moved into itself or one of its
+    /// sub-collections.
+    #[error("invalid parent")]
+    InvalidParent,
+    /// The collection cannot be deleted because it contains other
+    /// collections.
+    #[error("collection is not empty")]
+    NotEmpty,
+    /// The media could not be found.
+    #[error("media error: {0}")]
+    MediaError(#[from] media::Error),
+    /// The media is already in the collection.
+    #[error("media is already in the collection")]
+    AlreadyInCollection,
+    /// The media is not in the collection.
+    #[error("media is not in the collection")]
+    NotInCollection,
+    /// The position is after the end of the collection.
+    #[error("invalid position")]
+    InvalidPosition,
+    /// The new order does not contain exactly the media of the collection.
+    #[error("invalid order")]
+    InvalidOrder,
+}
+
+impl From<naming::Invalid> for Error {
+    fn from(value: naming::Invalid) -> Self {
+        match value {
+            naming::Invalid::Name => Error::InvalidName,
+            naming::Invalid::NameTooLong => Error::NameTooLong,
+            naming::Invalid::DescriptionTooLong => Error::DescriptionTooLong,
+        }
+    }
+}
+
+/// Represents a new collection to create.
+pub struct CreateCollection {
+    /// The name, unique among the collections with the same parent,
+    /// ignoring the case.
+    pub name: String,
+    /// What the collection contains, e.g. the occasion of the photos.
+    pub description: String,
+    /// The collection that will contain it, or `None` for a top-level
+    /// collection.
+    pub parent_id: Option<i32>,
+}
+
+impl CreateCollection {
+    /// Trims the name and the description, and checks them.
+    fn validate(self) -> Result<Self, Error> {
+        Ok(Self {
+            name: naming::name(&self.name)?,
+            description: naming::description(&self.description)?,
+            ..self
+        })
+    }
+}
+
+/// Used to define what to update in a collection.
+#[derive(Default)]
+pub struct UpdateCollection<'a> {
+    /// The new name. If `None` the existing name will be used.
+    pub name: Option<&'a str>,
+    /// The new description. If `None` the existing description will be used.
+    pub description: Option<&'a str>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = collections)]
+struct NewCollection {
+    name: String,
+    description: String,
+    parent_id: Option<i32>,
+    created_at: i64,
+}
+
+impl Collections {
+    /// Creates a new collection.
+    ///
+    /// It returns an error in case the name or description are invalid, the
+    /// parent was not found, it already contains a collection with the same
+    /// name or if there was an error on the database.
+    pub fn create(&self, data: CreateCollection) -> Result<Collection, Error> {
+        let data = data.validate()?;
+        if let Some(parent_id) = data.parent_id {
+            self.get_parent(parent_id)?;
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::insert_into(collections_table)
+            .values(NewCollection {
+                name: data.name,
+                description: data.description,
+                parent_id: data.parent_id,
+                created_at: unix_timestamp(SystemTime::now()),
+            })
+            .get_result(conn)
+            .map_err(map_unique_violation)
+    }
+
+    /// Gets a collection by ID.
+    ///
+    /// It returns an error if the ID is not valid, if the collection was not
+    /// found or if there was an error on the database.
+    pub fn get(&self, id: i32) -> Result<Collection, Error> {
+        if id <= 0 {
+            return Err(Error::InvalidID);
+        }
+
+        use database::schema::collections::dsl::id as c_id;
+        let conn = &mut self.connection.establish_connection()?;
+        collections_table
+            .filter(c_id.eq(id))
+            .first(conn)
+            .map_err(|err| match err {
+                diesel::result::Error::NotFound => Error::NotFound,
+                _ => Error::DatabaseError(err),
+            })
+    }
+
+    /// Lists the collections contained in the provided one, or the top-level
+    /// collections if `None`, ordered by name.
+    ///
+    /// It returns an error in case the parent was not found or if there was
+    /// an error on the database.
+    pub fn list(&self, parent: Option<i32>) -> Result<Vec<Collection>, Error> {
+        if let Some(parent) = parent {
+            self.get(parent)?;
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        children(conn, parent)
+    }
+
+    /// Lists the collections containing the media with the provided ID,
+    /// ordered by name.
+    ///
+    /// It returns an error in case there was an error on the database.
+    pub fn list_for_media(&self, media_id: i64) -> Result<Vec<Collection>, Error> {
+        use database::schema::{
+            collection_media::dsl::{collection_media, media_id as cm_media_id},
+            collections::dsl::name,
+        };
+        let conn = &mut self.connection.establish_connection()?;
+        collections_table
+            .inner_join(collection_media)
+            .filter(cm_media_id.eq(media_id))
+            .select(collections::all_columns)
+            .order(name.asc())
+            .load(conn)
+            .map_err(Error::DatabaseError)
+    }
+
+    /// Updates the name or description of a collection.
+    ///
+    /// It returns an error in case the collection was not found, the new
+    /// data is invalid, the parent already contains a collection with the new
+    /// name or if there was an error on the database.
+    pub fn update(&self, id: i32, new_data: UpdateCollection) -> Result<(), Error> {
+        let existing = self.get(id)?;
+        let new_name = naming::name(new_data.name.unwrap_or(&existing.name))?;
+        let new_description =
+            naming::description(new_data.description.unwrap_or(&existing.description))?;
+
+        let conn = &mut self.connection.establish_connection()?;
+        use database::schema::collections::dsl::{description, id as c_id, name};
+        diesel::update(collections_table.filter(c_id.eq(id)))
+            .set((name.eq(new_name), description.eq(new_description)))
+            .execute(conn)
+            .map_err(map_unique_violation)?;
+        Ok(())
+    }
+
+    /// Moves a collection into another one, or to the top level if `None`.
+    ///
+    /// It returns an error in case either collection was not found, the
+    /// collection would end up inside itself, the new parent already contains
+    /// a collection with the same name or if there was an error on the
+    /// database.
+    pub fn set_parent(&self, id: i32, new_parent: Option<i32>) -> Result<(), Error> {
+        self.get(id)?;
+
+        // Walk up from the new parent: the collection must not be found.
+        let mut ancestor = new_parent;
+        while let Some(ancestor_id) = ancestor {
+            if ancestor_id == id {
+                return Err(Error::InvalidParent);
+            }
+            ancestor = self.get_parent(ancestor_id)?.parent_id;
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        use database::schema::collections::dsl::{id as c_id, parent_id};
+        diesel::update(collections_table.filter(c_id.eq(id)))
+            .set(parent_id.eq(new_parent))
+            .execute(conn)
+            .map_err(map_unique_violation)?;
+        Ok(())
+    }
+
+    /// Sets the media shown for the collection, which must be one of its
+    /// media, or removes it if `None`.
+    ///
+    /// It returns an error in case the collection was not found, the media is
+    /// not in the collection or if there was an error on the database.
+    pub fn set_cover(&self, id: i32, media_id: Option<i64>) -> Result<(), Error> {
+        self.get(id)?;
+
+        let conn = &mut self.connection.establish_connection()?;
+        if let Some(media_id) = media_id {
+            position_of(conn, id, media_id)?.ok_or(Error::NotInCollection)?;
+        }
+
+        use database::schema::collections::dsl::{cover_media_id, id as c_id};
+        diesel::update(collections_table.filter(c_id.eq(id)))
+            .set(cover_media_id.eq(media_id))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Deletes a collection. Its media are not affected, but they no longer
+    /// belong to it.
+    ///
+    /// It returns an error in case the collection was not found, it contains
+    /// other collections or if there was an error on the database.
+    pub fn delete(&self, id: i32) -> Result<(), Error> {
+        self.get(id)?;
+
+        let conn = &mut self.connection.establish_connection()?;
+        if !children(conn, Some(id))?.is_empty() {
+            return Err(Error::NotEmpty);
+        }
+
+        conn.transaction(|conn| {
+            use database::schema::{
+                collection_media::dsl::{collection_id, collection_media},
+                collections::dsl::id as c_id,
+            };
+            diesel::delete(collection_media.filter(collection_id.eq(id))).execute(conn)?;
+            diesel::delete(collections_table.filter(c_id.eq(id))).execute(conn)
+        })
+        .map(|_| ())
+        .map_err(Error::DatabaseError)
+    }
+
+    /// Adds media to a collection, in the provided order, starting at the
+    /// provided position or at the end if `None`. The media after that
+    /// position are shifted.
+    ///
+    /// It returns an error in case the collection or a media was not found, a
+    /// media is already in the collection, the position is after the end or
+    /// if there was an error on the database. In case of error, no media is
+    /// added.
+    pub fn add_media(
+        &self,
+        id: i32,
+        media_ids: impl IntoIterator<Item = i64>,
+        position: Option<usize>,
+    ) -> Result<(), Error> {
+        self.get(id)?;
+
+        let mut seen = HashSet::new();
+        let mut new_ids: Vec<i64> = media_ids.into_iter().collect();
+        new_ids.retain(|media_id| seen.insert(*media_id));
+
+        let media = media(self.connection.clone());
+        for media_id in &new_ids {
+            media.get(*media_id)?;
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| {
+            for media_id in &new_ids {
+                if position_of(conn, id, *media_id)?.is_some() {
+                    return Err(Error::AlreadyInCollection);
+                }
+            }
+
+            let length = length(conn, id)?;
+            let start = match position {
+                None => length,
+                Some(position) if position as i64 > length => return Err(Error::InvalidPosition),
+                Some(position) => position as i64,
+            } as i32;
+
+            use database::schema::collection_media::dsl::{
+                collection_id, collection_media, media_id as cm_media_id, position as cm_position,
+            };
+            diesel::update(
+                collection_media
+                    .filter(collection_id.eq(id))
+                    .filter(cm_position.ge(start)),
+            )
+            .set(cm_position.eq(cm_position + new_ids.len() as i32))
+            .execute(conn)?;
+
+            let rows: Vec<_> = new_ids
+                .iter()
+                .enumerate()
+                .map(|(i, media_id)| {
+                    (
+                        collection_id.eq(id),
+                        cm_media_id.eq(*media_id),
+                        cm_position.eq(start + i as i32),
+                    )
+                })
+                .collect();
+            diesel::insert_into(collection_media)
+                .values(rows)
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Removes a media from a collection, and as its cover if it was. The
+    /// media after it are shifted.
+    ///
+    /// It returns an error in case the collection was not found, the media is
+    /// not in it or if there was an error on the database.
+    pub fn remove_media(&self, id: i32, media_id: i64) -> Result<(), Error> {
+        self.get(id)?;
+
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| {
+            let position = position_of(conn, id, media_id)?.ok_or(Error::NotInCollection)?;
+
+            use database::schema::collection_media::dsl::{
+                collection_id, collection_media, media_id as cm_media_id, position as cm_position,
+            };
+            diesel::delete(
+                collection_media
+                    .filter(collection_id.eq(id))
+                    .filter(cm_media_id.eq(media_id)),
+            )
+            .execute(conn)?;
+            diesel::update(
+                collection_media
+                    .filter(collection_id.eq(id))
+                    .filter(cm_position.gt(position)),
+            )
+            .set(cm_position.eq(cm_position - 1))
+            .execute(conn)?;
+
+            use database::schema::collections::dsl::{cover_media_id, id as c_id};
+            diesel::update(
+                collections_table
+                    .filter(c_id.eq(id))
+                    .filter(cover_media_id.eq(media_id)),
+            )
+            .set(cover_media_id.eq(None::<i64>))
+            .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Moves a media of a collection to the provided position, shifting the
+    /// media in between.
+    ///
+    /// It returns an error in case the collection was not found, the media is
+    /// not in it, the position is after the last media or if there was an
+    /// error on the database.
+    pub fn move_media(&self, id: i32, media_id: i64, position: usize) -> Result<(), Error> {
+        self.get(id)?;
+
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| {
+            let from = position_of(conn, id, media_id)?.ok_or(Error::NotInCollection)?;
+            if position as i64 >= length(conn, id)? {
+                return Err(Error::InvalidPosition);
+            }
+            let to = position as i32;
+
+            use database::schema::collection_media::dsl::{
+                collection_id, collection_media, media_id as cm_media_id, position as cm_position,
+            };
+            let items = collection_media.filter(collection_id.eq(id));
+            if to < from {
+                diesel::update(
+                    items
+                        .filter(cm_position.ge(to))
+                        .filter(cm_position.lt(from)),
+                )
+                .set(cm_position.eq(cm_position + 1))
+                .execute(conn)?;
+            } else if to > from {
+                diesel::update(
+                    items
+                        .filter(cm_position.gt(from))
+                        .filter(cm_position.le(to)),
+                )
+                .set(cm_position.eq(cm_position - 1))
+                .execute(conn)?;
+            }
+            diesel::update(items.filter(cm_media_id.eq(media_id)))
+                .set(cm_position.eq(to))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Sets the order of all the media of a collection at once, e.g. after
+    /// sorting them by date.
+    ///
+    /// It returns an error in case the collection was not found, the new
+    /// order does not contain each media of the collection exactly once or if
+    /// there was an error on the database.
+    pub fn reorder(&self, id: i32, media_ids: impl IntoIterator<Item = i64>) -> Result<(), Error> {
+        self.get(id)?;
+        let new_order: Vec<i64> = media_ids.into_iter().collect();
+
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| {
+            use database::schema::collection_media::dsl::{
+                collection_id, collection_media, media_id as cm_media_id, position as cm_position,
+            };
+            let current: HashSet<i64> = collection_media
+                .filter(collection_id.eq(id))
+                .select(cm_media_id)
+                .load::<i64>(conn)?
+                .into_iter()
+                .collect();
+            let new_set: HashSet<i64> = new_order.iter().copied().collect();
+            if new_order.len() != current.len() || new_set != current {
+                return Err(Error::InvalidOrder);
+            }
+
+            for (position, media_id) in new_order.iter().enumerate() {
+                diesel::update(
+                    collection_media
+                        .filter(collection_id.eq(id))
+                        .filter(cm_media_id.eq(media_id)),
+                )
+                .set(cm_position.eq(position as i32))
+                .execute(conn)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Lists the media of a collection, in order.
+    ///
+    /// It returns an error in case the collection was not found or if there
+    /// was an error on the database.
+    pub fn list_media(&self, id: i32) -> Result<Vec<MediaFile>, Error> {
+        self.get(id)?;
+
+        use database::schema::{
+            collection_media::dsl::{collection_id, collection_media, position},
+            media::{self, dsl::media as media_table},
+        };
+        let conn = &mut self.connection.establish_connection()?;
+        media_table
+            .inner_join(collection_media)
+            .filter(collection_id.eq(id))
+            .select(media::all_columns)
+            .order(position.asc())
+            .load(conn)
+            .map_err(Error::DatabaseError)
+    }
+
+    fn get_parent(&self, id: i32) -> Result<Collection, Error> {
+        self.get(id).map_err(|err| match err {
+            Error::NotFound | Error::InvalidID => Error::ParentNotFound,
+            _ => err,
+        })
+    }
+}
+
+fn children(conn: &mut SqliteConnection, parent: Option<i32>) -> Result<Vec<Collection>, Error> {
+    use database::schema::collections::dsl::{name, parent_id};
+    let query = match parent {
+        Some(parent) => collections_table.filter(parent_id.eq(parent)).into_boxed(),
+        None => collections_table.filter(parent_id.is_null()).into_boxed(),
+    };
+
+    query
+        .order(name.asc())
+        .load(conn)
+        .map_err(Error::DatabaseError)
+}
+
+/// Names are unique among the collections with the same parent, which the
+/// database enforces.
+fn map_unique_violation(err: diesel::result::Error) -> Error {
+    match err {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            Error::AlreadyExists
+        }
+        _ => Error::DatabaseError(err),
+    }
+}
+
+fn position_of(conn: &mut SqliteConnection, id: i32, media_id: i64) -> Result<Option<i32>, Error> {
+    use database::schema::collection_media::dsl::{
+        collection_id, collection_media, media_id as cm_media_id, position,
+    };
+    collection_media
+        .filter(collection_id.eq(id))
+        .filter(cm_media_id.eq(media_id))
+        .select(position)
+        .first(conn)
+        .optional()
+        .map_err(Error::DatabaseError)
+}
+
+fn length(conn: &mut SqliteConnection, id: i32) -> Result<i64, Error> {
+    use database::schema::collection_media::dsl::collection_id;
+    collection_media::table
+        .filter(collection_id.eq(id))
+        .select(count_star())
+        .get_result(conn)
+        .map_err(Error::DatabaseError)
+}
+
+/// Removes the media with the provided IDs from all the collections and as
+/// their covers, keeping the positions of the remaining media dense, e.g.
+/// before the media themselves are deleted.
+///
+/// It should be called inside a transaction.
+pub(crate) fn remove_media_rows(
+    conn: &mut SqliteConnection,
+    ids: &[i64],
+) -> diesel::QueryResult<()> {
+    use database::schema::collection_media::dsl::{
+        collection_id, collection_media, media_id, position,
+    };
+    use database::schema::collections::dsl::cover_media_id;
+    let mut affected: HashSet<i32> = HashSet::new();
+    for chunk in ids.chunks(MAX_BOUND_IDS) {
+        affected.extend(
+            collection_media
+                .filter(media_id.eq_any(chunk))
+                .select(collection_id)
+                .distinct()
+                .load::<i32>(conn)?,
+        );
+        diesel::delete(collection_media.filter(media_id.eq_any(chunk))).execute(conn)?;
+        diesel::update(collections_table.filter(cover_media_id.eq_any(chunk)))
+            .set(cover_media_id.eq(None::<i64>))
+            .execute(conn)?;
+    }
+
+    for id in affected {
+        let remaining: Vec<(i64, i32)> = collection_media
+            .filter(collection_id.eq(id))
+            .order(position.asc())
+            .select((media_id, position))
+            .load(conn)?;
+        for (new_position, (remaining_id, old_position)) in remaining.into_iter().enumerate() {
+            if old_position != new_position as i32 {
+                diesel::update(
+                    collection_media
+                        .filter(collection_id.eq(id))
+                        .filter(media_id.eq(remaining_id)),
+                )
+                .set(position.eq(new_position as i32))
+                .execute(conn)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+
+    use super::*;
+    use crate::database::connection::DatabaseLocation;
+
+    /// The media table as the migrations leave it, which the collections
+    /// reference.
+    const SCHEMA: &str = "\
+        CREATE TABLE media ( \
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, \
+            relative_path TEXT NOT NULL, base_path_id INTEGER NOT NULL, \
+            width SMALLINT, height SMALLINT, size DOUBLE NOT NULL, mark SMALLINT, \
+            description TEXT NOT NULL, media_type TEXT NOT NULL, content_hash TEXT, \
+            perceptual_hash BIGINT, modified BIGINT, missing BOOLEAN NOT NULL DEFAULT 0, \
+            archive_entry TEXT);";
+
+    /// Returns a collection containing the media `1` to `count`, in order,
+    /// in a new in-memory database. The database lives as long as the
+    /// returned connection.
+    fn collection(name: &str, count: i64) -> (Collections, i32, SqliteConnection) {
+        let location = format!("file:{name}?mode=memory&cache=shared");
+        let connection = DatabaseConnection::new(DatabaseLocation::URL(&location)).unwrap();
+        let mut conn = connection.establish_connection().unwrap();
+        conn.batch_execute(SCHEMA).unwrap();
+        conn.batch_execute(include_str!(
+            "../../migrations/2026-10-18-000015_create_collections/up.sql"
+        ))
+        .unwrap();
+        for i in 1..=count {
+            conn.batch_execute(&format!(
+                "INSERT INTO media \
+                 (id, relative_path, base_path_id, size, description, media_type) \
+                 VALUES ({i}, '{i}.jpg', 1, 1.0, '', 'image')"
+            ))
+            .unwrap();
+        }
+
+        let collections = collections(connection);
+        let id = collections
+            .create(CreateCollection {
+                name: name.to_string(),
+                description: String::new(),
+                parent_id: None,
+            })
+            .unwrap()
+            .id;
+        collections.add_media(id, 1..=count, None).unwrap();
+        (collections, id, conn)
+    }
+
+    /// The media of the collection, in order, checking that the positions
+    /// are dense.
+    fn order(conn: &mut SqliteConnection, id: i32) -> Vec<i64> {
+        use database::schema::collection_media::dsl::{
+            collection_id, collection_media, media_id, position,
+        };
+        let rows: Vec<(i64, i32)> = collection_media
+            .filter(collection_id.eq(id))
+            .order(position.asc())
+            .select((media_id, position))
+            .load(conn)
+            .unwrap();
+        for (expected, (_, actual)) in rows.iter().enumerate() {
+            assert_eq!(*actual, expected as i32);
+        }
+        rows.into_iter().map(|(media, _)| media).collect()
+    }
+
+    #[test]
+    fn add_media_shifts_the_following_ones() {
+        let (collections, id, mut conn) = collection("add_media", 5);
+        collections.remove_media(id, 4).unwrap();
+        collections.remove_media(id, 5).unwrap();
+
+        collections.add_media(id, [5, 4, 5], Some(1)).unwrap();
+        assert_eq!(order(&mut conn, id), vec![1, 5, 4, 2, 3]);
+        assert!(matches!(
+            collections.add_media(id, [2], None),
+            Err(Error::AlreadyInCollection)
+        ));
+        assert!(matches!(
+            collections.add_media(id, [], Some(6)),
+            Err(Error::InvalidPosition)
+        ));
+    }
+
+    #[test]
+    fn remove_media_shifts_the_following_ones() {
+        let (collections, id, mut conn) = collection("remove_media", 4);
+        collections.set_cover(id, Some(2)).unwrap();
+
+        collections.remove_media(id, 2).unwrap();
+        assert_eq!(order(&mut conn, id), vec![1, 3, 4]);
+        assert_eq!(collections.get(id).unwrap().cover_media_id, None);
+        assert!(matches!(
+            collections.remove_media(id, 2),
+            Err(Error::NotInCollection)
+        ));
+    }
+
+    #[test]
+    fn move_media_shifts_the_ones_in_between() {
+        let (collections, id, mut conn) = collection("move_media", 5);
+
+        collections.move_media(id, 2, 4).unwrap();
+        assert_eq!(order(&mut conn, id), vec![1, 3, 4, 5, 2]);
+        collections.move_media(id, 5, 0).unwrap();
+        assert_eq!(order(&mut conn, id), vec![5, 1, 3, 4, 2]);
+        collections.move_media(id, 3, 2).unwrap();
+        assert_eq!(order(&mut conn, id), vec![5, 1, 3, 4, 2]);
+        assert!(matches!(
+            collections.move_media(id, 1, 5),
+            Err(Error::InvalidPosition)
+        ));
+    }
+
+    #[test]
+    fn reorder_needs_each_media_once() {
+        let (collections, id, mut conn) = collection("reorder", 3);
+
+        collections.reorder(id, [3, 1, 2]).unwrap();
+        assert_eq!(order(&mut conn, id), vec![3, 1, 2]);
+        for invalid in [vec![3, 1], vec![3, 1, 1], vec![3, 1, 2, 2], vec![3, 1, 4]] {
+            assert!(matches!(
+                collections.reorder(id, invalid),
+                Err(Error::InvalidOrder)
+            ));
+        }
+        assert_eq!(order(&mut conn, id), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn remove_media_rows_compacts_the_collections() {
+        let count = MAX_BOUND_IDS as i64 + 10;
+        let (collections, id, mut conn) = collection("remove_media_rows", count);
+        collections.set_cover(id, Some(count)).unwrap();
+
+        let removed: Vec<i64> = (2..=count).step_by(2).collect();
+        conn.transaction(|conn| remove_media_rows(conn, &removed))
+            .unwrap();
+        let expected: Vec<i64> = (1..=count).step_by(2).collect();
+        assert_eq!(order(&mut conn, id), expected);
+        assert_eq!(collections.get(id).unwrap().cover_media_id, None);
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use diesel::{Connection, ExpressionMethods, QueryDsl, Queryable, RunQueryDsl};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    data::media_file::MediaFile,
+    database::{
+        self,
+        connection::DatabaseConnection,
+        schema::{
+            file_operations::dsl::file_operations as journal_table,
+            media::dsl::media as media_table,
+        },
+    },
+    media::{
+        audit::delete_media_rows,
+        base_paths::{self, base_paths, Status},
+        media::{self, media},
+        scanner::unix_timestamp,
+        thumbnails,
+    },
+};
+
+/// The name of the directory, at the root of each base path, where trashed
+/// files are moved. It is hidden so that scans skip it.
+pub const TRASH_DIRECTORY_NAME: &str = ".tag-media-trash";
+
+/// Files performs operations on the files of the media, keeping the
+/// database in sync: moving, renaming and trashing them.
+///
+/// Each operation is recorded in a journal before touching the disk and
+/// removed from it in the same transaction that updates the database, so
+/// that an operation interrupted halfway, e.g. by a crash, can be completed
+/// or rolled back by [`Files::recover`].
+pub struct Files {
+    connection: DatabaseConnection,
+}
+
+/// This returns a new instance of the `Files` struct.
+pub fn files(connection: DatabaseConnection) -> Files {
+    Files { connection }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The media could not be retrieved.
+    #[error("media error: {0}")]
+    MediaError(#[from] media::Error),
+    /// The base path could not be retrieved.
+    #[error("base path error: {0}")]
+    BasePathsError(#[from] base_paths::Error),
+    /// The thumbnails of a trashed media could not be removed.
+    #[error("thumbnail error: {0}")]
+    ThumbnailError(#[from] thumbnails::Error),
+    /// The file could not be moved.
+    #[error("io error: {0}")]
+    IOError(#[from] io::Error),
+    /// The relative path or the file name is invalid, e.g. it is empty,
+    /// contains `..` or a hidden component.
+    #[error("invalid relative path")]
+    InvalidRelativePath,
+    /// A file or a media already exists at the destination.
+    #[error("already exists")]
+    AlreadyExists,
+    /// The source or destination base path is not online.
+    #[error("base path is offline")]
+    Offline,
+    /// The media is stored inside an archive, so it has no file of its own
+    /// to move or trash.
+    #[error("not supported for archive entries")]
+    ArchiveEntry,
+}
+
+/// An operation recorded in the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Operation {
+    /// The file is moved or renamed.
+    Move,
+    /// The file is moved to the trash and its media deleted.
+    Trash,
+}
+
+impl Operation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Move => "move",
+            Operation::Trash => "trash",
+        }
+    }
+}
+
+/// What [`Files::recover`] did with an interrupted operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Outcome {
+    /// The file had been moved: the database has been updated.
+    Completed,
+    /// The file had not been moved, or only partially copied: it is back at
+    /// its original location.
+    RolledBack,
+    /// The file is neither at the source nor at the destination: the media
+    /// has been marked as missing.
+    Lost,
+}
+
+/// An interrupted operation recovered by [`Files::recover`].
+#[derive(Debug, Serialize)]
+pub struct Recovered {
+    pub media_id: i64,
+    pub operation: Operation,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Queryable)]
+struct JournalEntry {
+    id: i32,
+    media_id: i64,
+    operation: String,
+    source: String,
+    destination: String,
+    base_path_id: Option<i32>,
+    relative_path: Option<String>,
+    _started_at: i64,
+}
+
+impl Files {
+    /// Moves the file of the media with the provided ID to `relative_path`
+    /// inside the base path with ID `base_path_id`, creating the missing
+    /// directories. Tags, mark and description are kept.
+    ///
+    /// It returns the updated media, or an error in case the media or the
+    /// base path do not exist or are offline, the destination already
+    /// exists, the media is stored inside an archive, or the file cannot be
+    /// moved. On error both the file and the database are left unchanged.
+    pub fn move_to(
+        &self,
+        id: i64,
+        base_path_id: i32,
+        relative_path: impl AsRef<str>,
+    ) -> Result<MediaFile, Error> {
+        let media = media(self.connection.clone());
+        let file = media.get(id)?;
+        if file.archive_entry.is_some() {
+            return Err(Error::ArchiveEntry);
+        }
+
+        let rp = validate_relative_path(relative_path.as_ref())?;
+        if file.base_path_id == base_path_id && file.relative_path == rp {
+            return Ok(file);
+        }
+
+        let source = self.online_path(file.base_path_id, &file.relative_path)?;
+        let destination = self.online_path(base_path_id, &rp)?;
+        match media.get_by_relative_path(base_path_id, &rp) {
+            Ok(_) => return Err(Error::AlreadyExists),
+            Err(media::Error::NotFound) => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        let entry_id = self.start(
+            id,
+            Operation::Move,
+            &source,
+            &destination,
+            Some((base_path_id, &rp)),
+        )?;
+        if let Err(err) = move_file(&source, &destination) {
+            self.finish(entry_id)?;
+            return Err(err);
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        let result = conn.transaction(|conn| {
+            set_location(conn, id, base_path_id, &rp)?;
+            finish_entry(conn, entry_id)
+        });
+
+        if let Err(err) = result {
+            self.rollback(entry_id, &source, &destination);
+            return Err(err.into());
+        }
+
+        Ok(media.get(id)?)
+    }
+
+    /// Renames the file of the media with the provided ID, keeping it in
+    /// the same directory. Take a look at [`Files::move_to`] for the
+    /// errors.
+    pub fn rename(&self, id: i64, new_name: impl AsRef<str>) -> Result<MediaFile, Error> {
+        let name = new_name.as_ref().trim();
+        if name.is_empty() || name.contains('/') {
+            return Err(Error::InvalidRelativePath);
+        }
+
+        let file = media(self.connection.clone()).get(id)?;
+        let rp = match Path::new(&file.relative_path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                format!("{}/{}", parent.to_string_lossy(), name)
+            }
+            _ => name.to_string(),
+        };
+
+        self.move_to(id, file.base_path_id, rp)
+    }
+
+    /// Moves the file of the media with the provided ID to the trash
+    /// directory of its base path, and deletes the media along with its
+    /// tags, metadata, collection memberships and thumbnails.
+    ///
+    /// It returns the path of the file in the trash, or an error in case the
+    /// media does not exist, is stored inside an archive, its base path is
+    /// offline, or the file cannot be moved. On error both the file and the
+    /// database are left unchanged, except for the thumbnails that are
+    /// rendered again when needed.
+    pub fn trash(&self, id: i64) -> Result<PathBuf, Error> {
+        let file = media(self.connection.clone()).get(id)?;
+        if file.archive_entry.is_some() {
+            return Err(Error::ArchiveEntry);
+        }
+
+        let source = self.online_path(file.base_path_id, &file.relative_path)?;
+        let bp = base_paths(self.connection.clone()).get(file.base_path_id)?;
+
+        let now = unix_timestamp(SystemTime::now());
+        let destination = Path::new(&bp.base_path)
+            .join(TRASH_DIRECTORY_NAME)
+            .join(now.to_string())
+            .join(&file.relative_path);
+        if destination.symlink_metadata().is_ok() {
+            return Err(Error::AlreadyExists);
+        }
+
+        thumbnails::thumbnails(self.connection.clone()).remove(id)?;
+
+        let entry_id = self.start(id, Operation::Trash, &source, &destination, None)?;
+        if let Err(err) = move_file(&source, &destination) {
+            self.finish(entry_id)?;
+            return Err(err);
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        let result = conn.transaction(|conn| {
+            delete_media_rows(conn, &[id])?;
+            finish_entry(conn, entry_id)
+        });
+
+        if let Err(err) = result {
+            self.rollback(entry_id, &source, &destination);
+            return Err(err.into());
+        }
+
+        Ok(destination)
+    }
+
+    /// Completes or rolls back the operations that have been interrupted,
+    /// e.g. by a crash. It should be called when opening the library,
+    /// before performing other operations.
+    ///
+    /// An operation is completed if its file is only at the destination,
+    /// and rolled back if it is still at the source: in that case a partial
+    /// copy at the destination is removed.
+    pub fn recover(&self) -> Result<Vec<Recovered>, Error> {
+        let entries = {
+            use database::schema::file_operations::dsl::id;
+            let conn = &mut self.connection.establish_connection()?;
+            journal_table.order(id.asc()).load::<JournalEntry>(conn)?
+        };
+
+        let mut recovered = vec![];
+        for entry in entries {
+            let operation = match entry.operation.as_str() {
+                "trash" => Operation::Trash,
+                _ => Operation::Move,
+            };
+            let (source, destination) = (Path::new(&entry.source), Path::new(&entry.destination));
+
+            let outcome = if source.exists() {
+                if destination.exists() {
+                    fs::remove_file(destination)?;
+                }
+                self.finish(entry.id)?;
+                Outcome::RolledBack
+            } else if destination.exists() {
+                if operation == Operation::Trash {
+                    thumbnails::thumbnails(self.connection.clone()).remove(entry.media_id)?;
+                }
+
+                let conn = &mut self.connection.establish_connection()?;
+                conn.transaction(|conn| {
+                    match (operation, entry.base_path_id, &entry.relative_path) {
+                        (Operation::Move, Some(bp_id), Some(rp)) => {
+                            set_location(conn, entry.media_id, bp_id, rp)?;
+                        }
+                        (Operation::Trash, _, _) => {
+                            delete_media_rows(conn, &[entry.media_id])?;
+                        }
+                        _ => (),
+                    }
+                    finish_entry(conn, entry.id)
+                })?;
+                Outcome::Completed
+            } else {
+                let conn = &mut self.connection.establish_connection()?;
+                conn.transaction(|conn| {
+                    use database::schema::media::dsl::{id, missing};
+                    diesel::update(media_table.filter(id.eq(entry.media_id)))
+                        .set(missing.eq(true))
+                        .execute(conn)?;
+                    finish_entry(conn, entry.id)
+                })?;
+                Outcome::Lost
+            };
+
+            recovered.push(Recovered {
+                media_id: entry.media_id,
+                operation,
+                outcome,
+            });
+        }
+
+        Ok(recovered)
+    }
+
+    /// Returns the absolute path of a file in an online base path.
+    fn online_path(&self, base_path_id: i32, relative_path: &str) -> Result<PathBuf, Error> {
+        let bp = base_paths(self.connection.clone()).get(base_path_id)?;
+        if base_paths::status(&bp) == Status::Offline {
+            return Err(Error::Offline);
+        }
+
+        Ok(Path::new(&bp.base_path).join(relative_path))
+    }
+
+    /// Records an operation in the journal, returning the ID of the entry.
+    fn start(
+        &self,
+        media_id: i64,
+        operation: Operation,
+        source: &Path,
+        destination: &Path,
+        location: Option<(i32, &str)>,
+    ) -> Result<i32, Error> {
+        if destination.symlink_metadata().is_ok() {
+            return Err(Error::AlreadyExists);
+        }
+
+        use database::schema::file_operations::dsl as journal;
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::insert_into(journal_table)
+            .values((
+                journal::media_id.eq(media_id),
+                journal::operation.eq(operation.as_str()),
+                journal::source.eq(source.to_string_lossy()),
+                journal::destination.eq(destination.to_string_lossy()),
+                journal::base_path_id.eq(location.map(|(bp_id, _)| bp_id)),
+                journal::relative_path.eq(location.map(|(_, rp)| rp)),
+                journal::started_at.eq(unix_timestamp(SystemTime::now())),
+            ))
+            .returning(journal::id)
+            .get_result(conn)
+            .map_err(Error::DatabaseError)
+    }
+
+    /// Removes an entry from the journal.
+    fn finish(&self, entry_id: i32) -> Result<(), Error> {
+        let conn = &mut self.connection.establish_connection()?;
+        finish_entry(conn, entry_id)
+            .map(|_| ())
+            .map_err(Error::DatabaseError)
+    }
+
+    /// Moves the file back after the database could not be updated. If that
+    /// fails too the journal entry is kept, so that [`Files::recover`] can
+    /// complete the operation later.
+    fn rollback(&self, entry_id: i32, source: &Path, destination: &Path) {
+        if move_file(destination, source).is_ok() {
+            let _ = self.finish(entry_id);
+        }
+    }
+}
+
+/// Trims the provided relative path and checks that it only contains normal,
+/// non hidden components.
+pub(crate) fn validate_relative_path(relative_path: &str) -> Result<String, Error> {
+    let rp = relative_path.trim().trim_matches('/');
+    let is_valid = Path::new(rp).components().all(|component| match component {
+        Component::Normal(name) => !name.to_string_lossy().starts_with('.'),
+        _ => false,
+    });
+
+    if rp.is_empty() || !is_valid {
+        return Err(Error::InvalidRelativePath);
+    }
+
+    Ok(rp.to_string())
+}
+
+/// Moves a file, creating the parent directories of the destination. When
+/// the destination is on another device the file is copied, keeping its
+/// modification time, and then removed.
+fn move_file(source: &Path, destination: &Path) -> Result<(), Error> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::rename(source, destination) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => (),
+        result => return Ok(result?),
+    }
+
+    let copied = fs::copy(source, destination).and_then(|_| {
+        let modified = fs::metadata(source)?.modified()?;
+        File::options()
+            .write(true)
+            .open(destination)?
+            .set_modified(modified)
+    });
+
+    if let Err(err) = copied {
+        let _ = fs::remove_file(destination);
+        return Err(err.into());
+    }
+
+    Ok(fs::remove_file(source)?)
+}
+
+fn set_location(
+    conn: &mut diesel::SqliteConnection,
+    media_id: i64,
+    base_path_id: i32,
+    relative_path: &str,
+) -> diesel::QueryResult<usize> {
+    use database::schema::media::dsl::{base_path_id as bp_id, id, missing, relative_path as rp};
+    diesel::update(media_table.filter(id.eq(media_id)))
+        .set((
+            bp_id.eq(base_path_id),
+            rp.eq(relative_path),
+            missing.eq(false),
+        ))
+        .execute(conn)
+}
+
+fn finish_entry(conn: &mut diesel::SqliteConnection, entry_id: i32) -> diesel::QueryResult<usize> {
+    use database::schema::file_operations::dsl::id;
+    diesel::delete(journal_table.filter(id.eq(entry_id))).execute(conn)
+}
@@ -0,0 +1,221 @@
+use diesel::{
+    sql_query,
+    sql_types::{BigInt, Double, Text},
+    Connection, QueryableByName, RunQueryDsl,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::database::{self, connection::DatabaseConnection};
+
+/// The rowid of an entry of the index is the ID of its entity multiplied by
+/// this, plus the kind of entity: see [`Entity`].
+const KINDS: i64 = 4;
+/// Matches in the title, i.e. path or name, weigh more than matches in the
+/// description.
+const RANK: &str = "bm25(search_index, 10.0, 1.0)";
+/// How many tokens snippets of descriptions contain.
+const SNIPPET_TOKENS: i32 = 16;
+const DEFAULT_LIMIT: i64 = 50;
+
+/// FullText searches the paths, names and descriptions of media, tags, tag
+/// categories and base paths.
+///
+/// The index is kept in sync by triggers on the database, so it only needs
+/// to be rebuilt with [`FullText::rebuild`] if it got out of sync, e.g.
+/// after the database has been modified by another program.
+pub struct FullText {
+    connection: DatabaseConnection,
+    highlight: (String, String),
+}
+
+/// This returns a new instance of the `FullText` struct, that highlights
+/// matches between `[` and `]`.
+pub fn full_text(connection: DatabaseConnection) -> FullText {
+    FullText {
+        connection,
+        highlight: ("[".into(), "]".into()),
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The search does not contain any word.
+    #[error("empty search")]
+    EmptySearch,
+    /// The provided limit is <= 0.
+    #[error("invalid limit")]
+    InvalidLimit,
+}
+
+/// The kind of entity a hit refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Entity {
+    /// A media file, whose title is its relative path.
+    Media,
+    /// A tag, whose title is its name.
+    Tag,
+    /// A tag category, whose title is its name.
+    Category,
+    /// A base path, whose title is its path.
+    BasePath,
+}
+
+/// An entity matching a search.
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub entity: Entity,
+    /// The ID of the entity.
+    pub id: i64,
+    /// How well the entity matches: the higher, the better.
+    pub score: f64,
+    /// The path or name of the entity, with the matches highlighted.
+    pub title: String,
+    /// The part of the description that matches best, with the matches
+    /// highlighted, or an empty string.
+    pub snippet: String,
+}
+
+#[derive(QueryableByName)]
+struct HitRow {
+    #[diesel(sql_type = BigInt)]
+    rowid: i64,
+    #[diesel(sql_type = Double)]
+    score: f64,
+    #[diesel(sql_type = Text)]
+    title: String,
+    #[diesel(sql_type = Text)]
+    snippet: String,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+impl FullText {
+    /// Highlights matches between `start` and `end`, e.g. `<mark>` and
+    /// `</mark>`, instead of brackets.
+    pub fn with_highlight(mut self, start: impl Into<String>, end: impl Into<String>) -> Self {
+        self.highlight = (start.into(), end.into());
+        self
+    }
+
+    /// Searches all entities, returning at most `limit` hits, 50 if `None`,
+    /// from the best one.
+    ///
+    /// All the words must match, ignoring case and diacritics. A word ending
+    /// with `*` matches the words starting with it, e.g. `sun*`, and words
+    /// between double quotes must appear next to each other, e.g.
+    /// `"golden hour"`. Any other punctuation separates words, so that
+    /// paths can be searched as typed, e.g. `2023/beach`.
+    ///
+    /// It returns an error in case the search has no words, the limit is
+    /// invalid or if there was an error on the database.
+    pub fn search(&self, text: impl AsRef<str>, limit: Option<i64>) -> Result<Vec<Hit>, Error> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if limit <= 0 {
+            return Err(Error::InvalidLimit);
+        }
+
+        let query = to_fts_query(text.as_ref()).ok_or(Error::EmptySearch)?;
+        let (start, end) = &self.highlight;
+        let conn = &mut self.connection.establish_connection()?;
+        let rows = sql_query(format!(
+            "SELECT rowid, -{RANK} AS score, \
+             highlight(search_index, 0, ?, ?) AS title, \
+             snippet(search_index, 1, ?, ?, '…', {SNIPPET_TOKENS}) AS snippet \
+             FROM search_index WHERE search_index MATCH ? \
+             ORDER BY {RANK} LIMIT ?"
+        ))
+        .bind::<Text, _>(start)
+        .bind::<Text, _>(end)
+        .bind::<Text, _>(start)
+        .bind::<Text, _>(end)
+        .bind::<Text, _>(query)
+        .bind::<BigInt, _>(limit)
+        .load::<HitRow>(conn)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Hit {
+                entity: match row.rowid % KINDS {
+                    0 => Entity::Media,
+                    1 => Entity::Tag,
+                    2 => Entity::Category,
+                    _ => Entity::BasePath,
+                },
+                id: row.rowid / KINDS,
+                score: row.score,
+                title: row.title,
+                snippet: row.snippet,
+            })
+            .collect())
+    }
+
+    /// Empties the index and fills it again from all the entities, e.g. for
+    /// libraries created before the index existed or modified by another
+    /// program.
+    ///
+    /// It returns the number of entries in the index.
+    pub fn rebuild(&self) -> Result<usize, Error> {
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| {
+            // The entries are defined once, by the `search_documents` view
+            // of the migration creating the index.
+            sql_query("DELETE FROM search_index").execute(conn)?;
+            sql_query(
+                "INSERT INTO search_index (rowid, title, body) \
+                 SELECT id, title, body FROM search_documents",
+            )
+            .execute(conn)?;
+            sql_query("INSERT INTO search_index (search_index) VALUES ('optimize')")
+                .execute(conn)?;
+
+            sql_query("SELECT COUNT(*) AS count FROM search_index").get_result::<Count>(conn)
+        })
+        .map(|count| count.count as usize)
+        .map_err(Error::DatabaseError)
+    }
+}
+
+/// Converts a search to an FTS5 query, quoting each word so that
+/// punctuation and FTS5 keywords, e.g. `OR`, are not interpreted.
+fn to_fts_query(text: &str) -> Option<String> {
+    let mut terms = vec![];
+    for (i, part) in text.split('"').enumerate() {
+        // Odd parts are between quotes: an unclosed quote runs to the end.
+        if i % 2 == 1 {
+            if part.chars().any(char::is_alphanumeric) {
+                terms.push(format!("\"{}\"", part.trim()));
+            }
+            continue;
+        }
+
+        for word in part.split_whitespace() {
+            let (word, is_prefix) = match word.strip_suffix('*') {
+                Some(word) => (word.trim_end_matches('*'), true),
+                None => (word, false),
+            };
+
+            if word.chars().any(char::is_alphanumeric) {
+                terms.push(format!("\"{}\"{}", word, if is_prefix { "*" } else { "" }));
+            }
+        }
+    }
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use raster::error::RasterError;
+use thiserror::Error;
+
+/// The width of the grid used to compute the perceptual hash: it has one
+/// column more than the height since each bit compares two adjacent cells.
+const DHASH_WIDTH: usize = 9;
+const DHASH_HEIGHT: usize = 8;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The file could not be opened or read.
+    #[error("cannot read file: {0}")]
+    IOError(#[from] io::Error),
+    /// The file is not an image that can be decoded, e.g. its format is not
+    /// supported.
+    #[error("cannot decode image: {0}")]
+    DecodeError(String),
+}
+
+impl From<RasterError> for Error {
+    fn from(value: RasterError) -> Self {
+        match value {
+            RasterError::Io(err) => Error::IOError(err),
+            err => Error::DecodeError(format!("{:?}", err)),
+        }
+    }
+}
+
+/// Computes the BLAKE3 hash of the content of the file at the provided path,
+/// returned as a lowercase hex string.
+///
+/// It returns an error in case the file cannot be opened or read.
+pub fn content_hash(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Same as [`content_hash`], but for content already in memory, e.g. an
+/// entry read from an archive.
+pub fn content_hash_of(content: &[u8]) -> String {
+    blake3::hash(content).to_hex().to_string()
+}
+
+/// Same as [`content_hash`], but reads the file at most at
+/// `bytes_per_second`, sleeping between reads, so that hashing large
+/// libraries does not saturate the disk.
+pub fn content_hash_throttled(path: impl AsRef<Path>, bytes_per_second: u64) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let (start, mut read) = (Instant::now(), 0_u64);
+
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..n]);
+        read += n as u64;
+
+        let expected = Duration::from_secs_f64(read as f64 / bytes_per_second.max(1) as f64);
+        if let Some(ahead) = expected.checked_sub(start.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Computes the difference hash (dHash) of the image at the provided path.
+///
+/// The image is reduced to a 9x8 grid of average luminances and each bit of
+/// the hash tells whether a cell is darker than the one on its right, so that
+/// resized or re-encoded copies of the same image have the same hash or one
+/// with a small [`hamming_distance`].
+///
+/// Only JPEG, PNG and GIF images are supported.
+pub fn perceptual_hash(path: impl AsRef<Path>) -> Result<u64, Error> {
+    let path = path.as_ref();
+    let image = match path.to_str() {
+        None => return Err(Error::DecodeError("path is not valid UTF-8".into())),
+        Some(p) => raster::open(p)?,
+    };
+
+    let (width, height) = (image.width as usize, image.height as usize);
+    if width == 0 || height == 0 {
+        return Err(Error::DecodeError("image is empty".into()));
+    }
+
+    let mut sums = [[0.0_f64; DHASH_WIDTH]; DHASH_HEIGHT];
+    let mut counts = [[0_u64; DHASH_WIDTH]; DHASH_HEIGHT];
+    for (i, pixel) in image.bytes.chunks_exact(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let (cell_x, cell_y) = (x * DHASH_WIDTH / width, y * DHASH_HEIGHT / height);
+
+        sums[cell_y][cell_x] +=
+            0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64;
+        counts[cell_y][cell_x] += 1;
+    }
+
+    let mut hash = 0_u64;
+    for y in 0..DHASH_HEIGHT {
+        let averages: Vec<f64> = (0..DHASH_WIDTH)
+            .map(|x| sums[y][x] / counts[y][x].max(1) as f64)
+            .collect();
+
+        for x in 0..DHASH_WIDTH - 1 {
+            hash <<= 1;
+            if averages[x] < averages[x + 1] {
+                hash |= 1;
+            }
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Returns the number of bits that differ between two perceptual hashes.
+pub fn hamming_distance(first: i64, second: i64) -> u32 {
+    (first ^ second).count_ones()
+}
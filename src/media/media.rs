@@ -2,6 +2,7 @@ use super::base_paths::Error as BasePathsError;
 use crate::{
     data::{
         media_file::{MediaFile, MediaType},
+        media_metadata::MediaMetadata,
         tag::Tag,
     },
     database::{
@@ -12,17 +13,34 @@ use crate::{
             media_tags::{self},
         },
     },
-    media::base_paths,
+    media::{
+        archive, base_paths, hash, metadata,
+        query::{MediaPage, MediaQuery, TagFilter},
+        scanner, search, thumbnails,
+    },
     tags::{self},
 };
 use diesel::{
-    dsl::count_distinct, ExpressionMethods, Insertable, QueryDsl, Queryable, RunQueryDsl,
+    dsl::count_star, BoolExpressionMethods, Connection, ExpressionMethods, Insertable,
+    NullableExpressionMethods, QueryDsl, Queryable, RunQueryDsl,
+};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::From,
+    fs,
+    path::{Path, PathBuf},
 };
-use std::convert::From;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
 
 const MAX_DESCRIPTION_LENGTH: usize = 300;
+/// Sizes are stored in kB: files whose sizes differ by less than this are
+/// considered to have the same size.
+pub(crate) const SIZE_TOLERANCE_KB: f64 = 0.5;
+/// How many IDs are bound at once in a query, below the limit of bound
+/// parameters of SQLite.
+pub(crate) const MAX_BOUND_IDS: usize = 500;
 
 #[derive(Debug, Error)]
 #[non_exhaustive]
@@ -82,6 +100,55 @@ pub enum Error {
     /// No tags have been provided.
     #[error("no tags have been provided")]
     NoTagsProvided,
+    /// The metadata could not be read from the file.
+    #[error("metadata error: {0}")]
+    MetadataError(#[from] metadata::Error),
+    /// No metadata has been imported for this media yet.
+    #[error("metadata not found")]
+    MetadataNotFound,
+    /// The provided content hash is invalid, e.g. it is empty.
+    #[error("invalid hash")]
+    InvalidHash,
+    /// The file could not be read from disk.
+    #[error("cannot read file: {0}")]
+    IOError(#[from] std::io::Error),
+    /// The perceptual hash could not be computed.
+    #[error("hash error: {0}")]
+    HashError(#[from] hash::Error),
+    /// The operation is only available for media of type `Image`.
+    #[error("not an image")]
+    NotAnImage,
+    /// The provided Hamming distance is invalid, i.e. it is > 64.
+    #[error("invalid distance")]
+    InvalidDistance,
+    /// The thumbnails of the media could not be removed.
+    #[error("thumbnail error: {0}")]
+    ThumbnailError(thumbnails::Error),
+    /// The archive containing the media could not be read.
+    #[error("archive error: {0}")]
+    ArchiveError(#[from] archive::Error),
+    /// The operation is not available for media stored inside an archive,
+    /// e.g. decoding images requires a file on disk.
+    #[error("not supported for archive entries")]
+    ArchiveEntry,
+    /// A range of the query is invalid, e.g. its minimum is greater than its
+    /// maximum or it is negative.
+    #[error("invalid range")]
+    InvalidRange,
+    /// The limit of the query is <= 0 or its offset is < 0.
+    #[error("invalid pagination")]
+    InvalidPagination,
+    /// The cursor of the query was returned by a query with different sort
+    /// keys.
+    #[error("invalid cursor")]
+    InvalidCursor,
+    /// The search is invalid or refers to unknown tags.
+    #[error("search error: {0}")]
+    SearchError(#[from] search::Error),
+    /// The "at least" threshold of a tag filter is 0 or greater than the
+    /// number of its "any of" tags.
+    #[error("invalid tag threshold")]
+    InvalidTagThreshold,
 }
 
 pub struct Media {
@@ -113,6 +180,9 @@ pub struct CreateMediaFile {
     /// The media type, e.g. Image, Video or Sound.
     #[diesel(serialize_as = String)]
     pub media_type: MediaType,
+    /// The path of the entry inside the archive at `relative_path`, if the
+    /// media is stored in an archive.
+    pub archive_entry: Option<String>,
 }
 
 /// Represents a media file to update.
@@ -137,6 +207,10 @@ impl MediaFile {
             return Err(Error::InvalidBasePathID);
         }
 
+        if self.archive_entry.as_ref().is_some_and(String::is_empty) {
+            return Err(Error::InvalidRelativePath);
+        }
+
         match self.width {
             Some(val) if val <= 0 => return Err(Error::InvalidWidth),
             _ => (),
@@ -183,6 +257,11 @@ impl MediaFile {
             },
             description: update_data.description.unwrap_or(self.description),
             media_type: self.media_type,
+            content_hash: self.content_hash,
+            perceptual_hash: self.perceptual_hash,
+            modified: self.modified,
+            missing: self.missing,
+            archive_entry: self.archive_entry,
         };
 
         self
@@ -201,6 +280,13 @@ impl From<CreateMediaFile> for MediaFile {
             mark: value.mark,
             description: value.description.trim().into(),
             media_type: value.media_type,
+            content_hash: None,
+            perceptual_hash: None,
+            modified: None,
+            missing: false,
+            archive_entry: value
+                .archive_entry
+                .map(|entry| entry.trim_matches('/').into()),
         }
     }
 }
@@ -216,9 +302,70 @@ impl Into<CreateMediaFile> for MediaFile {
             mark: self.mark,
             description: self.description,
             media_type: self.media_type,
+            archive_entry: self.archive_entry,
         }
     }
 }
+
+/// A group of media files that have the same content.
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    /// The content hash shared by all files in the group.
+    pub content_hash: String,
+    /// The media files with this content, ordered by ID.
+    pub media: Vec<MediaFile>,
+}
+
+/// A media file similar to another image, along with the Hamming distance
+/// between their perceptual hashes.
+#[derive(Debug, Serialize)]
+pub struct SimilarMedia {
+    /// The similar media file.
+    pub media: MediaFile,
+    /// The number of bits that differ between the perceptual hashes: `0`
+    /// means that the images look the same.
+    pub distance: u32,
+}
+
+/// How a media file was matched to the file it has been moved to.
+#[derive(Debug, Serialize)]
+pub enum MatchedBy {
+    /// The new file has the same content hash.
+    Hash,
+    /// The new file has the same size and modification time.
+    SizeAndModified,
+}
+
+/// A media file whose file has been moved or renamed on disk.
+#[derive(Debug, Serialize)]
+pub struct MovedMedia {
+    /// The ID of the media file, which is kept.
+    pub media_id: i64,
+    /// The previous relative path, followed by the archive entry if any.
+    /// See [`scanner::display_path`].
+    pub from: String,
+    /// The new relative path, followed by the archive entry if any.
+    pub to: String,
+    /// How the new file was matched.
+    pub matched_by: MatchedBy,
+}
+
+/// The outcome of [`Media::reconcile`].
+#[derive(Debug, Default, Serialize)]
+pub struct ReconcileReport {
+    /// The media files that have been moved or renamed on disk, whose
+    /// relative path has been updated.
+    pub moved: Vec<MovedMedia>,
+    /// The relative paths of the files that are not registered yet, followed
+    /// by the archive entry if any.
+    pub new_files: Vec<String>,
+    /// The IDs of the media files created for the new files, if requested.
+    pub imported: Vec<i64>,
+    /// The media files whose file is missing and could not be matched to any
+    /// new file.
+    pub missing: Vec<MediaFile>,
+}
+
 #[derive(Debug, Queryable, Insertable)]
 #[diesel(table_name = media_tags)]
 struct MediaTag {
@@ -247,12 +394,40 @@ impl Media {
     }
 
     /// Gets a media file by using the relative path and the base path id.
+    ///
+    /// Media stored inside an archive are not returned: see
+    /// [`Media::get_archive_entry`].
     pub fn get_by_relative_path(
         &self,
         base_path_id: i32,
         relative_path: impl AsRef<str>,
     ) -> Result<MediaFile, Error> {
-        let rp = relative_path.as_ref().trim_matches('/');
+        self.find_at(base_path_id, relative_path.as_ref(), None)
+    }
+
+    /// Gets a media file stored inside an archive by using the relative path
+    /// of the archive, the path of the entry inside it and the base path id.
+    pub fn get_archive_entry(
+        &self,
+        base_path_id: i32,
+        relative_path: impl AsRef<str>,
+        entry: impl AsRef<str>,
+    ) -> Result<MediaFile, Error> {
+        let entry = entry.as_ref().trim_matches('/');
+        if entry.is_empty() {
+            return Err(Error::InvalidRelativePath);
+        }
+
+        self.find_at(base_path_id, relative_path.as_ref(), Some(entry))
+    }
+
+    fn find_at(
+        &self,
+        base_path_id: i32,
+        relative_path: &str,
+        entry: Option<&str>,
+    ) -> Result<MediaFile, Error> {
+        let rp = relative_path.trim_matches('/');
         if rp.is_empty() {
             return Err(Error::InvalidRelativePath);
         }
@@ -262,48 +437,82 @@ impl Media {
         }
 
         let conn = &mut self.connection.establish_connection()?;
-        use media::dsl::{base_path_id as bp_id, relative_path as media_relative_path};
+        use media::dsl::{
+            archive_entry, base_path_id as bp_id, relative_path as media_relative_path,
+        };
 
-        media_table
+        let mut query = media_table
             .filter(media_relative_path.eq(rp))
             .filter(bp_id.eq(base_path_id))
-            .first(conn)
-            .map_err(|err| match err {
-                diesel::NotFound => Error::NotFound,
-                _ => Error::DatabaseError(err),
-            })
+            .into_boxed();
+        query = match entry {
+            None => query.filter(archive_entry.is_null()),
+            Some(entry) => query.filter(archive_entry.eq(entry)),
+        };
+
+        query.first(conn).map_err(|err| match err {
+            diesel::NotFound => Error::NotFound,
+            _ => Error::DatabaseError(err),
+        })
     }
 
     /// Creates a media file.
     ///
     /// It returns the created `MediaFile` or an error.
     /// Look at [`CreateMediaFile`] for more clues on the errors.
+    ///
+    /// The content hash, and the perceptual hash for images, are computed if
+    /// the file can be read: if not, e.g. because the base path is not
+    /// mounted, they can be computed later with
+    /// [`Media::update_missing_hashes`]. Media stored inside an archive never
+    /// get a perceptual hash, since decoding requires a file on disk.
     pub fn create(&self, create_data: CreateMediaFile) -> Result<MediaFile, Error> {
-        if let Err(err) =
-            base_paths::base_paths(self.connection.clone()).get(create_data.base_path_id)
+        let bp = match base_paths::base_paths(self.connection.clone()).get(create_data.base_path_id)
         {
-            return Err(Error::BasePathsError(err));
-        }
+            Err(err) => return Err(Error::BasePathsError(err)),
+            Ok(bp) => bp,
+        };
 
         let data: CreateMediaFile = MediaFile::from(create_data).validate()?.into();
 
-        {
-            let conn = &mut self.connection.establish_connection()?;
-            use database::schema::media::dsl::{base_path_id, relative_path};
-            match media_table
-                .filter(base_path_id.eq(data.base_path_id))
-                .filter(relative_path.eq(&data.relative_path))
-                .first::<MediaFile>(conn)
-            {
-                Ok(_) => return Err(Error::AlreadyExists),
-                Err(err) if err == diesel::result::Error::NotFound => (),
-                Err(err) => return Err(Error::DatabaseError(err)),
-            }
+        match self.find_at(
+            data.base_path_id,
+            &data.relative_path,
+            data.archive_entry.as_deref(),
+        ) {
+            Ok(_) => return Err(Error::AlreadyExists),
+            Err(Error::NotFound) => (),
+            Err(err) => return Err(err),
         }
 
+        let file_path = Path::new(&bp.base_path).join(&data.relative_path);
+        let (file_hash, image_hash) = match (&data.archive_entry, &data.media_type) {
+            (Some(entry), _) => (
+                archive::read_entry(&file_path, entry)
+                    .ok()
+                    .map(|content| hash::content_hash_of(&content)),
+                None,
+            ),
+            (None, MediaType::Image) => (
+                hash::content_hash(&file_path).ok(),
+                hash::perceptual_hash(&file_path).ok().map(|h| h as i64),
+            ),
+            (None, _) => (hash::content_hash(&file_path).ok(), None),
+        };
+        let file_modified = fs::metadata(&file_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(scanner::unix_timestamp);
+
         let conn = &mut self.connection.establish_connection()?;
+        use database::schema::media::dsl::{content_hash, modified, perceptual_hash};
         match diesel::insert_into(media_table)
-            .values(data)
+            .values((
+                data,
+                content_hash.eq(file_hash),
+                perceptual_hash.eq(image_hash),
+                modified.eq(file_modified),
+            ))
             .get_result(conn)
         {
             Ok(val) => Ok(val),
@@ -335,16 +544,294 @@ impl Media {
             return Err(Error::BasePathsError(err));
         }
 
-        use media::dsl::id as media_id;
+        use media::dsl::{base_path_id as bp_id, id as media_id};
         let conn = &mut self.connection.establish_connection()?;
 
-        match media_table.order(media_id.asc()).load::<MediaFile>(conn) {
+        match media_table
+            .filter(bp_id.eq(base_path_id))
+            .order(media_id.asc())
+            .load::<MediaFile>(conn)
+        {
             Err(err) => Err(Error::DatabaseError(err)),
             Ok(files) => Ok(files),
         }
     }
 
+    /// Lists the media matching the filters of the provided query, sorted and
+    /// paginated, along with how many media match in total.
+    ///
+    /// It returns an error in case the query is invalid or if there was an
+    /// error on the database. See [`MediaQuery`].
+    pub fn query(&self, query: &MediaQuery) -> Result<MediaPage, Error> {
+        query.validate()?;
+        let search = query
+            .search()
+            .map(|text| search::resolve(&self.connection, text))
+            .transpose()?;
+
+        let conn = &mut self.connection.establish_connection()?;
+        let total = query
+            .filtered(search.as_ref())
+            .count()
+            .get_result::<i64>(conn)?;
+        let files = query.paginated(search.as_ref()).load::<MediaFile>(conn)?;
+
+        Ok(MediaPage {
+            next: query.next_cursor(&files),
+            media: files,
+            total: total as usize,
+        })
+    }
+
+    /// Reconciles the media files of a base path with the files that are
+    /// actually on disk.
+    ///
+    /// Media files whose file vanished are matched to the files that are not
+    /// registered yet, first by content hash and then by size and
+    /// modification time: when exactly one file matches, the relative path
+    /// is updated in place, so that tags, marks and descriptions are kept.
+    ///
+    /// If `import_new_files` is `true`, a media file is created for each file
+    /// that is still unregistered after matching.
+    ///
+    /// Media stored inside archives are matched the same way, so that the
+    /// pages of a renamed `.cbz` keep their tags.
+    ///
+    /// It returns an error in case the base path does not exist or cannot be
+    /// scanned, e.g. because it is not mounted.
+    pub fn reconcile(
+        &self,
+        base_path_id: i32,
+        import_new_files: bool,
+    ) -> Result<ReconcileReport, Error> {
+        let bp = match base_paths::base_paths(self.connection.clone()).get(base_path_id) {
+            Err(err) => return Err(Error::BasePathsError(err)),
+            Ok(bp) => bp,
+        };
+
+        // Files inside another base path nested in this one belong to the
+        // latter.
+        let nested: Vec<PathBuf> = base_paths::base_paths(self.connection.clone())
+            .list(None::<Vec<_>>)
+            .map_err(Error::BasePathsError)?
+            .into_iter()
+            .filter(|other| other.id != bp.id)
+            .filter_map(|other| {
+                Path::new(&other.base_path)
+                    .strip_prefix(&bp.base_path)
+                    .ok()
+                    .map(Path::to_path_buf)
+            })
+            .collect();
+
+        let mut files = scanner::scan(&bp.base_path)?;
+        files.retain(|f| {
+            !nested
+                .iter()
+                .any(|n| Path::new(&f.relative_path).starts_with(n))
+        });
+        let rows = {
+            use database::schema::media::dsl::{base_path_id as bp_id, id};
+            let conn = &mut self.connection.establish_connection()?;
+            media_table
+                .filter(bp_id.eq(base_path_id))
+                .order(id.asc())
+                .load::<MediaFile>(conn)?
+        };
+
+        let on_disk: HashSet<(&str, Option<&str>)> = files
+            .iter()
+            .map(|f| (f.relative_path.as_str(), f.archive_entry.as_deref()))
+            .collect();
+        let (vanished, registered): (Vec<MediaFile>, Vec<MediaFile>) =
+            rows.into_iter().partition(|row| {
+                !on_disk.contains(&(row.relative_path.as_str(), row.archive_entry.as_deref()))
+            });
+        let registered: HashSet<(String, Option<String>)> = registered
+            .into_iter()
+            .map(|r| (r.relative_path, r.archive_entry))
+            .collect();
+        let mut new_files: Vec<scanner::ScannedFile> = files
+            .into_iter()
+            .filter(|f| !registered.contains(&(f.relative_path.clone(), f.archive_entry.clone())))
+            .collect();
+
+        // Hashes of the new files are only computed when needed, i.e. when
+        // they have the same size as a vanished file.
+        let mut new_hashes: HashMap<String, Option<String>> = HashMap::new();
+        let mut locations = vec![];
+        let mut report = ReconcileReport::default();
+        for row in vanished {
+            let same_size: Vec<usize> = (0..new_files.len())
+                .filter(|&i| (new_files[i].size_kb() - row.size).abs() < SIZE_TOLERANCE_KB)
+                .collect();
+
+            let mut found = None;
+            if let Some(row_hash) = &row.content_hash {
+                let by_hash: Vec<usize> = same_size
+                    .iter()
+                    .copied()
+                    .filter(|&i| {
+                        let file = &new_files[i];
+                        let path = Path::new(&bp.base_path).join(&file.relative_path);
+                        new_hashes
+                            .entry(file.display_path())
+                            .or_insert_with(|| match &file.archive_entry {
+                                None => hash::content_hash(path).ok(),
+                                Some(entry) => archive::read_entry(path, entry)
+                                    .ok()
+                                    .map(|content| hash::content_hash_of(&content)),
+                            })
+                            .as_ref()
+                            == Some(row_hash)
+                    })
+                    .collect();
+
+                if by_hash.len() == 1 {
+                    found = Some((by_hash[0], MatchedBy::Hash));
+                }
+            }
+
+            if let (None, Some(row_modified)) = (&found, row.modified) {
+                let by_modified: Vec<usize> = same_size
+                    .iter()
+                    .copied()
+                    .filter(|&i| new_files[i].modified == row_modified)
+                    .collect();
+
+                if by_modified.len() == 1 {
+                    found = Some((by_modified[0], MatchedBy::SizeAndModified));
+                }
+            }
+
+            match found {
+                None => report.missing.push(row),
+                Some((i, matched_by)) => {
+                    let file = new_files.remove(i);
+                    report.moved.push(MovedMedia {
+                        media_id: row.id,
+                        from: scanner::display_path(
+                            &row.relative_path,
+                            row.archive_entry.as_deref(),
+                        ),
+                        to: file.display_path(),
+                        matched_by,
+                    });
+                    locations.push((row.id, file.relative_path, file.archive_entry));
+                }
+            }
+        }
+
+        {
+            use database::schema::media::dsl::{archive_entry, id, missing, relative_path};
+            let conn = &mut self.connection.establish_connection()?;
+            conn.transaction(|conn| {
+                for (media_id, rp, entry) in &locations {
+                    diesel::update(media_table.filter(id.eq(media_id)))
+                        .set((
+                            relative_path.eq(rp),
+                            archive_entry.eq(entry),
+                            missing.eq(false),
+                        ))
+                        .execute(conn)?;
+                }
+
+                diesel::QueryResult::Ok(())
+            })?;
+        }
+
+        report.new_files = new_files.iter().map(|f| f.display_path()).collect();
+        if import_new_files {
+            for file in new_files {
+                match self.create(CreateMediaFile {
+                    size: file.size_kb(),
+                    relative_path: file.relative_path,
+                    base_path_id,
+                    width: None,
+                    height: None,
+                    mark: None,
+                    description: String::new(),
+                    media_type: file.media_type,
+                    archive_entry: file.archive_entry,
+                }) {
+                    Ok(created) => report.imported.push(created.id),
+                    // Empty files cannot be registered.
+                    Err(Error::InvalidSize) => (),
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Lists the media matching the provided search, e.g.
+    /// `(beach OR sea) AND Person:alice -blurry mark:>=7 type:video`,
+    /// ordered by ID. See [`search::parse`] for the syntax.
+    ///
+    /// This is a convenient function for [`Media::query`] and thus returns
+    /// the same errors.
+    pub fn search(&self, search: impl AsRef<str>) -> Result<Vec<MediaFile>, Error> {
+        Ok(self.query(&MediaQuery::new().with_search(search))?.media)
+    }
+
+    /// Changes the location of the media file with the provided ID, e.g.
+    /// because its file has been moved or renamed on disk, keeping its tags,
+    /// mark and description.
+    ///
+    /// Only the database is updated: the file itself is not touched. Media
+    /// stored inside an archive keep their entry, i.e. `relative_path` is the
+    /// new location of the archive.
+    /// It returns an error in case the media or the base path do not exist,
+    /// or another media file is already registered at the new location.
+    pub fn set_location(
+        &self,
+        id: i64,
+        base_path_id: i32,
+        relative_path: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let existing = self.get(id)?;
+        let rp = relative_path.as_ref().trim_matches('/');
+        match self.find_at(base_path_id, rp, existing.archive_entry.as_deref()) {
+            Ok(other) if other.id == id => return Ok(()),
+            Ok(_) => return Err(Error::AlreadyExists),
+            Err(Error::NotFound) => (),
+            Err(err) => return Err(err),
+        }
+
+        if let Err(err) = base_paths::base_paths(self.connection.clone()).get(base_path_id) {
+            return Err(Error::BasePathsError(err));
+        }
+
+        use database::schema::media::dsl::{
+            base_path_id as bp_id, id as media_id, relative_path as m_rp,
+        };
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::update(media_table.filter(media_id.eq(id)))
+            .set((bp_id.eq(base_path_id), m_rp.eq(rp)))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::DatabaseError)
+    }
+
+    /// Sets whether the file of the media with the provided ID is missing on
+    /// disk, e.g. to keep it for review instead of deleting it.
+    pub fn set_missing(&self, id: i64, is_missing: bool) -> Result<(), Error> {
+        let _existing = self.get(id)?;
+
+        use database::schema::media::dsl::{id as media_id, missing};
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::update(media_table.filter(media_id.eq(id)))
+            .set(missing.eq(is_missing))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(Error::DatabaseError)
+    }
+
     /// Deletes a media file with the provided ID.
+    ///
+    /// It returns [`Error::InUse`] in case the media is tagged or belongs to
+    /// a collection.
     pub fn delete(&self, id: i64) -> Result<(), Error> {
         let _existing = self.get(id)?;
 
@@ -352,18 +839,450 @@ impl Media {
 
         {
             use database::schema::media_tags::dsl::{media_id, media_tags as mt_table};
-            match mt_table.filter(media_id.eq(id)).count().execute(conn) {
-                Ok(vals) if vals == 0 => (),
+            match mt_table
+                .filter(media_id.eq(id))
+                .count()
+                .get_result::<i64>(conn)
+            {
+                Ok(0) => (),
                 Ok(_) => return Err(Error::InUse),
                 Err(err) => return Err(Error::DatabaseError(err)),
             }
         }
 
-        use database::schema::media::dsl::id as media_id;
-        match diesel::delete(media_table.filter(media_id.eq(id))).execute(conn) {
-            Ok(_) => Ok(()),
-            Err(err) => Err(Error::DatabaseError(err)),
+        {
+            use database::schema::collection_media::dsl::{collection_media, media_id};
+            match collection_media
+                .filter(media_id.eq(id))
+                .count()
+                .get_result::<i64>(conn)
+            {
+                Ok(0) => (),
+                Ok(_) => return Err(Error::InUse),
+                Err(err) => return Err(Error::DatabaseError(err)),
+            }
+        }
+
+        if let Err(err) = thumbnails::thumbnails(self.connection.clone()).remove(id) {
+            return Err(Error::ThumbnailError(err));
         }
+
+        conn.transaction(|conn| {
+            use database::schema::media_metadata::dsl::{media_id as mm_id, media_metadata};
+            diesel::delete(media_metadata.filter(mm_id.eq(id))).execute(conn)?;
+
+            use database::schema::media_verifications::dsl::{
+                media_id as mv_id, media_verifications,
+            };
+            diesel::delete(media_verifications.filter(mv_id.eq(id))).execute(conn)?;
+
+            use database::schema::media::dsl::id as media_id;
+            diesel::delete(media_table.filter(media_id.eq(id))).execute(conn)
+        })
+        .map(|_| ())
+        .map_err(Error::DatabaseError)
+    }
+
+    /// Returns the absolute path of the provided media file on disk. For
+    /// media stored inside an archive, this is the path of the archive.
+    ///
+    /// It returns an error in case the base path of the file does not exist.
+    pub fn absolute_path(&self, file: &MediaFile) -> Result<PathBuf, Error> {
+        match base_paths::base_paths(self.connection.clone()).get(file.base_path_id) {
+            Err(err) => Err(Error::BasePathsError(err)),
+            Ok(bp) => Ok(Path::new(&bp.base_path).join(&file.relative_path)),
+        }
+    }
+
+    /// Reads the whole content of the provided media file, reading it from
+    /// its archive if needed.
+    ///
+    /// It returns an error in case the base path of the file does not exist,
+    /// or the file or the archive entry cannot be read.
+    pub fn read_content(&self, file: &MediaFile) -> Result<Vec<u8>, Error> {
+        let path = self.absolute_path(file)?;
+        match &file.archive_entry {
+            None => Ok(fs::read(path)?),
+            Some(entry) => Ok(archive::read_entry(path, entry)?),
+        }
+    }
+
+    /// Gets the path of the thumbnail of the image with the provided ID,
+    /// fitting in a square of `size` pixels.
+    ///
+    /// This is a convenient function for [`thumbnails::Thumbnails::get`] and
+    /// thus returns the same errors.
+    pub fn thumbnail(&self, id: i64, size: u32) -> Result<PathBuf, thumbnails::Error> {
+        thumbnails::thumbnails(self.connection.clone()).get(id, size)
+    }
+
+    /// Gets the metadata, e.g. EXIF, that was imported for the media with the
+    /// provided ID.
+    ///
+    /// It returns an error in case the media does not exist or if no metadata
+    /// has been imported for it, yet.
+    pub fn get_metadata(&self, id: i64) -> Result<MediaMetadata, Error> {
+        let _existing = self.get(id)?;
+
+        use database::schema::media_metadata::dsl::{media_id, media_metadata};
+        let conn = &mut self.connection.establish_connection()?;
+        media_metadata
+            .filter(media_id.eq(id))
+            .first(conn)
+            .map_err(|err| match err {
+                diesel::NotFound => Error::MetadataNotFound,
+                _ => Error::DatabaseError(err),
+            })
+    }
+
+    /// Reads the EXIF data from the file of the media with the provided ID and
+    /// stores it, replacing any metadata previously imported.
+    ///
+    /// In case the orientation rotates the image by 90 or 270 degrees, width
+    /// and height of the media are swapped, so that they reflect the image as
+    /// it is displayed.
+    ///
+    /// It returns an error in case the media does not exist, the file cannot
+    /// be read or does not contain EXIF data.
+    pub fn import_metadata(&self, id: i64) -> Result<MediaMetadata, Error> {
+        let existing = self.get(id)?;
+        let exif = match existing.archive_entry {
+            None => metadata::read_exif(self.absolute_path(&existing)?)?,
+            Some(_) => metadata::read_exif_from_bytes(&self.read_content(&existing)?)?,
+        };
+
+        let (width, height) = match exif.oriented_dimensions() {
+            Some((w, h)) => match (i16::try_from(w), i16::try_from(h)) {
+                (Ok(w), Ok(h)) => (Some(w), Some(h)),
+                _ => (existing.width, existing.height),
+            },
+            None => {
+                // Without dimensions in the EXIF data, the existing ones are
+                // swapped only if the rotation changed since the last import.
+                let previous = match self.get_metadata(id) {
+                    Ok(val) => val.orientation,
+                    Err(Error::MetadataNotFound) => None,
+                    Err(err) => return Err(err),
+                };
+
+                if metadata::is_rotated(previous) != metadata::is_rotated(exif.metadata.orientation)
+                {
+                    (existing.height, existing.width)
+                } else {
+                    (existing.width, existing.height)
+                }
+            }
+        };
+
+        let data = MediaMetadata {
+            media_id: id,
+            ..exif.metadata
+        };
+
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| {
+            use database::schema::media::dsl::{height as m_height, id as m_id, width as m_width};
+            diesel::update(media_table.filter(m_id.eq(id)))
+                .set((m_width.eq(width), m_height.eq(height)))
+                .execute(conn)?;
+
+            use database::schema::media_metadata::dsl::media_metadata;
+            diesel::replace_into(media_metadata)
+                .values(&data)
+                .execute(conn)
+        })
+        .map_err(Error::DatabaseError)?;
+
+        Ok(data)
+    }
+
+    /// Computes the content hash of the file of the media with the provided
+    /// ID and stores it, replacing the existing one.
+    ///
+    /// It returns the new hash or an error in case the media does not exist
+    /// or its file cannot be read.
+    pub fn update_hash(&self, id: i64) -> Result<String, Error> {
+        let existing = self.get(id)?;
+        let file_hash = match existing.archive_entry {
+            None => hash::content_hash(self.absolute_path(&existing)?)?,
+            Some(_) => hash::content_hash_of(&self.read_content(&existing)?),
+        };
+
+        use database::schema::media::dsl::{content_hash, id as media_id};
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::update(media_table.filter(media_id.eq(id)))
+            .set(content_hash.eq(&file_hash))
+            .execute(conn)?;
+
+        Ok(file_hash)
+    }
+
+    /// Computes the perceptual hash of the image with the provided ID and
+    /// stores it, replacing the existing one.
+    ///
+    /// It returns the new hash or an error in case the media does not exist,
+    /// is not an image, is stored inside an archive or its file cannot be
+    /// decoded.
+    pub fn update_perceptual_hash(&self, id: i64) -> Result<i64, Error> {
+        let existing = self.get(id)?;
+        if !matches!(existing.media_type, MediaType::Image) {
+            return Err(Error::NotAnImage);
+        }
+
+        if existing.archive_entry.is_some() {
+            return Err(Error::ArchiveEntry);
+        }
+
+        let image_hash = hash::perceptual_hash(self.absolute_path(&existing)?)? as i64;
+
+        use database::schema::media::dsl::{id as media_id, perceptual_hash};
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::update(media_table.filter(media_id.eq(id)))
+            .set(perceptual_hash.eq(image_hash))
+            .execute(conn)?;
+
+        Ok(image_hash)
+    }
+
+    /// Computes the content hash of all the media files of the provided base
+    /// path that do not have one yet, e.g. because they were created while
+    /// the base path was not mounted. The same goes for the perceptual hash
+    /// of images, except those stored inside an archive.
+    ///
+    /// Files that cannot be read or decoded are skipped. It returns the number
+    /// of media files that have been hashed.
+    pub fn update_missing_hashes(&self, base_path_id: i32) -> Result<usize, Error> {
+        if let Err(err) = base_paths::base_paths(self.connection.clone()).get(base_path_id) {
+            return Err(Error::BasePathsError(err));
+        }
+
+        let files = {
+            use database::schema::media::dsl::{
+                archive_entry, base_path_id as bp_id, content_hash, id, media_type, perceptual_hash,
+            };
+            let conn = &mut self.connection.establish_connection()?;
+            media_table
+                .filter(bp_id.eq(base_path_id))
+                .filter(
+                    content_hash.is_null().or(media_type
+                        .eq("image")
+                        .and(perceptual_hash.is_null())
+                        .and(archive_entry.is_null())),
+                )
+                .order(id.asc())
+                .load::<MediaFile>(conn)?
+        };
+
+        let mut hashed = 0;
+        for file in files {
+            let mut updated = false;
+            if file.content_hash.is_none() {
+                match self.update_hash(file.id) {
+                    Ok(_) => updated = true,
+                    Err(Error::IOError(_) | Error::ArchiveError(_)) => (),
+                    Err(err) => return Err(err),
+                }
+            }
+
+            if matches!(file.media_type, MediaType::Image)
+                && file.perceptual_hash.is_none()
+                && file.archive_entry.is_none()
+            {
+                match self.update_perceptual_hash(file.id) {
+                    Ok(_) => updated = true,
+                    Err(Error::HashError(_)) => (),
+                    Err(err) => return Err(err),
+                }
+            }
+
+            if updated {
+                hashed += 1;
+            }
+        }
+
+        Ok(hashed)
+    }
+
+    /// Lists all media files, possibly under different base paths, with the
+    /// provided content hash.
+    pub fn find_by_hash(&self, file_hash: impl AsRef<str>) -> Result<Vec<MediaFile>, Error> {
+        let file_hash = file_hash.as_ref().trim().to_ascii_lowercase();
+        if file_hash.is_empty() {
+            return Err(Error::InvalidHash);
+        }
+
+        use database::schema::media::dsl::{content_hash, id};
+        let conn = &mut self.connection.establish_connection()?;
+        media_table
+            .filter(content_hash.eq(file_hash))
+            .order(id.asc())
+            .load(conn)
+            .map_err(Error::DatabaseError)
+    }
+
+    /// Finds all media files that share their content with at least another
+    /// media file, grouped by content hash.
+    ///
+    /// Media files without a content hash are ignored.
+    pub fn find_duplicates(&self) -> Result<Vec<DuplicateGroup>, Error> {
+        use database::schema::media::dsl::{content_hash, id};
+        let conn = &mut self.connection.establish_connection()?;
+
+        let others = diesel::alias!(media as others);
+        let duplicated = others
+            .select(others.field(content_hash))
+            .filter(others.field(content_hash).is_not_null())
+            .group_by(others.field(content_hash))
+            .having(count_star().gt(1));
+
+        let files = media_table
+            .filter(content_hash.eq_any(duplicated))
+            .order((content_hash.asc(), id.asc()))
+            .load::<MediaFile>(conn)?;
+
+        let mut groups: Vec<DuplicateGroup> = vec![];
+        for file in files {
+            let file_hash = file.content_hash.clone().unwrap_or_default();
+            match groups.last_mut() {
+                Some(group) if group.content_hash == file_hash => group.media.push(file),
+                _ => groups.push(DuplicateGroup {
+                    content_hash: file_hash,
+                    media: vec![file],
+                }),
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Finds the images that look like the one with the provided ID, i.e.
+    /// whose perceptual hash is within `max_distance` bits from its own.
+    ///
+    /// The perceptual hash of the image is computed if missing. The results
+    /// are ordered by distance, closest first, and do not include the image
+    /// itself.
+    pub fn find_similar(&self, id: i64, max_distance: u32) -> Result<Vec<SimilarMedia>, Error> {
+        let existing = self.get(id)?;
+        let image_hash = match existing.perceptual_hash {
+            Some(val) => val,
+            None => self.update_perceptual_hash(id)?,
+        };
+
+        Ok(self
+            .find_similar_to_hash(image_hash, max_distance)?
+            .into_iter()
+            .filter(|similar| similar.media.id != id)
+            .collect())
+    }
+
+    /// Finds the images of the library that look like the image at the
+    /// provided path, e.g. to know if it has already been imported.
+    ///
+    /// The results are ordered by distance, closest first.
+    pub fn find_similar_to_file(
+        &self,
+        path: impl AsRef<Path>,
+        max_distance: u32,
+    ) -> Result<Vec<SimilarMedia>, Error> {
+        let image_hash = hash::perceptual_hash(path)? as i64;
+        self.find_similar_to_hash(image_hash, max_distance)
+    }
+
+    /// Groups all the images of the library in clusters of images that look
+    /// alike, i.e. each image is within `max_distance` bits from at least
+    /// another image in the same cluster.
+    ///
+    /// Images without a perceptual hash and images that do not look like any
+    /// other are not included.
+    pub fn find_similar_clusters(&self, max_distance: u32) -> Result<Vec<Vec<MediaFile>>, Error> {
+        if max_distance > 64 {
+            return Err(Error::InvalidDistance);
+        }
+
+        let files = {
+            use database::schema::media::dsl::{id, perceptual_hash};
+            let conn = &mut self.connection.establish_connection()?;
+            media_table
+                .filter(perceptual_hash.is_not_null())
+                .order(id.asc())
+                .load::<MediaFile>(conn)?
+        };
+
+        // Union-find over the indexes of the files.
+        fn root(parents: &mut [usize], mut i: usize) -> usize {
+            while parents[i] != i {
+                parents[i] = parents[parents[i]];
+                i = parents[i];
+            }
+            i
+        }
+
+        let hashes: Vec<i64> = files.iter().filter_map(|f| f.perceptual_hash).collect();
+        let mut parents: Vec<usize> = (0..files.len()).collect();
+        for i in 0..hashes.len() {
+            for j in (i + 1)..hashes.len() {
+                if hash::hamming_distance(hashes[i], hashes[j]) <= max_distance {
+                    let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                    parents[a.max(b)] = a.min(b);
+                }
+            }
+        }
+
+        let mut clusters: Vec<Vec<MediaFile>> = (0..files.len()).map(|_| vec![]).collect();
+        for (i, file) in files.into_iter().enumerate() {
+            let r = root(&mut parents, i);
+            clusters[r].push(file);
+        }
+
+        Ok(clusters
+            .into_iter()
+            .filter(|cluster| cluster.len() > 1)
+            .collect())
+    }
+
+    fn find_similar_to_hash(
+        &self,
+        image_hash: i64,
+        max_distance: u32,
+    ) -> Result<Vec<SimilarMedia>, Error> {
+        if max_distance > 64 {
+            return Err(Error::InvalidDistance);
+        }
+
+        let candidates = {
+            use database::schema::media::dsl::{id, perceptual_hash};
+            let conn = &mut self.connection.establish_connection()?;
+            media_table
+                .select((id, perceptual_hash.assume_not_null()))
+                .filter(perceptual_hash.is_not_null())
+                .load::<(i64, i64)>(conn)?
+        };
+
+        let distances: HashMap<i64, u32> = candidates
+            .into_iter()
+            .map(|(media_id, h)| (media_id, hash::hamming_distance(image_hash, h)))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .collect();
+
+        use database::schema::media::dsl::id;
+        let conn = &mut self.connection.establish_connection()?;
+        let ids: Vec<i64> = distances.keys().copied().collect();
+        let mut similar: Vec<SimilarMedia> = vec![];
+        for chunk in ids.chunks(MAX_BOUND_IDS) {
+            similar.extend(
+                media_table
+                    .filter(id.eq_any(chunk))
+                    .load::<MediaFile>(conn)?
+                    .into_iter()
+                    .map(|file| SimilarMedia {
+                        distance: distances[&file.id],
+                        media: file,
+                    }),
+            );
+        }
+
+        similar.sort_by_key(|s| (s.distance, s.media.id));
+        Ok(similar)
     }
 
     /// Inserts a tag for the media id with the provided tag id.
@@ -456,38 +1375,36 @@ impl Media {
         }
     }
 
-    /// List media starting from tags
+    /// List media starting from tags, i.e. the media tagged with all of
+    /// them.
+    ///
+    /// This is a convenient function for [`Media::list_media_from_tag_filter`]
+    /// and thus returns the same errors.
     pub fn list_media_from_tags(
         &self,
         tags: impl IntoIterator<Item = i32>,
     ) -> Result<Vec<MediaFile>, Error> {
-        let tag_ids = {
-            let mut t = tags.into_iter().collect::<Vec<i32>>();
-
-            t.sort_unstable();
-            t.dedup();
-            t
-        };
+        self.list_media_from_tag_filter(&TagFilter::new().with_all_of(tags))
+    }
 
-        if tag_ids.is_empty() {
+    /// Lists the media selected by the provided tag filter, e.g. tagged with
+    /// `beach`, with at least 2 of `sea`, `sun` and `sand`, and not with
+    /// `blurry`, ordered by ID.
+    ///
+    /// It returns an error in case the filter has no tags, its threshold is
+    /// invalid or if there was an error on the database.
+    pub fn list_media_from_tag_filter(&self, filter: &TagFilter) -> Result<Vec<MediaFile>, Error> {
+        if filter.is_empty() {
             return Err(Error::NoTagsProvided);
         }
-
-        use database::schema::media_tags::dsl::{media_id, media_tags as mt_table, tag_id as tid};
-        let conn = &mut self.connection.establish_connection()?;
-
-        let img_ids = mt_table
-            .select(media_id)
-            .filter(tid.eq_any(&tag_ids))
-            .group_by(media_id)
-            .having(count_distinct(tid).eq(tag_ids.len() as i64))
-            .distinct()
-            .load::<i64>(conn)?;
+        filter.validate()?;
 
         use database::schema::media::dsl::id;
-        media_table
-            .filter(id.eq_any(img_ids))
+        let conn = &mut self.connection.establish_connection()?;
+        filter
+            .apply(media_table.into_boxed())
+            .order(id.asc())
             .load(conn)
-            .map_err(|err| Error::DatabaseError(err))
+            .map_err(Error::DatabaseError)
     }
 }
@@ -0,0 +1,184 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Seek},
+    path::Path,
+};
+
+use exif::{In, Reader, Tag, Value};
+use thiserror::Error;
+
+use crate::data::media_metadata::MediaMetadata;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The file could not be opened or read.
+    #[error("cannot read file: {0}")]
+    IOError(#[from] std::io::Error),
+    /// The file does not contain any EXIF data.
+    #[error("no EXIF data found")]
+    NotFound,
+    /// The format of the file is not supported or the EXIF data is corrupted.
+    #[error("cannot parse EXIF data: {0}")]
+    ParseError(exif::Error),
+}
+
+impl From<exif::Error> for Error {
+    fn from(value: exif::Error) -> Self {
+        match value {
+            exif::Error::NotFound(_) => Error::NotFound,
+            exif::Error::Io(err) => Error::IOError(err),
+            err => Error::ParseError(err),
+        }
+    }
+}
+
+/// The EXIF data read from a file.
+#[derive(Debug)]
+pub struct ExifData {
+    /// The metadata, with `media_id` left to `0`.
+    pub metadata: MediaMetadata,
+    /// The width and height as stored in the file, i.e. *before* applying
+    /// the orientation, if present.
+    pub dimensions: Option<(u32, u32)>,
+}
+
+impl ExifData {
+    /// Returns the width and height as they should be displayed, i.e.
+    /// swapped in case the orientation rotates the image by 90 or 270
+    /// degrees.
+    pub fn oriented_dimensions(&self) -> Option<(u32, u32)> {
+        self.dimensions.map(|(width, height)| {
+            if is_rotated(self.metadata.orientation) {
+                (height, width)
+            } else {
+                (width, height)
+            }
+        })
+    }
+}
+
+/// Returns whether the provided EXIF orientation rotates the image by 90 or
+/// 270 degrees, meaning that width and height must be swapped.
+pub fn is_rotated(orientation: Option<i16>) -> bool {
+    matches!(orientation, Some(5..=8))
+}
+
+/// Reads the EXIF data from a JPEG, TIFF, HEIF, PNG or WebP file.
+///
+/// It returns an error in case the file cannot be read, it does not contain
+/// EXIF data or the data cannot be parsed.
+pub fn read_exif(path: impl AsRef<Path>) -> Result<ExifData, Error> {
+    read_from(&mut BufReader::new(File::open(path)?))
+}
+
+/// Same as [`read_exif`], but for content already in memory, e.g. an entry
+/// read from an archive.
+pub fn read_exif_from_bytes(content: &[u8]) -> Result<ExifData, Error> {
+    read_from(&mut Cursor::new(content))
+}
+
+fn read_from(reader: &mut (impl BufRead + Seek)) -> Result<ExifData, Error> {
+    let exif = Reader::new().read_from_container(reader)?;
+
+    let metadata = MediaMetadata {
+        media_id: 0,
+        captured_at: [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+            .into_iter()
+            .find_map(|tag| datetime_field(&exif, tag)),
+        camera_make: string_field(&exif, Tag::Make),
+        camera_model: string_field(&exif, Tag::Model),
+        lens: string_field(&exif, Tag::LensModel),
+        exposure_time: exif.get_field(Tag::ExposureTime, In::PRIMARY).and_then(
+            |field| match &field.value {
+                Value::Rational(vals) if !vals.is_empty() && vals[0].denom != 0 => {
+                    Some(match vals[0].num {
+                        1 => format!("1/{}", vals[0].denom),
+                        _ => format!("{}", vals[0].to_f64()),
+                    })
+                }
+                _ => None,
+            },
+        ),
+        f_number: rational_field(&exif, Tag::FNumber),
+        iso: uint_field(&exif, Tag::PhotographicSensitivity).and_then(|v| i32::try_from(v).ok()),
+        focal_length: rational_field(&exif, Tag::FocalLength),
+        orientation: uint_field(&exif, Tag::Orientation)
+            .filter(|v| (1..=8).contains(v))
+            .map(|v| v as i16),
+        gps_latitude: coordinate_field(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S'),
+        gps_longitude: coordinate_field(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W'),
+        gps_altitude: rational_field(&exif, Tag::GPSAltitude).map(|alt| {
+            match uint_field(&exif, Tag::GPSAltitudeRef) {
+                Some(1) => -alt,
+                _ => alt,
+            }
+        }),
+    };
+
+    let dimensions = match (
+        uint_field(&exif, Tag::PixelXDimension).or(uint_field(&exif, Tag::ImageWidth)),
+        uint_field(&exif, Tag::PixelYDimension).or(uint_field(&exif, Tag::ImageLength)),
+    ) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => Some((width, height)),
+        _ => None,
+    };
+
+    Ok(ExifData {
+        metadata,
+        dimensions,
+    })
+}
+
+fn string_field(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(vals) => vals
+            .first()
+            .map(|val| String::from_utf8_lossy(val).trim().to_string())
+            .filter(|val| !val.is_empty()),
+        _ => None,
+    }
+}
+
+fn uint_field(exif: &exif::Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+fn rational_field(exif: &exif::Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(vals) => vals
+            .first()
+            .filter(|val| val.denom != 0)
+            .map(|val| val.to_f64()),
+        _ => None,
+    }
+}
+
+fn datetime_field(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(vals) => vals
+            .first()
+            .and_then(|val| exif::DateTime::from_ascii(val).ok())
+            .map(|dt| dt.to_string()),
+        _ => None,
+    }
+}
+
+/// Converts a GPS coordinate, expressed as degrees, minutes and seconds, to
+/// decimal degrees. The value is negative if the reference is `negative_ref`,
+/// e.g. `S` for latitudes.
+fn coordinate_field(exif: &exif::Exif, tag: Tag, ref_tag: Tag, negative_ref: u8) -> Option<f64> {
+    let dms = match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(vals) if vals.len() >= 3 && vals.iter().all(|v| v.denom != 0) => {
+            vals[0].to_f64() + vals[1].to_f64() / 60.0 + vals[2].to_f64() / 3600.0
+        }
+        _ => return None,
+    };
+
+    let is_negative = match exif.get_field(ref_tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(vals)) => vals.first().and_then(|v| v.first()) == Some(&negative_ref),
+        _ => false,
+    };
+
+    Some(if is_negative { -dms } else { dms })
+}
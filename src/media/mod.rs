@@ -1,2 +1,21 @@
+pub mod archive;
+pub mod audit;
 pub mod base_paths;
+pub mod collections;
+pub mod files;
+pub mod full_text;
+pub mod hash;
 pub mod media;
+pub mod metadata;
+pub(crate) mod naming;
+pub mod organizer;
+pub mod query;
+pub mod recommendations;
+pub mod saved_searches;
+pub mod scanner;
+pub mod scrub;
+pub mod search;
+pub mod statistics;
+pub mod thumbnails;
+#[cfg(feature = "watcher")]
+pub mod watcher;
@@ -0,0 +1,44 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The maximum length of names, e.g. of collections and saved searches, in
+/// characters.
+const MAX_NAME_LENGTH: usize = 50;
+/// The maximum length of descriptions, in characters.
+const MAX_DESCRIPTION_LENGTH: usize = 300;
+
+/// Why a name or a description is invalid. Each service converts it into
+/// the matching variant of its own error.
+#[derive(Debug)]
+pub enum Invalid {
+    /// The name is empty.
+    Name,
+    /// The name is longer than [`MAX_NAME_LENGTH`] characters.
+    NameTooLong,
+    /// The description is longer than [`MAX_DESCRIPTION_LENGTH`] characters.
+    DescriptionTooLong,
+}
+
+/// Returns the provided name without leading and trailing whitespace, or an
+/// error in case it is then empty or too long.
+pub fn name(name: &str) -> Result<String, Invalid> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Invalid::Name);
+    }
+    if name.graphemes(true).count() > MAX_NAME_LENGTH {
+        return Err(Invalid::NameTooLong);
+    }
+
+    Ok(name.to_string())
+}
+
+/// Returns the provided description without leading and trailing
+/// whitespace, or an error in case it is then too long.
+pub fn description(description: &str) -> Result<String, Invalid> {
+    let description = description.trim();
+    if description.graphemes(true).count() > MAX_DESCRIPTION_LENGTH {
+        return Err(Invalid::DescriptionTooLong);
+    }
+
+    Ok(description.to_string())
+}
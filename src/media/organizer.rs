@@ -0,0 +1,756 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use diesel::{ExpressionMethods, QueryDsl, Queryable, RunQueryDsl};
+use serde::Serialize;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    data::media_file::{MediaFile, MediaType},
+    database::{
+        self,
+        connection::DatabaseConnection,
+        schema::{
+            media::dsl::media as media_table, media_metadata, media_tags,
+            organizer_moves::dsl::organizer_moves as moves_table, tag_categories, tags,
+        },
+    },
+    media::{
+        base_paths::{self, base_paths},
+        files::{self, files},
+        media::MAX_BOUND_IDS,
+    },
+};
+
+/// Organizer moves the files of a base path to the locations given by a
+/// path template, e.g. `{year}/{month}/{category:Event}/{filename}`.
+///
+/// Organizing happens in two steps: [`Organizer::plan`] evaluates the
+/// template for each media and reports the problems without touching
+/// anything, then [`Organizer::execute`] performs the moves. Each execution
+/// is recorded so that it can be reverted with [`Organizer::undo`].
+///
+/// The supported placeholders are:
+/// - `{year}`, `{month}` and `{day}`: the capture date or, if unknown, the
+///   modification date of the file.
+/// - `{category:Name}`: the tag of the media in the category `Name`.
+/// - `{mark}`, `{type}`, `{width}` and `{height}`.
+/// - `{filename}`, `{stem}` and `{ext}`: the current file name, without
+///   and only its extension.
+///
+/// A default value can be provided for when a placeholder has no value,
+/// e.g. `{category:Event|Unsorted}`.
+pub struct Organizer {
+    connection: DatabaseConnection,
+}
+
+/// This returns a new instance of the `Organizer` struct.
+pub fn organizer(connection: DatabaseConnection) -> Organizer {
+    Organizer { connection }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The base path could not be retrieved.
+    #[error("base path error: {0}")]
+    BasePathsError(#[from] base_paths::Error),
+    /// The template is invalid, e.g. a placeholder is unknown or not closed.
+    #[error("invalid template: {0}")]
+    InvalidTemplate(String),
+    /// No execution was found with the provided ID.
+    #[error("not found")]
+    NotFound,
+}
+
+/// The moves needed to organize a base path, along with the media that
+/// cannot be moved.
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    pub base_path_id: i32,
+    /// The moves that can be performed.
+    pub moves: Vec<PlannedMove>,
+    /// How many media are already at the right location.
+    pub unchanged: usize,
+    /// The media for which the template could not be evaluated.
+    pub conflicts: Vec<Conflict>,
+    /// The media that would be moved where a file already is, or where other
+    /// media would be moved too.
+    pub collisions: Vec<Collision>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedMove {
+    pub media_id: i64,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    pub media_id: i64,
+    pub reason: ConflictReason,
+}
+
+#[derive(Debug, Serialize)]
+pub enum ConflictReason {
+    /// The placeholder has no value for the media and no default.
+    MissingValue(String),
+    /// The media has more than one tag in the category, listed here.
+    AmbiguousCategory { category: String, tags: Vec<String> },
+    /// The evaluated path is not a valid relative path.
+    InvalidPath(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct Collision {
+    pub to: String,
+    pub media_ids: Vec<i64>,
+    /// Whether a file or another media is already at `to`.
+    pub exists: bool,
+}
+
+/// The result of executing a plan or undoing an execution.
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    /// The ID of the execution, to be used with [`Organizer::undo`].
+    pub run_id: String,
+    /// The IDs of the media that have been moved.
+    pub moved: Vec<i64>,
+    pub failed: Vec<FailedMove>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedMove {
+    pub media_id: i64,
+    pub error: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder {
+        placeholder: Placeholder,
+        default: Option<String>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum Placeholder {
+    Year,
+    Month,
+    Day,
+    Category(String),
+    Mark,
+    Type,
+    Width,
+    Height,
+    FileName,
+    Stem,
+    Extension,
+}
+
+/// What the template can use about a media, besides the media itself.
+#[derive(Default)]
+struct MediaInfo {
+    /// `(year, month, day)`.
+    date: Option<(i64, u32, u32)>,
+    /// Tag names by lowercase category name.
+    tags: HashMap<String, Vec<String>>,
+}
+
+#[derive(Queryable)]
+struct OrganizerMove {
+    _id: i32,
+    _run_id: String,
+    media_id: i64,
+    base_path_id: i32,
+    from_path: String,
+    to_path: String,
+    _undone: bool,
+}
+
+impl Organizer {
+    /// Evaluates the template for all the media of the base path with the
+    /// provided ID, without moving anything. Media stored inside archives
+    /// are left out, since they have no file of their own.
+    ///
+    /// It returns an error in case the base path does not exist, the
+    /// template is invalid or if there was an error on the database.
+    pub fn plan(&self, base_path_id: i32, template: impl AsRef<str>) -> Result<Plan, Error> {
+        let segments = parse_template(template.as_ref())?;
+        let bp = base_paths(self.connection.clone()).get(base_path_id)?;
+
+        let files = {
+            use database::schema::media::dsl::{archive_entry, base_path_id as bp_id, id};
+            let conn = &mut self.connection.establish_connection()?;
+            media_table
+                .filter(bp_id.eq(base_path_id))
+                .filter(archive_entry.is_null())
+                .order(id.asc())
+                .load::<MediaFile>(conn)?
+        };
+        let mut infos = self.load_infos(&files)?;
+
+        let mut plan = Plan {
+            base_path_id,
+            moves: vec![],
+            unchanged: 0,
+            conflicts: vec![],
+            collisions: vec![],
+        };
+
+        let mut targets: HashMap<String, Vec<&MediaFile>> = HashMap::new();
+        for file in &files {
+            let info = infos.remove(&file.id).unwrap_or_default();
+            match render(&segments, file, &info) {
+                Err(reason) => plan.conflicts.push(Conflict {
+                    media_id: file.id,
+                    reason,
+                }),
+                Ok(to) if to == file.relative_path => plan.unchanged += 1,
+                Ok(to) => targets.entry(to).or_default().push(file),
+            }
+        }
+
+        let mut targets: Vec<(String, Vec<&MediaFile>)> = targets.into_iter().collect();
+        targets.sort_by_key(|(_, targeted)| targeted[0].id);
+        for (to, targeted) in targets {
+            let exists = files.iter().any(|file| file.relative_path == to)
+                || Path::new(&bp.base_path)
+                    .join(&to)
+                    .symlink_metadata()
+                    .is_ok();
+
+            if targeted.len() > 1 || exists {
+                plan.collisions.push(Collision {
+                    to,
+                    media_ids: targeted.iter().map(|file| file.id).collect(),
+                    exists,
+                });
+                continue;
+            }
+
+            plan.moves.push(PlannedMove {
+                media_id: targeted[0].id,
+                from: targeted[0].relative_path.clone(),
+                to,
+            });
+        }
+
+        Ok(plan)
+    }
+
+    /// Performs the moves of the provided plan, skipping the media that have
+    /// been moved since the plan was made. Empty directories left behind
+    /// are removed.
+    ///
+    /// A move that fails does not stop the others: it is reported instead.
+    pub fn execute(&self, plan: &Plan) -> Result<RunReport, Error> {
+        let bp = base_paths(self.connection.clone()).get(plan.base_path_id)?;
+        let files = files(self.connection.clone());
+        let mut report = RunReport {
+            run_id: Uuid::new_v4().to_string(),
+            moved: vec![],
+            failed: vec![],
+        };
+
+        for planned in &plan.moves {
+            let current = {
+                use database::schema::media::dsl::id;
+                let conn = &mut self.connection.establish_connection()?;
+                media_table
+                    .filter(id.eq(planned.media_id))
+                    .first::<MediaFile>(conn)
+            };
+
+            let result = match current {
+                Ok(file)
+                    if file.base_path_id == plan.base_path_id
+                        && file.relative_path == planned.from =>
+                {
+                    files.move_to(planned.media_id, plan.base_path_id, &planned.to)
+                }
+                Ok(_) | Err(diesel::NotFound) => {
+                    report.failed.push(FailedMove {
+                        media_id: planned.media_id,
+                        error: "media changed since the plan was made".into(),
+                    });
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            if let Err(err) = result {
+                report.failed.push(FailedMove {
+                    media_id: planned.media_id,
+                    error: err.to_string(),
+                });
+                continue;
+            }
+
+            use database::schema::organizer_moves::dsl as moves;
+            let conn = &mut self.connection.establish_connection()?;
+            diesel::insert_into(moves_table)
+                .values((
+                    moves::run_id.eq(&report.run_id),
+                    moves::media_id.eq(planned.media_id),
+                    moves::base_path_id.eq(plan.base_path_id),
+                    moves::from_path.eq(&planned.from),
+                    moves::to_path.eq(&planned.to),
+                ))
+                .execute(conn)?;
+
+            report.moved.push(planned.media_id);
+            remove_empty_parents(Path::new(&bp.base_path), &planned.from);
+        }
+
+        Ok(report)
+    }
+
+    /// Moves back the media moved by the execution with the provided ID, in
+    /// reverse order. Media that have been moved or deleted since are
+    /// reported as failed.
+    ///
+    /// It returns an error in case the execution was not found, has already
+    /// been undone, or if there was an error on the database.
+    pub fn undo(&self, run_id: impl AsRef<str>) -> Result<RunReport, Error> {
+        let to_undo = {
+            use database::schema::organizer_moves::dsl::{id, run_id as r_id, undone};
+            let conn = &mut self.connection.establish_connection()?;
+            moves_table
+                .filter(r_id.eq(run_id.as_ref()))
+                .filter(undone.eq(false))
+                .order(id.desc())
+                .load::<OrganizerMove>(conn)?
+        };
+
+        if to_undo.is_empty() {
+            return Err(Error::NotFound);
+        }
+
+        let files = files(self.connection.clone());
+        let mut report = RunReport {
+            run_id: run_id.as_ref().to_string(),
+            moved: vec![],
+            failed: vec![],
+        };
+
+        for planned in to_undo {
+            let current = {
+                use database::schema::media::dsl::id;
+                let conn = &mut self.connection.establish_connection()?;
+                media_table
+                    .filter(id.eq(planned.media_id))
+                    .first::<MediaFile>(conn)
+            };
+
+            let current = match current {
+                Ok(file)
+                    if file.base_path_id == planned.base_path_id
+                        && file.relative_path == planned.to_path =>
+                {
+                    files
+                        .move_to(planned.media_id, planned.base_path_id, &planned.from_path)
+                        .and_then(|file| {
+                            Ok(base_paths(self.connection.clone()).get(file.base_path_id)?)
+                        })
+                }
+                Ok(_) | Err(diesel::NotFound) => {
+                    report.failed.push(FailedMove {
+                        media_id: planned.media_id,
+                        error: "media changed since it was moved".into(),
+                    });
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            match current {
+                Err(err) => report.failed.push(FailedMove {
+                    media_id: planned.media_id,
+                    error: err.to_string(),
+                }),
+                Ok(bp) => {
+                    use database::schema::organizer_moves::dsl::{
+                        media_id, run_id as r_id, undone,
+                    };
+                    let conn = &mut self.connection.establish_connection()?;
+                    diesel::update(
+                        moves_table
+                            .filter(r_id.eq(run_id.as_ref()))
+                            .filter(media_id.eq(planned.media_id)),
+                    )
+                    .set(undone.eq(true))
+                    .execute(conn)?;
+
+                    report.moved.push(planned.media_id);
+                    remove_empty_parents(Path::new(&bp.base_path), &planned.to_path);
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Loads the capture dates and the tags of the provided media.
+    fn load_infos(&self, files: &[MediaFile]) -> Result<HashMap<i64, MediaInfo>, Error> {
+        let ids: Vec<i64> = files.iter().map(|file| file.id).collect();
+        let conn = &mut self.connection.establish_connection()?;
+
+        let mut captured: HashMap<i64, String> = HashMap::new();
+        let mut tagged: Vec<(i64, String, String)> = vec![];
+        for chunk in ids.chunks(MAX_BOUND_IDS) {
+            captured.extend(
+                media_metadata::table
+                    .select((media_metadata::media_id, media_metadata::captured_at))
+                    .filter(media_metadata::media_id.eq_any(chunk))
+                    .load::<(i64, Option<String>)>(conn)?
+                    .into_iter()
+                    .filter_map(|(id, captured_at)| Some((id, captured_at?))),
+            );
+            tagged.extend(
+                media_tags::table
+                    .inner_join(tags::table.inner_join(tag_categories::table))
+                    .select((media_tags::media_id, tag_categories::name, tags::name))
+                    .filter(media_tags::media_id.eq_any(chunk))
+                    .order(tags::name.asc())
+                    .load::<(i64, String, String)>(conn)?,
+            );
+        }
+
+        let mut infos: HashMap<i64, MediaInfo> = HashMap::new();
+        for file in files {
+            let date = captured
+                .get(&file.id)
+                .and_then(|captured_at| parse_date(captured_at))
+                .or_else(|| {
+                    file.modified
+                        .map(|modified| civil_from_days(modified.div_euclid(86400)))
+                });
+            infos.entry(file.id).or_default().date = date;
+        }
+        for (id, category, tag) in tagged {
+            infos
+                .entry(id)
+                .or_default()
+                .tags
+                .entry(category.to_lowercase())
+                .or_default()
+                .push(tag);
+        }
+
+        Ok(infos)
+    }
+}
+
+fn parse_template(template: &str) -> Result<Vec<Segment>, Error> {
+    let mut segments = vec![];
+    let trimmed = template.trim_end();
+    let mut rest = trimmed.trim_start();
+    while !rest.is_empty() {
+        let Some(start) = rest.find('{') else {
+            segments.push(Segment::Literal(rest.to_string()));
+            break;
+        };
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+
+        let Some(length) = rest[start..].find('}') else {
+            // The position is in characters, as seen by the user.
+            let offset = trimmed.len() - rest.len() + start;
+            return Err(Error::InvalidTemplate(format!(
+                "placeholder at {} is not closed",
+                template[..offset].chars().count()
+            )));
+        };
+
+        let content = &rest[start + 1..start + length];
+        let (name, default) = match content.split_once('|') {
+            Some((name, default)) => (name, Some(default.to_string())),
+            None => (content, None),
+        };
+        let placeholder = match name.trim().split_once(':') {
+            Some(("category", category)) if !category.trim().is_empty() => {
+                Placeholder::Category(category.trim().to_lowercase())
+            }
+            None => match name.trim() {
+                "year" => Placeholder::Year,
+                "month" => Placeholder::Month,
+                "day" => Placeholder::Day,
+                "mark" => Placeholder::Mark,
+                "type" => Placeholder::Type,
+                "width" => Placeholder::Width,
+                "height" => Placeholder::Height,
+                "filename" => Placeholder::FileName,
+                "stem" => Placeholder::Stem,
+                "ext" => Placeholder::Extension,
+                other => {
+                    return Err(Error::InvalidTemplate(format!(
+                        "unknown placeholder {{{}}}",
+                        other
+                    )))
+                }
+            },
+            _ => {
+                return Err(Error::InvalidTemplate(format!(
+                    "unknown placeholder {{{}}}",
+                    name
+                )))
+            }
+        };
+
+        segments.push(Segment::Placeholder {
+            placeholder,
+            default,
+        });
+        rest = &rest[start + length + 1..];
+    }
+
+    Ok(segments)
+}
+
+/// Evaluates the template for a media, returning its new relative path.
+fn render(
+    segments: &[Segment],
+    file: &MediaFile,
+    info: &MediaInfo,
+) -> Result<String, ConflictReason> {
+    let path = Path::new(&file.relative_path);
+    let mut rendered = String::new();
+    for segment in segments {
+        let (placeholder, default) = match segment {
+            Segment::Literal(literal) => {
+                rendered.push_str(literal);
+                continue;
+            }
+            Segment::Placeholder {
+                placeholder,
+                default,
+            } => (placeholder, default),
+        };
+
+        let value = match placeholder {
+            Placeholder::Year => info.date.map(|(year, _, _)| format!("{:04}", year)),
+            Placeholder::Month => info.date.map(|(_, month, _)| format!("{:02}", month)),
+            Placeholder::Day => info.date.map(|(_, _, day)| format!("{:02}", day)),
+            Placeholder::Category(category) => match info.tags.get(category) {
+                Some(tags) if tags.len() > 1 => {
+                    return Err(ConflictReason::AmbiguousCategory {
+                        category: category.clone(),
+                        tags: tags.clone(),
+                    })
+                }
+                Some(tags) => tags.first().cloned(),
+                None => None,
+            },
+            Placeholder::Mark => file.mark.map(|mark| mark.to_string()),
+            Placeholder::Type => match file.media_type {
+                MediaType::Image => Some("image".to_string()),
+                MediaType::Video => Some("video".to_string()),
+                MediaType::Sound => Some("sound".to_string()),
+                MediaType::Unknown => None,
+            },
+            Placeholder::Width => file.width.map(|width| width.to_string()),
+            Placeholder::Height => file.height.map(|height| height.to_string()),
+            Placeholder::FileName => path.file_name().map(|name| name.to_string_lossy().into()),
+            Placeholder::Stem => path.file_stem().map(|stem| stem.to_string_lossy().into()),
+            Placeholder::Extension => path.extension().map(|ext| ext.to_string_lossy().into()),
+        };
+
+        match value.or_else(|| default.clone()) {
+            Some(value) => rendered.push_str(&sanitize(&value)),
+            None => {
+                return Err(ConflictReason::MissingValue(
+                    match placeholder {
+                        Placeholder::Year => "year",
+                        Placeholder::Month => "month",
+                        Placeholder::Day => "day",
+                        Placeholder::Category(category) => category,
+                        Placeholder::Mark => "mark",
+                        Placeholder::Type => "type",
+                        Placeholder::Width => "width",
+                        Placeholder::Height => "height",
+                        Placeholder::FileName => "filename",
+                        Placeholder::Stem => "stem",
+                        Placeholder::Extension => "ext",
+                    }
+                    .to_string(),
+                ))
+            }
+        }
+    }
+
+    files::validate_relative_path(&rendered).map_err(|_| ConflictReason::InvalidPath(rendered))
+}
+
+/// Makes a value usable as a single path component.
+fn sanitize(value: &str) -> String {
+    value
+        .trim()
+        .replace(['/', '\\'], "_")
+        .trim_start_matches('.')
+        .to_string()
+}
+
+/// Parses the date of a capture time formatted as `YYYY-MM-DD HH:MM:SS`.
+fn parse_date(captured_at: &str) -> Option<(i64, u32, u32)> {
+    let mut parts = captured_at.get(..10)?.split('-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts
+        .next()?
+        .parse()
+        .ok()
+        .filter(|m| (1..=12).contains(m))?;
+    let day = parts
+        .next()?
+        .parse()
+        .ok()
+        .filter(|d| (1..=31).contains(d))?;
+
+    Some((year, month, day))
+}
+
+/// Converts a number of days since the UNIX epoch to a `(year, month, day)`
+/// date in the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Removes the directories containing `relative_path` that are empty, up to
+/// the base path.
+fn remove_empty_parents(base_path: &Path, relative_path: &str) {
+    let mut directory: Option<PathBuf> = Path::new(relative_path).parent().map(Path::to_path_buf);
+    while let Some(dir) = directory.filter(|dir| !dir.as_os_str().is_empty()) {
+        if fs::remove_dir(base_path.join(&dir)).is_err() {
+            break;
+        }
+        directory = dir.parent().map(Path::to_path_buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(literal: &str) -> Segment {
+        Segment::Literal(literal.to_string())
+    }
+
+    fn placeholder(placeholder: Placeholder, default: Option<&str>) -> Segment {
+        Segment::Placeholder {
+            placeholder,
+            default: default.map(str::to_string),
+        }
+    }
+
+    fn template_error(template: &str) -> String {
+        match parse_template(template) {
+            Err(Error::InvalidTemplate(message)) => message,
+            _ => panic!("{template} should be invalid"),
+        }
+    }
+
+    #[test]
+    fn templates_mix_literals_and_placeholders() {
+        assert_eq!(
+            parse_template(" {year}/{month}-{ category:Event |Unsorted}/{filename}").unwrap(),
+            vec![
+                placeholder(Placeholder::Year, None),
+                literal("/"),
+                placeholder(Placeholder::Month, None),
+                literal("-"),
+                placeholder(Placeholder::Category("event".to_string()), Some("Unsorted")),
+                literal("/"),
+                placeholder(Placeholder::FileName, None),
+            ]
+        );
+        assert_eq!(
+            parse_template("photos/{stem}.{ext}").unwrap(),
+            vec![
+                literal("photos/"),
+                placeholder(Placeholder::Stem, None),
+                literal("."),
+                placeholder(Placeholder::Extension, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert_eq!(template_error("{name}"), "unknown placeholder {name}");
+        assert_eq!(
+            template_error("{category:}"),
+            "unknown placeholder {category:}"
+        );
+        assert_eq!(template_error("{size:x}"), "unknown placeholder {size:x}");
+        assert_eq!(
+            template_error("{year}/{month"),
+            "placeholder at 7 is not closed"
+        );
+    }
+
+    #[test]
+    fn template_positions_count_characters() {
+        assert_eq!(
+            template_error("  été/{year"),
+            "placeholder at 6 is not closed"
+        );
+        assert_eq!(
+            template_error("{category:Évènement}/{day "),
+            "placeholder at 21 is not closed"
+        );
+    }
+
+    #[test]
+    fn sanitize_keeps_a_single_component() {
+        assert_eq!(sanitize(" New York "), "New York");
+        assert_eq!(sanitize("AC/DC"), "AC_DC");
+        assert_eq!(sanitize("a\\b"), "a_b");
+        assert_eq!(sanitize("../secret"), "_secret");
+        assert_eq!(sanitize(".hidden"), "hidden");
+    }
+
+    #[test]
+    fn dates_are_parsed_from_capture_times() {
+        assert_eq!(parse_date("2024-02-29 13:45:00"), Some((2024, 2, 29)));
+        assert_eq!(parse_date("1999-12-31"), Some((1999, 12, 31)));
+        assert_eq!(parse_date("2024-13-01 00:00:00"), None);
+        assert_eq!(parse_date("2024-00-01 00:00:00"), None);
+        assert_eq!(parse_date("2024-01-32 00:00:00"), None);
+        assert_eq!(parse_date("2024:01:01 00:00:00"), None);
+        assert_eq!(parse_date("2024-1-1"), None);
+        assert_eq!(parse_date("été-01-01 00:00:00"), None);
+    }
+
+    #[test]
+    fn days_are_converted_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-719_468), (0, 3, 1));
+    }
+}
@@ -0,0 +1,643 @@
+use diesel::{
+    dsl::{count_distinct, not, sql},
+    sql_types::{BigInt, Bool, Double, Text},
+    sqlite::Sqlite,
+    BoolExpressionMethods, BoxableExpression, EscapeExpressionMethods, ExpressionMethods, QueryDsl,
+    TextExpressionMethods,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::media_file::{MediaFile, MediaType},
+    database::schema::media::{self, dsl::media as media_table},
+    media::{media::Error, search::Resolved},
+};
+
+type Condition = Box<dyn BoxableExpression<media::table, Sqlite, SqlType = Bool>>;
+
+/// A key the media can be sorted by. Media without a mark, dimensions or
+/// modification time come first in ascending order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortKey {
+    Id,
+    RelativePath,
+    Size,
+    Mark,
+    Width,
+    Height,
+    Modified,
+    MediaType,
+}
+
+impl SortKey {
+    fn sql(&self) -> &'static str {
+        match self {
+            SortKey::Id => "media.id",
+            SortKey::RelativePath => "media.relative_path",
+            SortKey::Size => "media.size",
+            SortKey::Mark => "COALESCE(media.mark, 0)",
+            SortKey::Width => "COALESCE(media.width, 0)",
+            SortKey::Height => "COALESCE(media.height, 0)",
+            SortKey::Modified => "COALESCE(media.modified, 0)",
+            SortKey::MediaType => "media.media_type",
+        }
+    }
+
+    fn value(&self, file: &MediaFile) -> SortValue {
+        match self {
+            SortKey::Id => SortValue::Int(file.id),
+            SortKey::RelativePath => SortValue::Text(file.relative_path.clone()),
+            SortKey::Size => SortValue::Float(file.size),
+            SortKey::Mark => SortValue::Int(file.mark.unwrap_or_default().into()),
+            SortKey::Width => SortValue::Int(file.width.unwrap_or_default().into()),
+            SortKey::Height => SortValue::Int(file.height.unwrap_or_default().into()),
+            SortKey::Modified => SortValue::Int(file.modified.unwrap_or_default()),
+            SortKey::MediaType => SortValue::Text(match file.media_type {
+                MediaType::Image => "image".into(),
+                MediaType::Video => "video".into(),
+                MediaType::Sound => "sound".into(),
+                MediaType::Unknown => String::new(),
+            }),
+        }
+    }
+
+    fn matches(&self, value: &SortValue) -> bool {
+        matches!(
+            (self, value),
+            (
+                SortKey::Id | SortKey::Mark | SortKey::Width | SortKey::Height | SortKey::Modified,
+                SortValue::Int(_)
+            ) | (SortKey::Size, SortValue::Float(_))
+                | (
+                    SortKey::RelativePath | SortKey::MediaType,
+                    SortValue::Text(_)
+                )
+        )
+    }
+}
+
+/// The direction of a sort key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum SortValue {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// The position after the last media of a page, used to get the next page
+/// with [`MediaQuery::with_cursor`].
+///
+/// Unlike offsets, cursors keep working when media are added or removed
+/// between pages. A cursor is only valid for a query with the same sort
+/// keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    values: Vec<SortValue>,
+}
+
+/// A page of media returned by [`Media::query`](crate::media::media::Media::query).
+#[derive(Debug, Serialize)]
+pub struct MediaPage {
+    /// The media of the page, in order.
+    pub media: Vec<MediaFile>,
+    /// How many media match the filters, regardless of pagination.
+    pub total: usize,
+    /// The cursor to get the next page, if the page is full. The next page
+    /// may be empty.
+    pub next: Option<Cursor>,
+}
+
+/// TagFilter selects media by their tags: all the sets must match.
+///
+/// It is used by [`Media::list_media_from_tag_filter`] and
+/// [`MediaQuery::with_tag_filter`], and runs as part of the query listing
+/// the media, whatever the number of tags.
+///
+/// [`Media::list_media_from_tag_filter`]: crate::media::media::Media::list_media_from_tag_filter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TagFilter {
+    all_of: Vec<i32>,
+    any_of: Vec<i32>,
+    none_of: Vec<i32>,
+    at_least: Option<usize>,
+}
+
+impl TagFilter {
+    /// Returns a filter that matches all the media.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only matches media tagged with all of the provided tags.
+    pub fn with_all_of(mut self, tag_ids: impl IntoIterator<Item = i32>) -> Self {
+        self.all_of.extend(tag_ids);
+        self
+    }
+
+    /// Only matches media tagged with at least one of the provided tags, or
+    /// with as many as set with [`TagFilter::with_at_least`].
+    pub fn with_any_of(mut self, tag_ids: impl IntoIterator<Item = i32>) -> Self {
+        self.any_of.extend(tag_ids);
+        self
+    }
+
+    /// Only matches media tagged with none of the provided tags.
+    pub fn with_none_of(mut self, tag_ids: impl IntoIterator<Item = i32>) -> Self {
+        self.none_of.extend(tag_ids);
+        self
+    }
+
+    /// Only matches media tagged with at least `count` of the tags added
+    /// with [`TagFilter::with_any_of`], e.g. 2 of `beach`, `sea` and `sun`.
+    pub fn with_at_least(mut self, count: usize) -> Self {
+        self.at_least = Some(count);
+        self
+    }
+
+    /// Returns whether the filter matches all the media.
+    pub fn is_empty(&self) -> bool {
+        self.all_of.is_empty() && self.any_of.is_empty() && self.none_of.is_empty()
+    }
+
+    /// Checks that the threshold is between 1 and the number of distinct
+    /// tags added with [`TagFilter::with_any_of`].
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        match self.at_least {
+            Some(count) if count == 0 || count > distinct(&self.any_of).len() => {
+                Err(Error::InvalidTagThreshold)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the IDs of all the tags of the filter.
+    pub(crate) fn tag_ids(&self) -> Vec<i32> {
+        distinct(&[&self.all_of[..], &self.any_of, &self.none_of].concat())
+    }
+
+    /// Replaces the IDs of the tags, e.g. to use the filter on another
+    /// library.
+    pub(crate) fn map_tag_ids(mut self, map: impl Fn(i32) -> i32) -> Self {
+        for tag_ids in [&mut self.all_of, &mut self.any_of, &mut self.none_of] {
+            tag_ids.iter_mut().for_each(|tag_id| *tag_id = map(*tag_id));
+        }
+        self
+    }
+
+    /// Restricts the query to the media matching the filter.
+    pub(crate) fn apply<'a>(
+        &self,
+        mut query: media::BoxedQuery<'a, Sqlite>,
+    ) -> media::BoxedQuery<'a, Sqlite> {
+        use crate::database::schema::{
+            media::dsl::id,
+            media_tags::dsl::{media_id, media_tags, tag_id},
+        };
+
+        let all_of = distinct(&self.all_of);
+        if !all_of.is_empty() {
+            let count = all_of.len() as i64;
+            query = query.filter(
+                id.eq_any(
+                    media_tags
+                        .select(media_id)
+                        .filter(tag_id.eq_any(all_of))
+                        .group_by(media_id)
+                        .having(count_distinct(tag_id).eq(count)),
+                ),
+            );
+        }
+
+        let any_of = distinct(&self.any_of);
+        if !any_of.is_empty() {
+            let count = self.at_least.unwrap_or(1) as i64;
+            query = query.filter(
+                id.eq_any(
+                    media_tags
+                        .select(media_id)
+                        .filter(tag_id.eq_any(any_of))
+                        .group_by(media_id)
+                        .having(count_distinct(tag_id).ge(count)),
+                ),
+            );
+        }
+
+        let none_of = distinct(&self.none_of);
+        if !none_of.is_empty() {
+            query = query.filter(not(
+                id.eq_any(media_tags.select(media_id).filter(tag_id.eq_any(none_of)))
+            ));
+        }
+
+        query
+    }
+}
+
+fn distinct(tag_ids: &[i32]) -> Vec<i32> {
+    let mut tag_ids = tag_ids.to_vec();
+    tag_ids.sort_unstable();
+    tag_ids.dedup();
+    tag_ids
+}
+
+/// MediaQuery combines filters, sorting and pagination to list media.
+///
+/// All the filters must match. Without sort keys, media are ordered by ID;
+/// the ID is always used as the last key, so that the order is stable.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaQuery {
+    base_path_ids: Vec<i32>,
+    media_types: Vec<String>,
+    mark: (Option<i16>, Option<i16>),
+    size: (Option<f64>, Option<f64>),
+    width: (Option<i16>, Option<i16>),
+    height: (Option<i16>, Option<i16>),
+    aspect_ratio: (Option<f64>, Option<f64>),
+    path_glob: Option<String>,
+    description: Option<String>,
+    tags: TagFilter,
+    search: Option<String>,
+    sort: Vec<(SortKey, Direction)>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    cursor: Option<Cursor>,
+}
+
+impl MediaQuery {
+    /// Returns a query that matches all the media, ordered by ID.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only matches media under one of the provided base paths.
+    pub fn with_base_paths(mut self, base_path_ids: impl IntoIterator<Item = i32>) -> Self {
+        self.base_path_ids.extend(base_path_ids);
+        self
+    }
+
+    /// Only matches media of one of the provided types.
+    pub fn with_media_types(mut self, media_types: impl IntoIterator<Item = MediaType>) -> Self {
+        self.media_types
+            .extend(media_types.into_iter().map(String::from));
+        self
+    }
+
+    /// Only matches media with a mark between `min` and `max`, inclusive.
+    /// Media without a mark never match.
+    pub fn with_mark_range(mut self, min: Option<i16>, max: Option<i16>) -> Self {
+        self.mark = (min, max);
+        self
+    }
+
+    /// Only matches media with a size, in kB, between `min` and `max`,
+    /// inclusive.
+    pub fn with_size_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.size = (min, max);
+        self
+    }
+
+    /// Only matches media with a width between `min` and `max`, inclusive.
+    /// Media without dimensions never match.
+    pub fn with_width_range(mut self, min: Option<i16>, max: Option<i16>) -> Self {
+        self.width = (min, max);
+        self
+    }
+
+    /// Only matches media with a height between `min` and `max`, inclusive.
+    /// Media without dimensions never match.
+    pub fn with_height_range(mut self, min: Option<i16>, max: Option<i16>) -> Self {
+        self.height = (min, max);
+        self
+    }
+
+    /// Only matches media whose width divided by height is between `min` and
+    /// `max`, inclusive, e.g. `Some(1.0)` and `None` for landscapes. Media
+    /// without dimensions never match.
+    pub fn with_aspect_ratio_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.aspect_ratio = (min, max);
+        self
+    }
+
+    /// Only matches media whose relative path matches the provided glob,
+    /// e.g. `2023/*.jpg`. `*` and `?` also match `/`, and matching is case
+    /// sensitive.
+    pub fn with_path_glob(mut self, glob: impl AsRef<str>) -> Self {
+        self.path_glob = Some(glob.as_ref().trim_matches('/').to_string());
+        self
+    }
+
+    /// Only matches media whose description contains the provided text,
+    /// ignoring the case of ASCII letters.
+    pub fn with_description(mut self, text: impl AsRef<str>) -> Self {
+        self.description = Some(text.as_ref().trim().to_string());
+        self
+    }
+
+    /// Only matches media tagged with all of the provided tags.
+    pub fn with_tags(mut self, tag_ids: impl IntoIterator<Item = i32>) -> Self {
+        self.tags = self.tags.with_all_of(tag_ids);
+        self
+    }
+
+    /// Only matches media tagged with none of the provided tags.
+    pub fn without_tags(mut self, tag_ids: impl IntoIterator<Item = i32>) -> Self {
+        self.tags = self.tags.with_none_of(tag_ids);
+        self
+    }
+
+    /// Only matches media selected by the provided tag filter, replacing the
+    /// tags added with [`MediaQuery::with_tags`] and
+    /// [`MediaQuery::without_tags`].
+    pub fn with_tag_filter(mut self, filter: TagFilter) -> Self {
+        self.tags = filter;
+        self
+    }
+
+    /// Only matches media matching the provided search, e.g.
+    /// `(beach OR sea) -blurry mark:>=7`. See [`search::parse`] for the
+    /// syntax.
+    ///
+    /// [`search::parse`]: crate::media::search::parse
+    pub fn with_search(mut self, search: impl AsRef<str>) -> Self {
+        self.search = Some(search.as_ref().to_string());
+        self
+    }
+
+    /// Sorts by the provided key, after the keys already added.
+    pub fn with_sort(mut self, key: SortKey, direction: Direction) -> Self {
+        self.sort.push((key, direction));
+        self
+    }
+
+    /// Returns at most `limit` media.
+    pub fn with_limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skips the first `offset` media.
+    pub fn with_offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Only returns the media after the provided cursor, i.e. the next page.
+    pub fn with_cursor(mut self, cursor: Cursor) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    /// Checks that ranges are not reversed or negative, that the tag
+    /// threshold and the pagination are valid and that the cursor matches the
+    /// sort keys.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        fn check<T: PartialOrd + Default>(range: &(Option<T>, Option<T>)) -> Result<(), Error> {
+            let negative = [&range.0, &range.1]
+                .into_iter()
+                .flatten()
+                .any(|value| *value < T::default());
+            match range {
+                _ if negative => Err(Error::InvalidRange),
+                (Some(min), Some(max)) if min > max => Err(Error::InvalidRange),
+                _ => Ok(()),
+            }
+        }
+
+        check(&self.mark)?;
+        check(&self.size)?;
+        check(&self.width)?;
+        check(&self.height)?;
+        check(&self.aspect_ratio)?;
+        self.tags.validate()?;
+
+        if self.limit.is_some_and(|limit| limit <= 0) || self.offset.is_some_and(|o| o < 0) {
+            return Err(Error::InvalidPagination);
+        }
+
+        if let Some(cursor) = &self.cursor {
+            let keys = self.sort_keys();
+            if cursor.values.len() != keys.len()
+                || !keys
+                    .iter()
+                    .zip(&cursor.values)
+                    .all(|((k, _), v)| k.matches(v))
+            {
+                return Err(Error::InvalidCursor);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn search(&self) -> Option<&str> {
+        self.search.as_deref()
+    }
+
+    pub(crate) fn base_path_ids(&self) -> &[i32] {
+        &self.base_path_ids
+    }
+
+    pub(crate) fn tag_ids(&self) -> Vec<i32> {
+        self.tags.tag_ids()
+    }
+
+    /// Replaces the IDs of the tags and base paths, e.g. to use the query on
+    /// another library.
+    pub(crate) fn map_ids(
+        mut self,
+        tag: impl Fn(i32) -> i32,
+        base_path: impl Fn(i32) -> i32,
+    ) -> Self {
+        self.tags = self.tags.map_tag_ids(tag);
+        self.base_path_ids
+            .iter_mut()
+            .for_each(|base_path_id| *base_path_id = base_path(*base_path_id));
+        self
+    }
+
+    /// Removes the cursor, so that the query starts from the first page.
+    pub(crate) fn without_cursor(mut self) -> Self {
+        self.cursor = None;
+        self
+    }
+
+    /// Returns the media matching the filters and the provided search, which
+    /// must have been resolved from [`MediaQuery::search`], without sorting
+    /// or pagination.
+    pub(crate) fn filtered(&self, search: Option<&Resolved>) -> media::BoxedQuery<'_, Sqlite> {
+        use crate::database::schema::media::dsl::{
+            base_path_id, description, height, mark, media_type, size, width,
+        };
+
+        let mut query = media_table.into_boxed();
+        if !self.base_path_ids.is_empty() {
+            query = query.filter(base_path_id.eq_any(&self.base_path_ids));
+        }
+
+        if !self.media_types.is_empty() {
+            query = query.filter(media_type.eq_any(&self.media_types));
+        }
+
+        if let Some(min) = self.mark.0 {
+            query = query.filter(mark.ge(min));
+        }
+        if let Some(max) = self.mark.1 {
+            query = query.filter(mark.le(max));
+        }
+        if let Some(min) = self.size.0 {
+            query = query.filter(size.ge(min));
+        }
+        if let Some(max) = self.size.1 {
+            query = query.filter(size.le(max));
+        }
+        if let Some(min) = self.width.0 {
+            query = query.filter(width.ge(min));
+        }
+        if let Some(max) = self.width.1 {
+            query = query.filter(width.le(max));
+        }
+        if let Some(min) = self.height.0 {
+            query = query.filter(height.ge(min));
+        }
+        if let Some(max) = self.height.1 {
+            query = query.filter(height.le(max));
+        }
+
+        if self.aspect_ratio != (None, None) {
+            query = query.filter(sql::<Bool>("media.height > 0"));
+        }
+        if let Some(min) = self.aspect_ratio.0 {
+            query = query.filter(
+                sql::<Bool>("CAST(media.width AS REAL) / media.height >= ").bind::<Double, _>(min),
+            );
+        }
+        if let Some(max) = self.aspect_ratio.1 {
+            query = query.filter(
+                sql::<Bool>("CAST(media.width AS REAL) / media.height <= ").bind::<Double, _>(max),
+            );
+        }
+
+        if let Some(glob) = &self.path_glob {
+            query = query.filter(sql::<Bool>("media.relative_path GLOB ").bind::<Text, _>(glob));
+        }
+
+        if let Some(text) = &self.description {
+            let pattern = format!(
+                "%{}%",
+                text.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            );
+            query = query.filter(description.like(pattern).escape('\\'));
+        }
+
+        query = self.tags.apply(query);
+
+        if let Some(search) = search {
+            query = query.filter(search.condition());
+        }
+
+        query
+    }
+
+    /// Returns the media matching the filters, sorted and paginated.
+    pub(crate) fn paginated(&self, search: Option<&Resolved>) -> media::BoxedQuery<'_, Sqlite> {
+        let keys = self.sort_keys();
+        let mut query = self.filtered(search);
+
+        if let Some(cursor) = &self.cursor {
+            query = query.filter(after(&keys, &cursor.values));
+        }
+
+        for (key, direction) in &keys {
+            let column = sql::<BigInt>(key.sql());
+            query = match direction {
+                Direction::Ascending => query.then_order_by(column.asc()),
+                Direction::Descending => query.then_order_by(column.desc()),
+            };
+        }
+
+        match (self.limit, self.offset) {
+            (Some(limit), Some(offset)) => query.limit(limit).offset(offset),
+            (Some(limit), None) => query.limit(limit),
+            // SQLite does not support an offset without a limit.
+            (None, Some(offset)) => query.limit(-1).offset(offset),
+            (None, None) => query,
+        }
+    }
+
+    /// Returns the cursor after the last media of a page, if the page is
+    /// full.
+    pub(crate) fn next_cursor(&self, page: &[MediaFile]) -> Option<Cursor> {
+        let last = page.last()?;
+        if self.limit != Some(page.len() as i64) {
+            return None;
+        }
+
+        Some(Cursor {
+            values: self
+                .sort_keys()
+                .iter()
+                .map(|(key, _)| key.value(last))
+                .collect(),
+        })
+    }
+
+    fn sort_keys(&self) -> Vec<(SortKey, Direction)> {
+        let mut keys: Vec<(SortKey, Direction)> = vec![];
+        for (key, direction) in &self.sort {
+            if !keys.iter().any(|(k, _)| k == key) {
+                keys.push((*key, *direction));
+            }
+        }
+
+        if !keys.iter().any(|(k, _)| *k == SortKey::Id) {
+            keys.push((SortKey::Id, Direction::Ascending));
+        }
+
+        keys
+    }
+}
+
+/// Builds the condition matching the rows after the provided values of the
+/// sort keys, i.e. `k1 > v1 OR (k1 = v1 AND k2 > v2) OR ...`, with `<` for
+/// descending keys.
+fn after(keys: &[(SortKey, Direction)], values: &[SortValue]) -> Condition {
+    let mut condition: Option<Condition> = None;
+    for i in 0..keys.len() {
+        let (key, direction) = keys[i];
+        let op = match direction {
+            Direction::Ascending => ">",
+            Direction::Descending => "<",
+        };
+
+        let mut branch = compare(key, op, &values[i]);
+        for j in 0..i {
+            branch = Box::new(compare(keys[j].0, "=", &values[j]).and(branch));
+        }
+
+        condition = Some(match condition {
+            None => branch,
+            Some(condition) => Box::new(condition.or(branch)),
+        });
+    }
+
+    condition.unwrap_or_else(|| Box::new(sql::<Bool>("1")))
+}
+
+fn compare(key: SortKey, op: &str, value: &SortValue) -> Condition {
+    let lhs = format!("{} {} ", key.sql(), op);
+    match value {
+        SortValue::Int(value) => Box::new(sql::<Bool>(&lhs).bind::<BigInt, _>(*value)),
+        SortValue::Float(value) => Box::new(sql::<Bool>(&lhs).bind::<Double, _>(*value)),
+        SortValue::Text(value) => Box::new(sql::<Bool>(&lhs).bind::<Text, _>(value.clone())),
+    }
+}
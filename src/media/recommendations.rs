@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use diesel::{
+    dsl::count_star,
+    sql_function, sql_query,
+    sql_types::{BigInt, Double},
+    ExpressionMethods, QueryDsl, QueryableByName, RunQueryDsl,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    data::{media_file::MediaFile, tag::Tag},
+    database::{self, connection::DatabaseConnection, schema::media::dsl::media as media_table},
+    media::media::{self, media},
+};
+
+sql_function!(fn idf(document_frequency: BigInt, document_count: BigInt) -> Double);
+sql_function!(fn sqrt(x: Double) -> Double);
+
+/// Recommendations finds the media whose tags are the most similar to the
+/// tags of a media, i.e. "more like this".
+///
+/// Tags are weighted by their rarity, i.e. their inverse document
+/// frequency: sharing a rare tag, e.g. `eiffel tower`, counts more than
+/// sharing a common one, e.g. `outdoor`. Only the media sharing at least one
+/// tag are scored, in a single query, so that it stays fast on large
+/// libraries.
+pub struct Recommendations {
+    connection: DatabaseConnection,
+    metric: Metric,
+    mark_weight: f64,
+    media_type_weight: f64,
+}
+
+/// This returns a new instance of the `Recommendations` struct, that uses
+/// the cosine similarity and ignores marks and media types.
+pub fn recommendations(connection: DatabaseConnection) -> Recommendations {
+    Recommendations {
+        connection,
+        metric: Metric::Cosine,
+        mark_weight: 0.0,
+        media_type_weight: 0.0,
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The media could not be found.
+    #[error("media error: {0}")]
+    MediaError(#[from] media::Error),
+    /// A weight is not between 0 and 1.
+    #[error("invalid weight")]
+    InvalidWeight,
+}
+
+/// How the similarity of two sets of weighted tags is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// The weight of the shared tags over the weight of all their tags.
+    /// Media with many other tags are penalized.
+    Jaccard,
+    /// The cosine of the angle between the weight vectors of their tags,
+    /// which penalizes media with many other tags less than Jaccard.
+    Cosine,
+}
+
+/// A media similar to the provided one.
+#[derive(Debug, Serialize)]
+pub struct Recommendation {
+    pub media: MediaFile,
+    /// How similar it is, from `0` to `1`: the higher, the better.
+    pub score: f64,
+    /// The tags it shares with the provided media, from the rarest.
+    pub shared_tags: Vec<Tag>,
+}
+
+#[derive(QueryableByName)]
+struct Scored {
+    #[diesel(sql_type = BigInt)]
+    media_id: i64,
+    #[diesel(sql_type = Double)]
+    score: f64,
+}
+
+impl Recommendations {
+    /// Measures the similarity with the provided metric instead of the
+    /// cosine similarity.
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Favors the media with a better mark: with a weight of 1 the score is
+    /// proportional to the mark, with 0 the mark is ignored. Media that have
+    /// never been rated count as rated 5.
+    pub fn with_mark_weight(mut self, weight: f64) -> Self {
+        self.mark_weight = weight;
+        self
+    }
+
+    /// Favors the media with the same type: the score of media of another
+    /// type is reduced by the weight, e.g. with a weight of 1 only media of
+    /// the same type are returned.
+    pub fn with_media_type_weight(mut self, weight: f64) -> Self {
+        self.media_type_weight = weight;
+        self
+    }
+
+    /// Returns at most `limit` media similar to the media with the provided
+    /// ID, from the most similar one. A media without tags has no similar
+    /// media.
+    ///
+    /// It returns an error in case the media was not found, a weight is
+    /// invalid or if there was an error on the database.
+    pub fn more_like_this(&self, id: i64, limit: usize) -> Result<Vec<Recommendation>, Error> {
+        if ![self.mark_weight, self.media_type_weight]
+            .iter()
+            .all(|weight| (0.0..=1.0).contains(weight))
+        {
+            return Err(Error::InvalidWeight);
+        }
+        media(self.connection.clone()).get(id)?;
+
+        let conn = &mut self.connection.establish_connection()?;
+        idf::register_impl(conn, tag_weight)?;
+        sqrt::register_impl(conn, f64::sqrt)?;
+
+        let media_count = media_table.count().get_result::<i64>(conn)?;
+        let similarity = match self.metric {
+            Metric::Jaccard => {
+                "candidates.shared / (source_norms.total + candidates.total - candidates.shared)"
+            }
+            Metric::Cosine => {
+                "candidates.shared_squares \
+                 / (sqrt(source_norms.total_squares) * sqrt(candidates.total_squares))"
+            }
+        };
+
+        // The weights of the tags of the media, then the weights shared by
+        // each candidate with the media and the weights of all the tags of
+        // each candidate. Only the tags of the media and of the candidates
+        // are weighted, rather than all the tags of the library.
+        let scored = sql_query(format!(
+            "WITH source AS ( \
+                 SELECT tag_id, idf(COUNT(*), ?) AS weight FROM media_tags \
+                 WHERE tag_id IN (SELECT tag_id FROM media_tags WHERE media_id = ?) \
+                 GROUP BY tag_id), \
+             source_norms AS ( \
+                 SELECT SUM(weight) AS total, SUM(weight * weight) AS total_squares FROM source), \
+             shared AS ( \
+                 SELECT mt.media_id, SUM(source.weight) AS shared, \
+                 SUM(source.weight * source.weight) AS shared_squares \
+                 FROM media_tags mt JOIN source USING (tag_id) \
+                 WHERE mt.media_id != ? GROUP BY mt.media_id), \
+             weights AS ( \
+                 SELECT tag_id, idf(COUNT(*), ?) AS weight FROM media_tags \
+                 WHERE tag_id IN (SELECT mt.tag_id FROM media_tags mt JOIN shared USING (media_id)) \
+                 GROUP BY tag_id), \
+             candidates AS ( \
+                 SELECT shared.media_id, shared.shared, shared.shared_squares, \
+                 SUM(weights.weight) AS total, SUM(weights.weight * weights.weight) AS total_squares \
+                 FROM shared JOIN media_tags mt ON mt.media_id = shared.media_id \
+                 JOIN weights USING (tag_id) GROUP BY shared.media_id) \
+             SELECT candidates.media_id, ({similarity}) \
+                 * (1.0 - ? + ? * COALESCE(media.mark, 5) / 10.0) \
+                 * (CASE WHEN media.media_type = (SELECT media_type FROM media WHERE id = ?) THEN 1.0 ELSE 1.0 - ? END) AS score \
+             FROM candidates CROSS JOIN source_norms \
+             JOIN media ON media.id = candidates.media_id \
+             WHERE score > 0 ORDER BY score DESC, candidates.media_id LIMIT ?"
+        ))
+        .bind::<BigInt, _>(media_count)
+        .bind::<BigInt, _>(id)
+        .bind::<BigInt, _>(id)
+        .bind::<BigInt, _>(media_count)
+        .bind::<Double, _>(self.mark_weight)
+        .bind::<Double, _>(self.mark_weight)
+        .bind::<BigInt, _>(id)
+        .bind::<Double, _>(self.media_type_weight)
+        .bind::<BigInt, _>(limit as i64)
+        .load::<Scored>(conn)?;
+        if scored.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let ids: Vec<i64> = scored.iter().map(|s| s.media_id).collect();
+        let mut files: HashMap<i64, MediaFile> = {
+            use database::schema::media::dsl::id as media_id;
+            media_table
+                .filter(media_id.eq_any(&ids))
+                .load::<MediaFile>(conn)?
+                .into_iter()
+                .map(|file| (file.id, file))
+                .collect()
+        };
+
+        // The shared tags, from the rarest, to explain each match.
+        use database::schema::{
+            media_tags::dsl::{media_id, media_tags, tag_id},
+            tags::dsl::{id as t_id, tags as tags_table},
+        };
+        let source_tags: Vec<i32> = media_tags
+            .filter(media_id.eq(id))
+            .select(tag_id)
+            .load(conn)?;
+        let weights: HashMap<i32, f64> = media_tags
+            .filter(tag_id.eq_any(&source_tags))
+            .group_by(tag_id)
+            .select((tag_id, count_star()))
+            .load::<(i32, i64)>(conn)?
+            .into_iter()
+            .map(|(tag, count)| (tag, tag_weight(count, media_count)))
+            .collect();
+        let mut source_tags: Vec<Tag> = tags_table.filter(t_id.eq_any(&source_tags)).load(conn)?;
+        source_tags.sort_by(|a, b| {
+            weights[&b.id]
+                .total_cmp(&weights[&a.id])
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        let mut shared: HashMap<i64, Vec<i32>> = HashMap::new();
+        for (candidate, tag) in media_tags
+            .filter(media_id.eq_any(&ids))
+            .filter(tag_id.eq_any(source_tags.iter().map(|tag| tag.id)))
+            .select((media_id, tag_id))
+            .load::<(i64, i32)>(conn)?
+        {
+            shared.entry(candidate).or_default().push(tag);
+        }
+
+        Ok(scored
+            .into_iter()
+            .filter_map(|s| {
+                let shared = shared.remove(&s.media_id).unwrap_or_default();
+                Some(Recommendation {
+                    media: files.remove(&s.media_id)?,
+                    score: s.score,
+                    shared_tags: source_tags
+                        .iter()
+                        .filter(|tag| shared.contains(&tag.id))
+                        .cloned()
+                        .collect(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// The inverse document frequency of a tag used by `document_frequency` of
+/// the `document_count` media. It stays positive for tags used by all the
+/// media, so that they still count a little.
+fn tag_weight(document_frequency: i64, document_count: i64) -> f64 {
+    (1.0 + document_count as f64 / document_frequency.max(1) as f64).ln()
+}
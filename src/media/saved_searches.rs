@@ -0,0 +1,557 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use diesel::{
+    result::DatabaseErrorKind, Connection, ExpressionMethods, Insertable, QueryDsl, Queryable,
+    RunQueryDsl, SqliteConnection,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    data::saved_search::SavedSearch,
+    database::{
+        self,
+        connection::DatabaseConnection,
+        schema::saved_searches::{self, dsl::saved_searches as ss_table},
+    },
+    media::{
+        media::{self, media},
+        naming,
+        query::{MediaPage, MediaQuery},
+        scanner::unix_timestamp,
+        search,
+    },
+};
+
+/// The version of the JSON format of exported saved searches.
+const EXPORT_VERSION: u32 = 1;
+
+/// SavedSearches stores named queries, so that the same searches do not
+/// have to be built again each time.
+///
+/// Only the query is stored, not its results: evaluating a saved search
+/// always lists the media currently matching it.
+pub struct SavedSearches {
+    connection: DatabaseConnection,
+}
+
+pub fn saved_searches(connection: DatabaseConnection) -> SavedSearches {
+    SavedSearches { connection }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The provided ID is invalid, e.g. it is <= 0.
+    #[error("invalid id")]
+    InvalidID,
+    /// The saved search was not found.
+    #[error("not found")]
+    NotFound,
+    /// The provided name is invalid, e.g. it is empty.
+    #[error("invalid name")]
+    InvalidName,
+    /// The provided name is longer than 50 characters.
+    #[error("name too long")]
+    NameTooLong,
+    /// The provided description is longer than 300 characters.
+    #[error("description too long")]
+    DescriptionTooLong,
+    /// Another saved search has the same name, ignoring the case.
+    #[error("already exists")]
+    AlreadyExists,
+    /// The query is invalid or could not be evaluated.
+    #[error("media error: {0}")]
+    MediaError(#[from] media::Error),
+    /// The saved searches could not be converted from or to JSON.
+    #[error("json error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    /// The saved searches were exported in a format that is not supported.
+    #[error("unsupported version {0}")]
+    UnsupportedVersion(u32),
+    /// An imported saved search refers to a tag, as `category:name`, that
+    /// does not exist in this library.
+    #[error("unknown tag {0}")]
+    UnknownTag(String),
+    /// An imported saved search refers to a base path that does not exist in
+    /// this library.
+    #[error("unknown base path {0}")]
+    UnknownBasePath(String),
+}
+
+impl From<naming::Invalid> for Error {
+    fn from(value: naming::Invalid) -> Self {
+        match value {
+            naming::Invalid::Name => Error::InvalidName,
+            naming::Invalid::NameTooLong => Error::NameTooLong,
+            naming::Invalid::DescriptionTooLong => Error::DescriptionTooLong,
+        }
+    }
+}
+
+/// Represents a saved search to create.
+pub struct CreateSavedSearch {
+    /// The name, unique among the saved searches, ignoring the case.
+    pub name: String,
+    /// What the search is for.
+    pub description: String,
+    /// The filters and sort order. The cursor, if any, is not saved.
+    pub query: MediaQuery,
+}
+
+impl CreateSavedSearch {
+    /// Trims the name and the description, and checks them. The query is
+    /// checked by [`SavedSearches`], which can resolve its tags.
+    fn validate(self) -> Result<Self, Error> {
+        Ok(Self {
+            name: naming::name(&self.name)?,
+            description: naming::description(&self.description)?,
+            ..self
+        })
+    }
+}
+
+#[derive(Queryable)]
+struct SavedSearchRow {
+    id: i32,
+    name: String,
+    description: String,
+    query: String,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl TryFrom<SavedSearchRow> for SavedSearch {
+    type Error = serde_json::Error;
+
+    fn try_from(value: SavedSearchRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id,
+            name: value.name,
+            description: value.description,
+            query: serde_json::from_str(&value.query)?,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = saved_searches)]
+struct NewSavedSearch {
+    name: String,
+    description: String,
+    query: String,
+    created_at: i64,
+    updated_at: i64,
+}
+
+/// The JSON format of exported saved searches.
+///
+/// Tags and base paths are referred to by ID in queries, so their names and
+/// paths are exported too, to find them again when importing into another
+/// library.
+#[derive(Serialize, Deserialize)]
+struct Export {
+    version: u32,
+    searches: Vec<ExportedSearch>,
+    #[serde(default)]
+    tags: Vec<ExportedTag>,
+    #[serde(default)]
+    base_paths: Vec<ExportedBasePath>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedSearch {
+    name: String,
+    #[serde(default)]
+    description: String,
+    query: MediaQuery,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedTag {
+    id: i32,
+    category: String,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedBasePath {
+    id: i32,
+    base_path: String,
+}
+
+impl SavedSearches {
+    /// Saves a new search.
+    ///
+    /// It returns an error in case the name or the description are invalid,
+    /// another saved search has the same name, the query is invalid, e.g. its
+    /// search refers to unknown tags, or if there was an error on the
+    /// database.
+    pub fn create(&self, data: CreateSavedSearch) -> Result<SavedSearch, Error> {
+        let new = self.validate(data)?;
+        let conn = &mut self.connection.establish_connection()?;
+        insert(conn, &new)
+    }
+
+    /// Gets a saved search by ID.
+    ///
+    /// It returns an error if the ID is not valid, if the saved search was
+    /// not found or if there was an error on the database.
+    pub fn get(&self, id: i32) -> Result<SavedSearch, Error> {
+        if id <= 0 {
+            return Err(Error::InvalidID);
+        }
+
+        use database::schema::saved_searches::dsl::id as ss_id;
+        let conn = &mut self.connection.establish_connection()?;
+        let row = ss_table
+            .filter(ss_id.eq(id))
+            .first::<SavedSearchRow>(conn)
+            .map_err(|err| match err {
+                diesel::result::Error::NotFound => Error::NotFound,
+                _ => Error::DatabaseError(err),
+            })?;
+        Ok(SavedSearch::try_from(row)?)
+    }
+
+    /// Gets a saved search by name, ignoring the case.
+    ///
+    /// It returns an error if the saved search was not found or if there was
+    /// an error on the database.
+    pub fn get_by_name(&self, name: impl AsRef<str>) -> Result<SavedSearch, Error> {
+        use database::schema::saved_searches::dsl::name as ss_name;
+        let conn = &mut self.connection.establish_connection()?;
+        let row = ss_table
+            .filter(ss_name.eq(name.as_ref().trim()))
+            .first::<SavedSearchRow>(conn)
+            .map_err(|err| match err {
+                diesel::result::Error::NotFound => Error::NotFound,
+                _ => Error::DatabaseError(err),
+            })?;
+        Ok(SavedSearch::try_from(row)?)
+    }
+
+    /// Lists all the saved searches, ordered by name.
+    ///
+    /// It returns an error in case there was an error on the database.
+    pub fn list(&self) -> Result<Vec<SavedSearch>, Error> {
+        use database::schema::saved_searches::dsl::name;
+        let conn = &mut self.connection.establish_connection()?;
+        ss_table
+            .order(name.asc())
+            .load::<SavedSearchRow>(conn)?
+            .into_iter()
+            .map(|row| SavedSearch::try_from(row).map_err(Error::JsonError))
+            .collect()
+    }
+
+    /// Evaluates the saved search against the current library, returning the
+    /// first page of matching media.
+    ///
+    /// To get the next pages, use [`Media::query`] with the query of the
+    /// saved search and the cursor of the page.
+    ///
+    /// It returns an error in case the saved search was not found, its query
+    /// is no longer valid, e.g. because a tag has been removed, or if there
+    /// was an error on the database.
+    ///
+    /// [`Media::query`]: crate::media::media::Media::query
+    pub fn evaluate(&self, id: i32) -> Result<MediaPage, Error> {
+        let saved = self.get(id)?;
+        Ok(media(self.connection.clone()).query(&saved.query)?)
+    }
+
+    /// Renames a saved search.
+    ///
+    /// It returns an error in case the name is invalid, another saved search
+    /// has the same name, the saved search was not found or if there was an
+    /// error on the database.
+    pub fn rename(&self, id: i32, new_name: impl AsRef<str>) -> Result<(), Error> {
+        let new_name = naming::name(new_name.as_ref())?;
+        self.get(id)?;
+
+        use database::schema::saved_searches::dsl::{id as ss_id, name, updated_at};
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::update(ss_table.filter(ss_id.eq(id)))
+            .set((
+                name.eq(new_name),
+                updated_at.eq(unix_timestamp(SystemTime::now())),
+            ))
+            .execute(conn)
+            .map_err(map_unique_violation)?;
+        Ok(())
+    }
+
+    /// Updates the description of a saved search.
+    ///
+    /// It returns an error in case the description is too long, the saved
+    /// search was not found or if there was an error on the database.
+    pub fn update_description(
+        &self,
+        id: i32,
+        new_description: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let new_description = naming::description(new_description.as_ref())?;
+        self.get(id)?;
+
+        use database::schema::saved_searches::dsl::{description, id as ss_id, updated_at};
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::update(ss_table.filter(ss_id.eq(id)))
+            .set((
+                description.eq(new_description),
+                updated_at.eq(unix_timestamp(SystemTime::now())),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Replaces the query of a saved search, e.g. to change its filters or
+    /// sort order.
+    ///
+    /// It returns the same errors as [`SavedSearches::create`] for the query,
+    /// or an error in case the saved search was not found.
+    pub fn update_query(&self, id: i32, new_query: MediaQuery) -> Result<(), Error> {
+        let new_query = self.validate_query(new_query)?;
+        self.get(id)?;
+
+        use database::schema::saved_searches::dsl::{id as ss_id, query, updated_at};
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::update(ss_table.filter(ss_id.eq(id)))
+            .set((
+                query.eq(new_query),
+                updated_at.eq(unix_timestamp(SystemTime::now())),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Deletes a saved search. The media it matches are not affected.
+    ///
+    /// It returns an error in case the ID is invalid, the saved search was
+    /// not found or if there was an error on the database.
+    pub fn delete(&self, id: i32) -> Result<(), Error> {
+        self.get(id)?;
+
+        use database::schema::saved_searches::dsl::id as ss_id;
+        let conn = &mut self.connection.establish_connection()?;
+        diesel::delete(ss_table.filter(ss_id.eq(id))).execute(conn)?;
+        Ok(())
+    }
+
+    /// Exports the saved searches with the provided IDs, or all of them if
+    /// `None`, as JSON, to be imported with [`SavedSearches::import`], e.g.
+    /// into another library.
+    ///
+    /// It returns an error in case a saved search was not found or if there
+    /// was an error on the database.
+    pub fn export(&self, ids: Option<impl IntoIterator<Item = i32>>) -> Result<String, Error> {
+        let searches = match ids {
+            None => self.list()?,
+            Some(ids) => ids
+                .into_iter()
+                .map(|id| self.get(id))
+                .collect::<Result<_, _>>()?,
+        };
+
+        let mut tag_ids: Vec<i32> = searches.iter().flat_map(|s| s.query.tag_ids()).collect();
+        tag_ids.sort_unstable();
+        tag_ids.dedup();
+        let mut base_path_ids: Vec<i32> = searches
+            .iter()
+            .flat_map(|s| s.query.base_path_ids().to_vec())
+            .collect();
+        base_path_ids.sort_unstable();
+        base_path_ids.dedup();
+
+        let conn = &mut self.connection.establish_connection()?;
+        let tags = {
+            use database::schema::{
+                tag_categories::dsl::{name as category_name, tag_categories},
+                tags::dsl::{id, name, tags},
+            };
+            tags.inner_join(tag_categories)
+                .filter(id.eq_any(tag_ids))
+                .select((id, category_name, name))
+                .load::<(i32, String, String)>(conn)?
+        };
+        let base_paths = {
+            use database::schema::base_paths::dsl::{base_path, base_paths, id};
+            base_paths
+                .filter(id.eq_any(base_path_ids))
+                .select((id, base_path))
+                .load::<(i32, String)>(conn)?
+        };
+
+        let export = Export {
+            version: EXPORT_VERSION,
+            searches: searches
+                .into_iter()
+                .map(|s| ExportedSearch {
+                    name: s.name,
+                    description: s.description,
+                    query: s.query,
+                })
+                .collect(),
+            tags: tags
+                .into_iter()
+                .map(|(id, category, name)| ExportedTag { id, category, name })
+                .collect(),
+            base_paths: base_paths
+                .into_iter()
+                .map(|(id, base_path)| ExportedBasePath { id, base_path })
+                .collect(),
+        };
+        Ok(serde_json::to_string_pretty(&export)?)
+    }
+
+    /// Imports saved searches exported with [`SavedSearches::export`].
+    ///
+    /// Tags are found by category and name, ignoring the case, and base paths
+    /// by path, so that the searches work on another library with the same
+    /// tags. Either all the searches are imported or none of them.
+    ///
+    /// It returns the imported saved searches, or an error in case the JSON
+    /// is invalid, a tag or base path does not exist in this library, a
+    /// saved search is invalid or has the name of an existing one, or if
+    /// there was an error on the database.
+    pub fn import(&self, json: impl AsRef<str>) -> Result<Vec<SavedSearch>, Error> {
+        let export: Export = serde_json::from_str(json.as_ref())?;
+        if export.version != EXPORT_VERSION {
+            return Err(Error::UnsupportedVersion(export.version));
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        let current_tags = {
+            use database::schema::{
+                tag_categories::dsl::{name as category_name, tag_categories},
+                tags::dsl::{id, name, tags},
+            };
+            tags.inner_join(tag_categories)
+                .select((id, category_name, name))
+                .load::<(i32, String, String)>(conn)?
+        };
+        let tag_ids: HashMap<i32, i32> = export
+            .tags
+            .iter()
+            .filter_map(|tag| {
+                current_tags
+                    .iter()
+                    .find(|(_, category, name)| {
+                        category.to_lowercase() == tag.category.to_lowercase()
+                            && name.to_lowercase() == tag.name.to_lowercase()
+                    })
+                    .map(|(id, _, _)| (tag.id, *id))
+            })
+            .collect();
+
+        let current_base_paths = {
+            use database::schema::base_paths::dsl::{base_path, base_paths, id};
+            base_paths
+                .select((id, base_path))
+                .load::<(i32, String)>(conn)?
+        };
+        let base_path_ids: HashMap<i32, i32> = export
+            .base_paths
+            .iter()
+            .filter_map(|bp| {
+                current_base_paths
+                    .iter()
+                    .find(|(_, path)| *path == bp.base_path)
+                    .map(|(id, _)| (bp.id, *id))
+            })
+            .collect();
+
+        let mut new = vec![];
+        for search in export.searches {
+            if let Some(missing) = search
+                .query
+                .tag_ids()
+                .into_iter()
+                .find(|id| !tag_ids.contains_key(id))
+            {
+                return Err(Error::UnknownTag(
+                    match export.tags.iter().find(|tag| tag.id == missing) {
+                        Some(tag) => format!("{}:{}", tag.category, tag.name),
+                        None => format!("#{missing}"),
+                    },
+                ));
+            }
+            if let Some(missing) = search
+                .query
+                .base_path_ids()
+                .iter()
+                .find(|id| !base_path_ids.contains_key(id))
+            {
+                return Err(Error::UnknownBasePath(
+                    match export.base_paths.iter().find(|bp| bp.id == *missing) {
+                        Some(bp) => bp.base_path.clone(),
+                        None => format!("#{missing}"),
+                    },
+                ));
+            }
+
+            new.push(
+                self.validate(CreateSavedSearch {
+                    name: search.name,
+                    description: search.description,
+                    query: search
+                        .query
+                        .map_ids(|id| tag_ids[&id], |id| base_path_ids[&id]),
+                })?,
+            );
+        }
+
+        conn.transaction(|conn| new.iter().map(|new| insert(conn, new)).collect())
+    }
+
+    /// Checks the data of a new saved search, returning it as it is stored.
+    fn validate(&self, data: CreateSavedSearch) -> Result<NewSavedSearch, Error> {
+        let data = data.validate()?;
+        let now = unix_timestamp(SystemTime::now());
+        Ok(NewSavedSearch {
+            name: data.name,
+            description: data.description,
+            query: self.validate_query(data.query)?,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+
+    /// Checks the query, including the tags of its search, and returns it as
+    /// JSON, without cursor.
+    fn validate_query(&self, query: MediaQuery) -> Result<String, Error> {
+        let query = query.without_cursor();
+        query.validate()?;
+        if let Some(text) = query.search() {
+            search::resolve(&self.connection, text).map_err(media::Error::SearchError)?;
+        }
+
+        Ok(serde_json::to_string(&query)?)
+    }
+}
+
+fn insert(conn: &mut SqliteConnection, new: &NewSavedSearch) -> Result<SavedSearch, Error> {
+    let row = diesel::insert_into(ss_table)
+        .values(new)
+        .get_result::<SavedSearchRow>(conn)
+        .map_err(map_unique_violation)?;
+    Ok(SavedSearch::try_from(row)?)
+}
+
+fn map_unique_violation(err: diesel::result::Error) -> Error {
+    match err {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            Error::AlreadyExists
+        }
+        _ => Error::DatabaseError(err),
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{data::media_file::MediaType, media::archive};
+
+/// A media file found on disk while scanning a base path.
+#[derive(Debug)]
+pub struct ScannedFile {
+    /// The path relative to the scanned base path.
+    pub relative_path: String,
+    /// The path of the entry inside the archive at `relative_path`, if the
+    /// media file is stored in an archive.
+    pub archive_entry: Option<String>,
+    /// The media type, guessed from the extension.
+    pub media_type: MediaType,
+    /// The size in bytes, uncompressed for archive entries.
+    pub size: u64,
+    /// The modification time, as a UNIX timestamp. Archive entries have the
+    /// modification time of their archive.
+    pub modified: i64,
+}
+
+impl ScannedFile {
+    /// Returns the size in kB, as stored in [`MediaFile`](crate::data::media_file::MediaFile).
+    pub fn size_kb(&self) -> f64 {
+        self.size as f64 / 1024.0
+    }
+
+    /// Returns the relative path, followed by the path of the archive entry
+    /// if any, e.g. `comics/issue-1.cbz/page-01.jpg`.
+    pub fn display_path(&self) -> String {
+        display_path(&self.relative_path, self.archive_entry.as_deref())
+    }
+}
+
+/// Joins a relative path and the path of an archive entry, if any, as done
+/// by [`ScannedFile::display_path`].
+pub fn display_path(relative_path: &str, archive_entry: Option<&str>) -> String {
+    match archive_entry {
+        None => relative_path.to_string(),
+        Some(entry) => format!("{}/{}", relative_path, entry),
+    }
+}
+
+/// Recursively scans the provided base path and returns all the media files
+/// found, ordered by relative path.
+///
+/// Files whose extension is not a known media type are skipped, as well as
+/// hidden files and directories, i.e. starting with a `.`. Symbolic links
+/// are not followed.
+///
+/// The media files inside archives, e.g. the pages of a `.cbz`, are listed
+/// as well, one per entry: see [`archive::list_entries`]. Archives that
+/// cannot be read are skipped.
+pub fn scan(base_path: impl AsRef<Path>) -> io::Result<Vec<ScannedFile>> {
+    let base_path = base_path.as_ref();
+    let mut files = vec![];
+    let mut directories = vec![base_path.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            let path = entry.path();
+            if metadata.is_dir() {
+                directories.push(path);
+                continue;
+            }
+
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let media_type = MediaType::from_path(&path);
+            let is_archive = archive::is_archive(&path);
+            if matches!(media_type, MediaType::Unknown) && !is_archive {
+                continue;
+            }
+
+            let relative_path = match path.strip_prefix(base_path).ok().and_then(|p| p.to_str()) {
+                Some(rp) => rp.to_string(),
+                None => continue,
+            };
+            let modified = metadata.modified().map(unix_timestamp).unwrap_or_default();
+
+            if is_archive {
+                let entries = archive::list_entries(&path).unwrap_or_default();
+                files.extend(entries.into_iter().map(|entry| ScannedFile {
+                    relative_path: relative_path.clone(),
+                    archive_entry: Some(entry.name),
+                    media_type: entry.media_type,
+                    size: entry.size,
+                    modified,
+                }));
+                continue;
+            }
+
+            files.push(ScannedFile {
+                relative_path,
+                archive_entry: None,
+                media_type,
+                size: metadata.len(),
+                modified,
+            });
+        }
+    }
+
+    files.sort_by(|a, b| {
+        (&a.relative_path, &a.archive_entry).cmp(&(&b.relative_path, &b.archive_entry))
+    });
+    Ok(files)
+}
+
+/// Converts a time to a UNIX timestamp, in seconds.
+pub(crate) fn unix_timestamp(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
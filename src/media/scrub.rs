@@ -0,0 +1,354 @@
+use std::{
+    io,
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
+
+use diesel::{ExpressionMethods, QueryDsl, Queryable, RunQueryDsl};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    data::media_file::MediaFile,
+    database::{
+        self,
+        connection::DatabaseConnection,
+        schema::{
+            media::dsl::media as media_table,
+            media_verifications::dsl::media_verifications as verifications_table,
+            scrub_cursors::dsl::scrub_cursors as cursors_table,
+        },
+    },
+    media::{
+        archive,
+        base_paths::{self, base_paths, Status},
+        hash,
+        scanner::unix_timestamp,
+    },
+};
+
+/// How many media are loaded from the database at a time.
+const BATCH_SIZE: i64 = 100;
+
+/// Scrub verifies that the content of the files still matches their stored
+/// hash, to detect silent corruption, e.g. on archive disks.
+///
+/// Verifying a large base path can take hours: each run stops when its time
+/// budget is exhausted and the next one resumes from where it stopped.
+pub struct Scrub {
+    connection: DatabaseConnection,
+    bytes_per_second: Option<u64>,
+    time_budget: Option<Duration>,
+}
+
+/// This returns a new instance of the `Scrub` struct, that reads files as
+/// fast as possible and verifies all of them in a single run.
+pub fn scrub(connection: DatabaseConnection) -> Scrub {
+    Scrub {
+        connection,
+        bytes_per_second: None,
+        time_budget: None,
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The base path could not be retrieved.
+    #[error("base path error: {0}")]
+    BasePathsError(#[from] base_paths::Error),
+    /// The base path is not online, so its files cannot be verified.
+    #[error("base path is offline")]
+    Offline,
+}
+
+/// The result of verifying the file of a media.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum VerifyResult {
+    /// The content matches the stored hash. Media without a stored hash
+    /// get it stored and are considered verified.
+    Ok,
+    /// The content does not match the stored hash: the file is corrupted or
+    /// has been modified, and should be restored from a backup.
+    Mismatch,
+    /// The file does not exist.
+    Missing,
+    /// The file exists but could not be read.
+    Unreadable,
+}
+
+impl VerifyResult {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VerifyResult::Ok => "ok",
+            VerifyResult::Mismatch => "mismatch",
+            VerifyResult::Missing => "missing",
+            VerifyResult::Unreadable => "unreadable",
+        }
+    }
+
+    fn from_str(value: &str) -> Self {
+        match value {
+            "mismatch" => VerifyResult::Mismatch,
+            "missing" => VerifyResult::Missing,
+            "unreadable" => VerifyResult::Unreadable,
+            _ => VerifyResult::Ok,
+        }
+    }
+}
+
+/// The last verification of a media.
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    pub media_id: i64,
+    /// When the media was verified, as a UNIX timestamp.
+    pub verified_at: i64,
+    pub result: VerifyResult,
+}
+
+/// The result of a scrub run.
+#[derive(Debug, Default, Serialize)]
+pub struct ScrubReport {
+    /// How many media have been verified in this run.
+    pub verified: usize,
+    /// How many bytes have been read.
+    pub bytes: u64,
+    /// IDs of the media whose content does not match their hash.
+    pub mismatches: Vec<i64>,
+    /// IDs of the media whose file does not exist.
+    pub missing: Vec<i64>,
+    /// IDs of the media whose file could not be read.
+    pub unreadable: Vec<i64>,
+    /// Whether all the media of the base path have been verified. If not,
+    /// the next run resumes from where this one stopped.
+    pub complete: bool,
+}
+
+#[derive(Queryable)]
+struct VerificationRow {
+    media_id: i64,
+    verified_at: i64,
+    result: String,
+}
+
+impl Scrub {
+    /// Limits how fast files are read, so that scrubbing does not saturate
+    /// the disk.
+    pub fn with_throttle(mut self, bytes_per_second: u64) -> Self {
+        self.bytes_per_second = Some(bytes_per_second);
+        self
+    }
+
+    /// Limits how long a run can last. The file being verified when the
+    /// budget is exhausted is completed.
+    pub fn with_time_budget(mut self, time_budget: Duration) -> Self {
+        self.time_budget = Some(time_budget);
+        self
+    }
+
+    /// Verifies the files of the base path with the provided ID, in order of
+    /// media ID, starting after the last media verified by the previous
+    /// run, and records the result of each one. The progress is saved after
+    /// each batch of media, and when the run fails.
+    ///
+    /// It returns an error in case the base path does not exist or is
+    /// offline, or if there was an error on the database.
+    pub fn run(&self, base_path_id: i32) -> Result<ScrubReport, Error> {
+        let bp = base_paths(self.connection.clone()).get(base_path_id)?;
+        if base_paths::status(&bp) == Status::Offline {
+            return Err(Error::Offline);
+        }
+
+        let started = Instant::now();
+        let mut cursor = self.cursor(base_path_id)?;
+        let mut report = ScrubReport::default();
+
+        loop {
+            let files = {
+                use database::schema::media::dsl::{base_path_id as bp_id, id};
+                let conn = &mut self.connection.establish_connection()?;
+                media_table
+                    .filter(bp_id.eq(base_path_id))
+                    .filter(id.gt(cursor))
+                    .order(id.asc())
+                    .limit(BATCH_SIZE)
+                    .load::<MediaFile>(conn)?
+            };
+
+            if files.is_empty() {
+                report.complete = true;
+                break;
+            }
+
+            for file in files {
+                if self
+                    .time_budget
+                    .is_some_and(|budget| started.elapsed() >= budget)
+                {
+                    self.set_cursor(base_path_id, Some(cursor))?;
+                    return Ok(report);
+                }
+
+                let result = match self.verify(Path::new(&bp.base_path), &file, &mut report) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        // The progress is kept even though the run failed.
+                        let _ = self.set_cursor(base_path_id, Some(cursor));
+                        return Err(err);
+                    }
+                };
+                match result {
+                    VerifyResult::Ok => (),
+                    VerifyResult::Mismatch => report.mismatches.push(file.id),
+                    VerifyResult::Missing => report.missing.push(file.id),
+                    VerifyResult::Unreadable => report.unreadable.push(file.id),
+                }
+
+                report.verified += 1;
+                cursor = file.id;
+            }
+
+            // Saved after each batch, so that a run that is killed resumes
+            // close to where it stopped.
+            self.set_cursor(base_path_id, Some(cursor))?;
+        }
+
+        self.set_cursor(base_path_id, None)?;
+        Ok(report)
+    }
+
+    /// Makes the next run of the base path with the provided ID start from
+    /// its first media.
+    pub fn reset(&self, base_path_id: i32) -> Result<(), Error> {
+        self.set_cursor(base_path_id, None)
+    }
+
+    /// Returns the last verification of each media of the base path with the
+    /// provided ID that has been verified at least once, optionally only
+    /// those with the provided result, e.g. the mismatches to restore.
+    pub fn verifications(
+        &self,
+        base_path_id: i32,
+        result: Option<VerifyResult>,
+    ) -> Result<Vec<Verification>, Error> {
+        use database::schema::media::dsl::{base_path_id as bp_id, id};
+        use database::schema::media_verifications::dsl as mv;
+
+        let mut query = verifications_table
+            .inner_join(media_table)
+            .select((mv::media_id, mv::verified_at, mv::result))
+            .filter(bp_id.eq(base_path_id))
+            .order(id.asc())
+            .into_boxed();
+        if let Some(result) = result {
+            query = query.filter(mv::result.eq(result.as_str()));
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        Ok(query
+            .load::<VerificationRow>(conn)?
+            .into_iter()
+            .map(|row| Verification {
+                media_id: row.media_id,
+                verified_at: row.verified_at,
+                result: VerifyResult::from_str(&row.result),
+            })
+            .collect())
+    }
+
+    /// Hashes the file of a media, compares it with the stored hash and
+    /// records the result.
+    fn verify(
+        &self,
+        base_path: &Path,
+        file: &MediaFile,
+        report: &mut ScrubReport,
+    ) -> Result<VerifyResult, Error> {
+        let path = base_path.join(&file.relative_path);
+        let mut entry_size = None;
+        let computed = match (&file.archive_entry, self.bytes_per_second) {
+            // Entries are read at once, since they are decompressed in memory.
+            (Some(name), _) => match archive::read_entry(&path, name) {
+                Ok(content) => {
+                    entry_size = Some(content.len() as u64);
+                    Ok(hash::content_hash_of(&content))
+                }
+                Err(archive::Error::IOError(err)) => Err(err),
+                Err(archive::Error::EntryNotFound) => Err(io::ErrorKind::NotFound.into()),
+                Err(err) => Err(io::Error::other(err)),
+            },
+            (None, Some(bytes_per_second)) => hash::content_hash_throttled(&path, bytes_per_second),
+            (None, None) => hash::content_hash(&path),
+        };
+
+        let conn = &mut self.connection.establish_connection()?;
+        let result = match (computed, &file.content_hash) {
+            (Err(err), _) if err.kind() == io::ErrorKind::NotFound => VerifyResult::Missing,
+            (Err(_), _) => VerifyResult::Unreadable,
+            (Ok(computed), Some(stored)) if &computed == stored => VerifyResult::Ok,
+            (Ok(_), Some(_)) => VerifyResult::Mismatch,
+            (Ok(computed), None) => {
+                use database::schema::media::dsl::{content_hash, id};
+                diesel::update(media_table.filter(id.eq(file.id)))
+                    .set(content_hash.eq(computed))
+                    .execute(conn)?;
+                VerifyResult::Ok
+            }
+        };
+
+        if !matches!(result, VerifyResult::Missing | VerifyResult::Unreadable) {
+            report.bytes += entry_size.unwrap_or_else(|| {
+                std::fs::metadata(&path)
+                    .map(|m| m.len())
+                    .unwrap_or_default()
+            });
+        }
+
+        use database::schema::media_verifications::dsl as mv;
+        diesel::replace_into(verifications_table)
+            .values((
+                mv::media_id.eq(file.id),
+                mv::verified_at.eq(unix_timestamp(SystemTime::now())),
+                mv::result.eq(result.as_str()),
+            ))
+            .execute(conn)?;
+
+        Ok(result)
+    }
+
+    /// Returns the ID of the last media verified by the previous run that
+    /// did not complete, or `0`.
+    fn cursor(&self, base_path_id: i32) -> Result<i64, Error> {
+        use database::schema::scrub_cursors::dsl::{base_path_id as bp_id, last_media_id};
+        let conn = &mut self.connection.establish_connection()?;
+        match cursors_table
+            .select(last_media_id)
+            .filter(bp_id.eq(base_path_id))
+            .first(conn)
+        {
+            Ok(cursor) => Ok(cursor),
+            Err(diesel::NotFound) => Ok(0),
+            Err(err) => Err(Error::DatabaseError(err)),
+        }
+    }
+
+    fn set_cursor(&self, base_path_id: i32, cursor: Option<i64>) -> Result<(), Error> {
+        use database::schema::scrub_cursors::dsl::{base_path_id as bp_id, last_media_id};
+        let conn = &mut self.connection.establish_connection()?;
+        match cursor {
+            None => diesel::delete(cursors_table.filter(bp_id.eq(base_path_id))).execute(conn)?,
+            Some(cursor) => diesel::replace_into(cursors_table)
+                .values((bp_id.eq(base_path_id), last_media_id.eq(cursor)))
+                .execute(conn)?,
+        };
+
+        Ok(())
+    }
+}
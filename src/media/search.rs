@@ -0,0 +1,857 @@
+use std::collections::HashMap;
+
+use diesel::{
+    dsl::{not, sql},
+    sql_function,
+    sql_types::{Bool, Double, Text},
+    sqlite::Sqlite,
+    BoolExpressionMethods, BoxableExpression, ExpressionMethods, QueryDsl, RunQueryDsl,
+};
+use thiserror::Error;
+
+use crate::database::{self, connection::DatabaseConnection, schema::media};
+
+type Condition = Box<dyn BoxableExpression<media::table, Sqlite, SqlType = Bool>>;
+
+/// How deeply negations and parentheses can be nested, so that parsing and
+/// compiling a search cannot overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+sql_function!(fn normalize_name(name: Text) -> Text);
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The tags could not be loaded to resolve their names.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The search is empty.
+    #[error("empty search")]
+    Empty,
+    /// A token is not allowed where it appears, e.g. `OR` at the start.
+    #[error("unexpected `{token}` at position {position}")]
+    Unexpected { position: usize, token: String },
+    /// The search ends where a term was expected, e.g. after `AND`.
+    #[error("unexpected end of search at position {position}")]
+    UnexpectedEnd { position: usize },
+    /// A quote is never closed.
+    #[error("unterminated quote at position {position}")]
+    UnterminatedQuote { position: usize },
+    /// A parenthesis is never closed.
+    #[error("unclosed parenthesis at position {position}")]
+    UnclosedParenthesis { position: usize },
+    /// A qualifier is not followed by a value, e.g. `mark:`.
+    #[error("missing value at position {position}")]
+    MissingValue { position: usize },
+    /// The value of a field is invalid, e.g. `type:photo` or `mark:>=x`.
+    #[error("invalid value `{value}` at position {position}")]
+    InvalidValue { position: usize, value: String },
+    /// Negations or parentheses are nested too deeply.
+    #[error("search nested too deeply at position {position}")]
+    TooDeep { position: usize },
+    /// No tag has this name, in the category if provided.
+    #[error("unknown tag `{name}` at position {position}")]
+    UnknownTag { position: usize, name: String },
+}
+
+impl Error {
+    /// Returns the position of the offending token, counted in characters
+    /// from the start of the search, if the error is about the search text.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            Error::Unexpected { position, .. }
+            | Error::UnexpectedEnd { position }
+            | Error::UnterminatedQuote { position }
+            | Error::UnclosedParenthesis { position }
+            | Error::MissingValue { position }
+            | Error::InvalidValue { position, .. }
+            | Error::TooDeep { position }
+            | Error::UnknownTag { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+}
+
+/// A numeric field of the media that can be compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Mark,
+    Width,
+    Height,
+    /// The size in kB.
+    Size,
+}
+
+impl Field {
+    fn sql(&self) -> &'static str {
+        match self {
+            Field::Mark => "media.mark",
+            Field::Width => "media.width",
+            Field::Height => "media.height",
+            Field::Size => "media.size",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Comparison {
+    fn sql(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+        }
+    }
+}
+
+/// A parsed search expression. `position` is where the term starts in the
+/// search, counted in characters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Media tagged with a tag with this name, e.g. `beach`, optionally in
+    /// the category with this name, e.g. `Person:alice`.
+    Tag {
+        position: usize,
+        category: Option<String>,
+        name: String,
+    },
+    /// Media whose field compares to the value, e.g. `mark:>=7`.
+    Compare {
+        position: usize,
+        field: Field,
+        comparison: Comparison,
+        value: f64,
+    },
+    /// Media of this type, e.g. `type:video`.
+    Type {
+        position: usize,
+        media_type: String,
+    },
+    /// Media whose relative path matches the glob, e.g. `path:2023/*`.
+    Path {
+        position: usize,
+        glob: String,
+    },
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+/// A search whose tag names have been resolved to IDs.
+pub(crate) struct Resolved {
+    expr: Expr,
+    /// The IDs of the tags matching each tag term, by position.
+    tag_ids: HashMap<usize, Vec<i32>>,
+}
+
+impl Resolved {
+    /// Compiles the search to a condition on the media table.
+    pub(crate) fn condition(&self) -> Condition {
+        compile(&self.expr, &self.tag_ids)
+    }
+}
+
+/// Parses a search, without resolving the names of the tags.
+///
+/// Terms are separated by spaces and match when all of them match, unless
+/// `OR` is used; `AND` can also be written explicitly. `NOT` or `-` before
+/// a term negates it and parentheses group terms, e.g.
+/// `(beach OR sea) AND Person:alice -blurry mark:>=7 type:video`.
+///
+/// A term is a tag name, optionally qualified by the name of its category:
+/// without a category, it matches the tags with this name in any category.
+/// It can also be one of the fields `mark`, `width`, `height` and `size`
+/// (in kB) followed by an optional comparison (`>`, `>=`, `<`, `<=`) and a
+/// number, `type` followed by `image`, `video` or `sound`, or `path`
+/// followed by a glob. Names and values containing spaces can be quoted,
+/// e.g. `Place:"New York"`; a quoted qualifier is always a category, even
+/// if it is named like a field.
+///
+/// It returns an error pointing at the offending token in case the search
+/// is invalid.
+pub fn parse(search: impl AsRef<str>) -> Result<Expr, Error> {
+    let chars: Vec<char> = search.as_ref().chars().collect();
+    let tokens = tokenize(&chars)?;
+    if tokens.is_empty() {
+        return Err(Error::Empty);
+    }
+
+    let mut parser = Parser {
+        tokens,
+        next: 0,
+        end: chars.len(),
+        depth: 0,
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(token.unexpected()),
+    }
+}
+
+/// Parses a search and resolves the names of its tags, ignoring the case.
+///
+/// Only the tags named like a term of the search are loaded.
+pub(crate) fn resolve(
+    connection: &DatabaseConnection,
+    search: impl AsRef<str>,
+) -> Result<Resolved, Error> {
+    let expr = parse(search)?;
+    let mut terms = vec![];
+    collect_tags(&expr, &mut terms);
+
+    let mut tag_ids = HashMap::new();
+    if !terms.is_empty() {
+        use crate::database::schema::{tag_categories, tags};
+
+        let names: Vec<String> = terms.iter().map(|(_, _, name)| normalize(name)).collect();
+        let conn = &mut connection.establish_connection()?;
+        normalize_name::register_impl(conn, |name: String| normalize(&name))?;
+        let named: Vec<(i32, String, String)> = tags::table
+            .inner_join(tag_categories::table)
+            .filter(normalize_name(tags::name).eq_any(&names))
+            .select((
+                tags::id,
+                normalize_name(tags::name),
+                normalize_name(tag_categories::name),
+            ))
+            .load(conn)?;
+
+        for (position, category, name) in terms {
+            let (wanted_category, wanted_name) = (category.map(normalize), normalize(name));
+            let ids: Vec<i32> = named
+                .iter()
+                .filter(|(_, name, _)| *name == wanted_name)
+                .filter(|(_, _, category)| {
+                    wanted_category
+                        .as_ref()
+                        .is_none_or(|wanted| wanted == category)
+                })
+                .map(|(id, _, _)| *id)
+                .collect();
+
+            if ids.is_empty() {
+                return Err(Error::UnknownTag {
+                    position,
+                    name: name.to_string(),
+                });
+            }
+            tag_ids.insert(position, ids);
+        }
+    }
+
+    Ok(Resolved { expr, tag_ids })
+}
+
+/// Names are compared ignoring the case, and spaces can be typed as `_`,
+/// as for [`Tags::search_by_name`](crate::tags::tags::Tags::search_by_name).
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().replace(' ', "_")
+}
+
+fn collect_tags<'a>(expr: &'a Expr, terms: &mut Vec<(usize, Option<&'a str>, &'a str)>) {
+    match expr {
+        Expr::Tag {
+            position,
+            category,
+            name,
+        } => terms.push((*position, category.as_deref(), name)),
+        Expr::Not(inner) => collect_tags(inner, terms),
+        Expr::And(exprs) | Expr::Or(exprs) => {
+            exprs.iter().for_each(|e| collect_tags(e, terms));
+        }
+        _ => (),
+    }
+}
+
+fn compile(expr: &Expr, tag_ids: &HashMap<usize, Vec<i32>>) -> Condition {
+    use crate::database::schema::{
+        media::dsl::{id, media_type as m_type},
+        media_tags::dsl::{media_id, media_tags, tag_id},
+    };
+
+    match expr {
+        Expr::Tag { position, .. } => {
+            let ids = tag_ids.get(position).cloned().unwrap_or_default();
+            Box::new(id.eq_any(media_tags.select(media_id).filter(tag_id.eq_any(ids))))
+        }
+        // The comparison is false rather than NULL for media without a
+        // value, so that they match when it is negated, e.g. `-mark:>=7`
+        // matches the media that have never been rated.
+        Expr::Compare {
+            field,
+            comparison,
+            value,
+            ..
+        } => Box::new(
+            sql::<Bool>(&format!(
+                "({0} IS NOT NULL AND {0} {1} ",
+                field.sql(),
+                comparison.sql()
+            ))
+            .bind::<Double, _>(*value)
+            .sql(")"),
+        ),
+        Expr::Type { media_type, .. } => Box::new(m_type.eq(media_type.clone())),
+        Expr::Path { glob, .. } => {
+            Box::new(sql::<Bool>("media.relative_path GLOB ").bind::<Text, _>(glob.clone()))
+        }
+        Expr::Not(inner) => Box::new(not(compile(inner, tag_ids))),
+        Expr::And(exprs) => exprs
+            .iter()
+            .map(|e| compile(e, tag_ids))
+            .reduce(|a, b| Box::new(a.and(b)))
+            .unwrap_or_else(|| Box::new(sql::<Bool>("1"))),
+        Expr::Or(exprs) => exprs
+            .iter()
+            .map(|e| compile(e, tag_ids))
+            .reduce(|a, b| Box::new(a.or(b)))
+            .unwrap_or_else(|| Box::new(sql::<Bool>("0"))),
+    }
+}
+
+#[derive(Debug)]
+enum TokenKind {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Term {
+        qualifier: Option<(String, bool)>,
+        value: String,
+        value_position: usize,
+    },
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+    text: String,
+}
+
+impl Token {
+    fn unexpected(&self) -> Error {
+        Error::Unexpected {
+            position: self.position,
+            token: self.text.clone(),
+        }
+    }
+}
+
+fn tokenize(chars: &[char]) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                TokenKind::LeftParen
+            }
+            ')' => {
+                i += 1;
+                TokenKind::RightParen
+            }
+            '-' if chars
+                .get(i + 1)
+                .is_some_and(|next| !next.is_whitespace() && *next != ')') =>
+            {
+                i += 1;
+                TokenKind::Not
+            }
+            _ => {
+                let (first, quoted) = read_part(chars, &mut i, true)?;
+                if chars.get(i) == Some(&':') {
+                    i += 1;
+                    let value_position = i;
+                    let (value, _) = read_part(chars, &mut i, false)?;
+                    if value.is_empty() {
+                        return Err(Error::MissingValue {
+                            position: value_position,
+                        });
+                    }
+
+                    TokenKind::Term {
+                        qualifier: Some((first, quoted)),
+                        value,
+                        value_position,
+                    }
+                } else {
+                    match first.as_str() {
+                        "AND" if !quoted => TokenKind::And,
+                        "OR" if !quoted => TokenKind::Or,
+                        "NOT" if !quoted => TokenKind::Not,
+                        _ if first.is_empty() && !quoted => {
+                            return Err(Error::Unexpected {
+                                position: start,
+                                token: c.to_string(),
+                            })
+                        }
+                        _ => TokenKind::Term {
+                            qualifier: None,
+                            value: first,
+                            value_position: start,
+                        },
+                    }
+                }
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            position: start,
+            text: chars[start..i].iter().collect(),
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Reads a quoted string, or a bare word up to a space, a parenthesis, a
+/// quote and, for the qualifier, a colon.
+fn read_part(chars: &[char], i: &mut usize, is_qualifier: bool) -> Result<(String, bool), Error> {
+    if chars.get(*i) == Some(&'"') {
+        let start = *i;
+        let end = (start + 1..chars.len())
+            .find(|&j| chars[j] == '"')
+            .ok_or(Error::UnterminatedQuote { position: start })?;
+        *i = end + 1;
+        return Ok((chars[start + 1..end].iter().collect(), true));
+    }
+
+    let start = *i;
+    while let Some(&c) = chars.get(*i) {
+        if c.is_whitespace() || matches!(c, '(' | ')' | '"') || (is_qualifier && c == ':') {
+            break;
+        }
+        *i += 1;
+    }
+
+    Ok((chars[start..*i].iter().collect(), false))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    /// The length of the search, for errors at its end.
+    end: usize,
+    /// How many negations and parentheses enclose the next token.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut exprs = vec![self.and()?];
+        while matches!(self.peek().map(|t| &t.kind), Some(TokenKind::Or)) {
+            self.next += 1;
+            exprs.push(self.and()?);
+        }
+
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::Or(exprs),
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut exprs = vec![self.unary()?];
+        loop {
+            match self.peek().map(|t| &t.kind) {
+                Some(TokenKind::And) => self.next += 1,
+                Some(TokenKind::LeftParen | TokenKind::Not | TokenKind::Term { .. }) => (),
+                _ => break,
+            }
+            exprs.push(self.unary()?);
+        }
+
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::And(exprs),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        if let Some(
+            token @ Token {
+                kind: TokenKind::Not,
+                ..
+            },
+        ) = self.peek()
+        {
+            let position = token.position;
+            self.next += 1;
+            let expr = self.nested(position, Self::unary)?;
+            return Ok(Expr::Not(Box::new(expr)));
+        }
+
+        self.primary()
+    }
+
+    /// Parses what a negation or a parenthesis at the provided position
+    /// encloses, or returns an error if it is nested too deeply.
+    fn nested(
+        &mut self,
+        position: usize,
+        parse: fn(&mut Self) -> Result<Expr, Error>,
+    ) -> Result<Expr, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(Error::TooDeep { position });
+        }
+
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn primary(&mut self) -> Result<Expr, Error> {
+        let Some(token) = self.tokens.get(self.next) else {
+            return Err(Error::UnexpectedEnd { position: self.end });
+        };
+        self.next += 1;
+
+        match &token.kind {
+            TokenKind::LeftParen => {
+                let position = token.position;
+                let expr = self.nested(position, Self::or)?;
+                match self.peek().map(|t| &t.kind) {
+                    Some(TokenKind::RightParen) => {
+                        self.next += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(Error::UnclosedParenthesis { position }),
+                }
+            }
+            TokenKind::Term {
+                qualifier,
+                value,
+                value_position,
+            } => term(token.position, qualifier, value, *value_position),
+            _ => Err(token.unexpected()),
+        }
+    }
+}
+
+fn term(
+    position: usize,
+    qualifier: &Option<(String, bool)>,
+    value: &str,
+    value_position: usize,
+) -> Result<Expr, Error> {
+    let invalid = || Error::InvalidValue {
+        position: value_position,
+        value: value.to_string(),
+    };
+
+    let field = match qualifier {
+        None => {
+            return Ok(Expr::Tag {
+                position,
+                category: None,
+                name: value.to_string(),
+            })
+        }
+        Some((qualifier, true)) => {
+            return Ok(Expr::Tag {
+                position,
+                category: Some(qualifier.clone()),
+                name: value.to_string(),
+            })
+        }
+        Some((qualifier, false)) => qualifier.to_lowercase(),
+    };
+
+    let field = match field.as_str() {
+        "mark" => Field::Mark,
+        "width" => Field::Width,
+        "height" => Field::Height,
+        "size" => Field::Size,
+        "type" => {
+            let media_type = value.to_lowercase();
+            return match media_type.as_str() {
+                "image" | "video" | "sound" => Ok(Expr::Type {
+                    position,
+                    media_type,
+                }),
+                _ => Err(invalid()),
+            };
+        }
+        "path" => {
+            return Ok(Expr::Path {
+                position,
+                glob: value.trim_matches('/').to_string(),
+            })
+        }
+        _ => {
+            return Ok(Expr::Tag {
+                position,
+                category: qualifier.as_ref().map(|(q, _)| q.clone()),
+                name: value.to_string(),
+            })
+        }
+    };
+
+    let (comparison, number) = [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+    ]
+    .into_iter()
+    .find_map(|(prefix, comparison)| value.strip_prefix(prefix).map(|rest| (comparison, rest)))
+    .unwrap_or((Comparison::Equal, value));
+
+    let value = match field {
+        Field::Size => number.parse::<f64>().ok().filter(|n| n.is_finite()),
+        _ => number.parse::<i16>().ok().map(f64::from),
+    }
+    .ok_or_else(invalid)?;
+
+    Ok(Expr::Compare {
+        position,
+        field,
+        comparison,
+        value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(position: usize, category: Option<&str>, name: &str) -> Expr {
+        Expr::Tag {
+            position,
+            category: category.map(str::to_string),
+            name: name.to_string(),
+        }
+    }
+
+    fn error_position(search: &str) -> Option<usize> {
+        parse(search).unwrap_err().position()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("a OR b c").unwrap(),
+            Expr::Or(vec![
+                tag(0, None, "a"),
+                Expr::And(vec![tag(5, None, "b"), tag(7, None, "c")]),
+            ])
+        );
+        assert_eq!(
+            parse("a AND b OR c").unwrap(),
+            Expr::Or(vec![
+                Expr::And(vec![tag(0, None, "a"), tag(6, None, "b")]),
+                tag(11, None, "c"),
+            ])
+        );
+    }
+
+    #[test]
+    fn parentheses_group_terms() {
+        assert_eq!(
+            parse("(a OR b) c").unwrap(),
+            Expr::And(vec![
+                Expr::Or(vec![tag(1, None, "a"), tag(6, None, "b")]),
+                tag(9, None, "c"),
+            ])
+        );
+    }
+
+    #[test]
+    fn minus_and_not_negate() {
+        let expected = Expr::And(vec![
+            tag(0, None, "a"),
+            Expr::Not(Box::new(tag(3, None, "b"))),
+        ]);
+        assert_eq!(parse("a -b").unwrap(), expected);
+        assert_eq!(
+            parse("a NOT b").unwrap(),
+            Expr::And(vec![
+                tag(0, None, "a"),
+                Expr::Not(Box::new(tag(6, None, "b"))),
+            ])
+        );
+        assert_eq!(
+            parse("-(a OR b)").unwrap(),
+            Expr::Not(Box::new(Expr::Or(vec![
+                tag(2, None, "a"),
+                tag(7, None, "b"),
+            ])))
+        );
+        // A dash inside a name does not negate.
+        assert_eq!(parse("t-shirt").unwrap(), tag(0, None, "t-shirt"));
+    }
+
+    #[test]
+    fn quotes_keep_spaces_and_keywords() {
+        assert_eq!(parse("\"New York\"").unwrap(), tag(0, None, "New York"));
+        assert_eq!(parse("\"OR\"").unwrap(), tag(0, None, "OR"));
+        assert_eq!(
+            parse("Place:\"New York\"").unwrap(),
+            tag(0, Some("Place"), "New York")
+        );
+        assert_eq!(
+            parse("\"Cat egory\":x").unwrap(),
+            tag(0, Some("Cat egory"), "x")
+        );
+    }
+
+    #[test]
+    fn quoted_qualifier_is_a_category() {
+        assert_eq!(parse("\"mark\":7").unwrap(), tag(0, Some("mark"), "7"));
+        assert_eq!(
+            parse("Person:alice").unwrap(),
+            tag(0, Some("Person"), "alice")
+        );
+    }
+
+    #[test]
+    fn fields_are_compared() {
+        assert_eq!(
+            parse("mark:>=7").unwrap(),
+            Expr::Compare {
+                position: 0,
+                field: Field::Mark,
+                comparison: Comparison::GreaterOrEqual,
+                value: 7.0,
+            }
+        );
+        assert_eq!(
+            parse("Size:<1.5").unwrap(),
+            Expr::Compare {
+                position: 0,
+                field: Field::Size,
+                comparison: Comparison::Less,
+                value: 1.5,
+            }
+        );
+        assert_eq!(
+            parse("width:800").unwrap(),
+            Expr::Compare {
+                position: 0,
+                field: Field::Width,
+                comparison: Comparison::Equal,
+                value: 800.0,
+            }
+        );
+        assert_eq!(
+            parse("type:Video").unwrap(),
+            Expr::Type {
+                position: 0,
+                media_type: "video".into(),
+            }
+        );
+        assert_eq!(
+            parse("path:/2023/*").unwrap(),
+            Expr::Path {
+                position: 0,
+                glob: "2023/*".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_values_are_reported() {
+        assert!(matches!(
+            parse("a mark:>=x"),
+            Err(Error::InvalidValue { position: 7, ref value }) if value == ">=x"
+        ));
+        assert!(matches!(
+            parse("type:photo"),
+            Err(Error::InvalidValue { position: 5, .. })
+        ));
+        assert!(matches!(
+            parse("a mark:"),
+            Err(Error::MissingValue { position: 7 })
+        ));
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_token() {
+        assert!(matches!(parse("   "), Err(Error::Empty)));
+        assert!(matches!(
+            parse("a (b c"),
+            Err(Error::UnclosedParenthesis { position: 2 })
+        ));
+        assert!(matches!(
+            parse("a AND"),
+            Err(Error::UnexpectedEnd { position: 5 })
+        ));
+        assert!(matches!(
+            parse("a OR"),
+            Err(Error::UnexpectedEnd { position: 4 })
+        ));
+        assert!(matches!(
+            parse("a AND )"),
+            Err(Error::Unexpected { position: 6, ref token }) if token == ")"
+        ));
+        assert!(matches!(
+            parse("OR a"),
+            Err(Error::Unexpected { position: 0, ref token }) if token == "OR"
+        ));
+        assert!(matches!(
+            parse("a b)"),
+            Err(Error::Unexpected { position: 3, ref token }) if token == ")"
+        ));
+        assert!(matches!(
+            parse("a \"b c"),
+            Err(Error::UnterminatedQuote { position: 2 })
+        ));
+    }
+
+    #[test]
+    fn positions_are_counted_in_characters() {
+        assert_eq!(error_position("été AND"), Some(7));
+        assert_eq!(error_position("ça mark:>=x"), Some(8));
+        assert_eq!(error_position("(été"), Some(0));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = format!("{}a{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(parse(nested).is_ok());
+        assert!(parse(format!("{}a", "-".repeat(MAX_DEPTH))).is_ok());
+
+        assert!(matches!(
+            parse(format!("b {}a", "-".repeat(100_000))),
+            Err(Error::TooDeep { position }) if position == 2 + MAX_DEPTH
+        ));
+        assert!(matches!(
+            parse(format!("{}a", "NOT ".repeat(100_000))),
+            Err(Error::TooDeep { position }) if position == 4 * MAX_DEPTH
+        ));
+        assert!(matches!(
+            parse(format!("{}a", "(".repeat(100_000))),
+            Err(Error::TooDeep { position }) if position == MAX_DEPTH
+        ));
+    }
+}
@@ -0,0 +1,415 @@
+use diesel::{
+    sql_query,
+    sql_types::{BigInt, Double, Integer, SmallInt, Text},
+    Connection, QueryableByName, RunQueryDsl, SqliteConnection,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    data::media_file::MediaType,
+    database::{self, connection::DatabaseConnection},
+};
+
+/// The width of the buckets of the dimension histograms of a [`Report`], in
+/// pixels.
+const DEFAULT_BUCKET_SIZE: i64 = 500;
+
+/// Statistics computes counts and sizes over the whole library, e.g. how many
+/// videos are untagged or which tags are the most used.
+///
+/// Each statistic is computed by the database with a single aggregate query,
+/// without loading the media. Sizes are in kB, as the size of media.
+pub struct Statistics {
+    connection: DatabaseConnection,
+}
+
+pub fn statistics(connection: DatabaseConnection) -> Statistics {
+    Statistics { connection }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The provided limit is <= 0.
+    #[error("invalid limit")]
+    InvalidLimit,
+    /// The provided bucket size is <= 0.
+    #[error("invalid bucket size")]
+    InvalidBucketSize,
+}
+
+/// The totals of the whole library.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct Summary {
+    /// How many media there are.
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    /// The total size of the media.
+    #[diesel(sql_type = Double)]
+    pub size: f64,
+    /// How many media have no tags.
+    #[diesel(sql_type = BigInt)]
+    pub untagged: i64,
+    /// How many media have never been rated, i.e. have no mark.
+    #[diesel(sql_type = BigInt)]
+    pub unrated: i64,
+    /// How many media are missing from disk.
+    #[diesel(sql_type = BigInt)]
+    pub missing: i64,
+}
+
+/// The statistics of the media of a type.
+#[derive(Debug, Serialize)]
+pub struct MediaTypeStatistics {
+    pub media_type: MediaType,
+    /// How many media of this type there are.
+    pub count: i64,
+    /// Their total size.
+    pub size: f64,
+    /// How many of them have no tags.
+    pub untagged: i64,
+    /// How many of them have never been rated.
+    pub unrated: i64,
+}
+
+#[derive(QueryableByName)]
+struct MediaTypeRow {
+    #[diesel(sql_type = Text)]
+    media_type: String,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+    #[diesel(sql_type = Double)]
+    size: f64,
+    #[diesel(sql_type = BigInt)]
+    untagged: i64,
+    #[diesel(sql_type = BigInt)]
+    unrated: i64,
+}
+
+/// The statistics of the media of a base path.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct BasePathStatistics {
+    #[diesel(sql_type = Integer)]
+    pub base_path_id: i32,
+    #[diesel(sql_type = Text)]
+    pub base_path: String,
+    /// How many media the base path contains.
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    /// Their total size.
+    #[diesel(sql_type = Double)]
+    pub size: f64,
+    /// How many of them are missing from disk.
+    #[diesel(sql_type = BigInt)]
+    pub missing: i64,
+}
+
+/// The statistics of the media tagged with a tag.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct TagStatistics {
+    #[diesel(sql_type = Integer)]
+    pub tag_id: i32,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(sql_type = Integer)]
+    pub category_id: i32,
+    /// How many media are tagged with it.
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    /// Their total size.
+    #[diesel(sql_type = Double)]
+    pub size: f64,
+}
+
+/// The statistics of the media tagged with the tags of a category.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct CategoryStatistics {
+    #[diesel(sql_type = Integer)]
+    pub category_id: i32,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    /// How many tags belong to the category.
+    #[diesel(sql_type = BigInt)]
+    pub tags: i64,
+    /// How many media are tagged with at least one of its tags.
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    /// Their total size.
+    #[diesel(sql_type = Double)]
+    pub size: f64,
+}
+
+/// How many media have a mark.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct MarkCount {
+    #[diesel(sql_type = SmallInt)]
+    pub mark: i16,
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+}
+
+/// How many media have a width or height in `start..end`, in pixels.
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    pub start: i64,
+    pub end: i64,
+    pub count: i64,
+}
+
+#[derive(QueryableByName)]
+struct BucketRow {
+    #[diesel(sql_type = BigInt)]
+    start: i64,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+/// The histograms of the widths and heights of the media. Empty buckets are
+/// left out.
+#[derive(Debug, Serialize)]
+pub struct DimensionHistograms {
+    pub width: Vec<Bucket>,
+    pub height: Vec<Bucket>,
+    /// How many media have no known dimensions, e.g. sounds.
+    pub unknown: i64,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+/// All the statistics of the library, computed at the same time, e.g. for a
+/// dashboard.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub summary: Summary,
+    pub media_types: Vec<MediaTypeStatistics>,
+    pub base_paths: Vec<BasePathStatistics>,
+    pub tags: Vec<TagStatistics>,
+    pub categories: Vec<CategoryStatistics>,
+    pub marks: Vec<MarkCount>,
+    /// With buckets of 500 pixels.
+    pub dimensions: DimensionHistograms,
+}
+
+/// Whether the media of the query has no tags.
+const UNTAGGED: &str = "NOT EXISTS (SELECT 1 FROM media_tags WHERE media_tags.media_id = media.id)";
+
+impl Statistics {
+    /// Returns the totals of the whole library.
+    ///
+    /// It returns an error in case there was an error on the database.
+    pub fn summary(&self) -> Result<Summary, Error> {
+        let conn = &mut self.connection.establish_connection()?;
+        summary(conn)
+    }
+
+    /// Returns the statistics of each media type, from the one with the most
+    /// media.
+    ///
+    /// It returns an error in case there was an error on the database.
+    pub fn media_types(&self) -> Result<Vec<MediaTypeStatistics>, Error> {
+        let conn = &mut self.connection.establish_connection()?;
+        media_types(conn)
+    }
+
+    /// Returns the statistics of each base path, ordered by path.
+    ///
+    /// It returns an error in case there was an error on the database.
+    pub fn base_paths(&self) -> Result<Vec<BasePathStatistics>, Error> {
+        let conn = &mut self.connection.establish_connection()?;
+        base_paths(conn)
+    }
+
+    /// Returns the statistics of at most `limit` tags, or all of them if
+    /// `None`, from the most used one.
+    ///
+    /// It returns an error in case the limit is invalid or if there was an
+    /// error on the database.
+    pub fn tags(&self, limit: Option<i64>) -> Result<Vec<TagStatistics>, Error> {
+        if limit.is_some_and(|limit| limit <= 0) {
+            return Err(Error::InvalidLimit);
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        tags(conn, limit)
+    }
+
+    /// Returns the statistics of each tag category, from the one tagging the
+    /// most media.
+    ///
+    /// It returns an error in case there was an error on the database.
+    pub fn categories(&self) -> Result<Vec<CategoryStatistics>, Error> {
+        let conn = &mut self.connection.establish_connection()?;
+        categories(conn)
+    }
+
+    /// Returns how many media have each mark, from the lowest. Marks without
+    /// media are left out, as well as media that have never been rated: see
+    /// [`Summary::unrated`].
+    ///
+    /// It returns an error in case there was an error on the database.
+    pub fn marks(&self) -> Result<Vec<MarkCount>, Error> {
+        let conn = &mut self.connection.establish_connection()?;
+        marks(conn)
+    }
+
+    /// Returns the histograms of the widths and heights of the media, with
+    /// buckets of `bucket_size` pixels, e.g. `0..500`, `500..1000`, etc.
+    ///
+    /// It returns an error in case the bucket size is invalid or if there was
+    /// an error on the database.
+    pub fn dimensions(&self, bucket_size: i64) -> Result<DimensionHistograms, Error> {
+        if bucket_size <= 0 {
+            return Err(Error::InvalidBucketSize);
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        dimensions(conn, bucket_size)
+    }
+
+    /// Returns all the statistics, computed in a single transaction so that
+    /// they are consistent with each other.
+    ///
+    /// It returns an error in case there was an error on the database.
+    pub fn report(&self) -> Result<Report, Error> {
+        let conn = &mut self.connection.establish_connection()?;
+        conn.transaction(|conn| {
+            Ok(Report {
+                summary: summary(conn)?,
+                media_types: media_types(conn)?,
+                base_paths: base_paths(conn)?,
+                tags: tags(conn, None)?,
+                categories: categories(conn)?,
+                marks: marks(conn)?,
+                dimensions: dimensions(conn, DEFAULT_BUCKET_SIZE)?,
+            })
+        })
+    }
+}
+
+fn summary(conn: &mut SqliteConnection) -> Result<Summary, Error> {
+    sql_query(format!(
+        "SELECT COUNT(*) AS count, COALESCE(SUM(size), 0.0) AS size, \
+         COALESCE(SUM({UNTAGGED}), 0) AS untagged, \
+         COALESCE(SUM(mark IS NULL), 0) AS unrated, \
+         COALESCE(SUM(missing), 0) AS missing \
+         FROM media"
+    ))
+    .get_result(conn)
+    .map_err(Error::DatabaseError)
+}
+
+fn media_types(conn: &mut SqliteConnection) -> Result<Vec<MediaTypeStatistics>, Error> {
+    let rows = sql_query(format!(
+        "SELECT media_type, COUNT(*) AS count, SUM(size) AS size, \
+         SUM({UNTAGGED}) AS untagged, SUM(mark IS NULL) AS unrated \
+         FROM media GROUP BY media_type ORDER BY count DESC, media_type"
+    ))
+    .load::<MediaTypeRow>(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| MediaTypeStatistics {
+            media_type: row.media_type.into(),
+            count: row.count,
+            size: row.size,
+            untagged: row.untagged,
+            unrated: row.unrated,
+        })
+        .collect())
+}
+
+fn base_paths(conn: &mut SqliteConnection) -> Result<Vec<BasePathStatistics>, Error> {
+    sql_query(
+        "SELECT base_paths.id AS base_path_id, base_paths.base_path, \
+         COUNT(media.id) AS count, COALESCE(SUM(media.size), 0.0) AS size, \
+         COALESCE(SUM(media.missing), 0) AS missing \
+         FROM base_paths LEFT JOIN media ON media.base_path_id = base_paths.id \
+         GROUP BY base_paths.id ORDER BY base_paths.base_path",
+    )
+    .load(conn)
+    .map_err(Error::DatabaseError)
+}
+
+fn tags(conn: &mut SqliteConnection, limit: Option<i64>) -> Result<Vec<TagStatistics>, Error> {
+    sql_query(
+        "SELECT tags.id AS tag_id, tags.name, tags.category_id, \
+         COUNT(media.id) AS count, COALESCE(SUM(media.size), 0.0) AS size \
+         FROM tags LEFT JOIN media_tags ON media_tags.tag_id = tags.id \
+         LEFT JOIN media ON media.id = media_tags.media_id \
+         GROUP BY tags.id ORDER BY count DESC, tags.name LIMIT ?",
+    )
+    .bind::<BigInt, _>(limit.unwrap_or(-1))
+    .load(conn)
+    .map_err(Error::DatabaseError)
+}
+
+fn categories(conn: &mut SqliteConnection) -> Result<Vec<CategoryStatistics>, Error> {
+    // A media tagged with several tags of a category is only counted once.
+    sql_query(
+        "SELECT tag_categories.id AS category_id, tag_categories.name, \
+         (SELECT COUNT(*) FROM tags WHERE tags.category_id = tag_categories.id) AS tags, \
+         COUNT(media.id) AS count, COALESCE(SUM(media.size), 0.0) AS size \
+         FROM tag_categories LEFT JOIN media ON media.id IN ( \
+             SELECT media_tags.media_id FROM media_tags \
+             JOIN tags ON tags.id = media_tags.tag_id \
+             WHERE tags.category_id = tag_categories.id) \
+         GROUP BY tag_categories.id ORDER BY count DESC, tag_categories.name",
+    )
+    .load(conn)
+    .map_err(Error::DatabaseError)
+}
+
+fn marks(conn: &mut SqliteConnection) -> Result<Vec<MarkCount>, Error> {
+    sql_query(
+        "SELECT mark, COUNT(*) AS count FROM media \
+         WHERE mark IS NOT NULL GROUP BY mark ORDER BY mark",
+    )
+    .load(conn)
+    .map_err(Error::DatabaseError)
+}
+
+fn dimensions(conn: &mut SqliteConnection, bucket_size: i64) -> Result<DimensionHistograms, Error> {
+    let mut histogram = |column: &str| -> Result<Vec<Bucket>, Error> {
+        let rows = sql_query(format!(
+            "SELECT ({column} / ?) * ? AS start, COUNT(*) AS count FROM media \
+             WHERE {column} IS NOT NULL GROUP BY start ORDER BY start"
+        ))
+        .bind::<BigInt, _>(bucket_size)
+        .bind::<BigInt, _>(bucket_size)
+        .load::<BucketRow>(conn)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Bucket {
+                start: row.start,
+                end: row.start + bucket_size,
+                count: row.count,
+            })
+            .collect())
+    };
+
+    let width = histogram("width")?;
+    let height = histogram("height")?;
+    let unknown =
+        sql_query("SELECT COUNT(*) AS count FROM media WHERE width IS NULL OR height IS NULL")
+            .get_result::<Count>(conn)?
+            .count;
+
+    Ok(DimensionHistograms {
+        width,
+        height,
+        unknown,
+    })
+}
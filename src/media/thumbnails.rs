@@ -0,0 +1,487 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use diesel::{Connection, ExpressionMethods, Insertable, QueryDsl, Queryable, RunQueryDsl};
+use raster::{error::RasterError, Image, TransformMode};
+use thiserror::Error;
+
+use crate::{
+    data::media_file::{MediaFile, MediaType},
+    database::{
+        self,
+        connection::DatabaseConnection,
+        schema::thumbnails::{self, dsl::thumbnails as thumbnails_table},
+    },
+    media::{
+        media::{self, Media},
+        scanner::unix_timestamp,
+    },
+};
+
+const THUMBNAILS_DIRECTORY_NAME: &str = "thumbnails";
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 2048;
+const DEFAULT_MAX_CACHE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Thumbnails renders previews of images and keeps them in a cache
+/// directory next to the database.
+///
+/// Each thumbnail is named after the content hash of its image, so that
+/// duplicates share the same file, and is rendered again whenever the size,
+/// modification time or hash of the image change. When the cache grows
+/// beyond its maximum size, the least recently used thumbnails are evicted.
+pub struct Thumbnails {
+    connection: DatabaseConnection,
+    max_cache_size: u64,
+}
+
+/// This returns a new instance of the `Thumbnails` struct, with a cache
+/// limited to 512 MiB.
+pub fn thumbnails(connection: DatabaseConnection) -> Thumbnails {
+    Thumbnails {
+        connection,
+        max_cache_size: DEFAULT_MAX_CACHE_SIZE,
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// The media could not be retrieved.
+    #[error("media error: {0}")]
+    MediaError(Box<media::Error>),
+    /// The provided size is invalid, i.e. it is < 16 or > 2048.
+    #[error("invalid size")]
+    InvalidSize,
+    /// Thumbnails can only be rendered for media of type `Image`.
+    #[error("not an image")]
+    NotAnImage,
+    /// Thumbnails cannot be rendered for images stored inside an archive,
+    /// since decoding requires a file on disk.
+    #[error("not supported for archive entries")]
+    ArchiveEntry,
+    /// The database is not a file on this computer, so there is no directory
+    /// where to store the thumbnails.
+    #[error("no cache directory")]
+    NoCacheDirectory,
+    /// The image or the cache directory could not be read or written.
+    #[error("io error: {0}")]
+    IOError(#[from] io::Error),
+    /// The image could not be decoded or the thumbnail could not be encoded.
+    #[error("cannot render thumbnail: {0}")]
+    RenderError(String),
+}
+
+impl From<media::Error> for Error {
+    fn from(value: media::Error) -> Self {
+        Error::MediaError(Box::new(value))
+    }
+}
+
+impl From<RasterError> for Error {
+    fn from(value: RasterError) -> Self {
+        match value {
+            RasterError::Io(err) => Error::IOError(err),
+            err => Error::RenderError(format!("{:?}", err)),
+        }
+    }
+}
+
+/// The progress of [`Thumbnails::generate`], reported after each media.
+#[derive(Debug)]
+pub struct Progress {
+    /// The media that has just been processed.
+    pub media_id: i64,
+    /// How many media have been processed so far.
+    pub done: usize,
+    /// How many media have to be processed in total.
+    pub total: usize,
+}
+
+/// The outcome of [`Thumbnails::generate`].
+#[derive(Debug, Default)]
+pub struct GenerationReport {
+    /// How many thumbnails are now available in the cache.
+    pub generated: usize,
+    /// The media for which thumbnails could not be rendered, e.g. because
+    /// they are not images or their file is missing.
+    pub failed: Vec<i64>,
+}
+
+#[derive(Debug, Queryable)]
+struct Thumbnail {
+    file_name: String,
+    source_size: i64,
+    source_modified: i64,
+    source_hash: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = thumbnails)]
+struct NewThumbnail<'a> {
+    media_id: i64,
+    size: i32,
+    file_name: &'a str,
+    source_size: i64,
+    source_modified: i64,
+    source_hash: Option<&'a str>,
+    bytes: i64,
+    last_accessed: i64,
+}
+
+impl Thumbnails {
+    /// Sets the maximum size, in bytes, of the thumbnails cache.
+    pub fn with_max_cache_size(mut self, max_cache_size: u64) -> Self {
+        self.max_cache_size = max_cache_size;
+        self
+    }
+
+    /// Gets the path of the thumbnail of the image with the provided ID,
+    /// fitting in a square of `size` pixels, rendering it if it is not in the
+    /// cache or if the image changed since it was rendered.
+    ///
+    /// It returns an error in case the size is invalid, the media is not an
+    /// image, is stored inside an archive or its file cannot be read or
+    /// decoded.
+    pub fn get(&self, media_id: i64, size: u32) -> Result<PathBuf, Error> {
+        if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+            return Err(Error::InvalidSize);
+        }
+
+        let media_service = media::media(self.connection.clone());
+        let file = media_service.get(media_id)?;
+        if !matches!(file.media_type, MediaType::Image) {
+            return Err(Error::NotAnImage);
+        }
+
+        if file.archive_entry.is_some() {
+            return Err(Error::ArchiveEntry);
+        }
+
+        let directory = self.cache_directory()?;
+        let source = media_service.absolute_path(&file)?;
+        let source_metadata = fs::metadata(&source)?;
+        let source_size = source_metadata.len() as i64;
+        let source_modified = source_metadata
+            .modified()
+            .map(unix_timestamp)
+            .unwrap_or_default();
+
+        use database::schema::thumbnails::dsl::{
+            file_name, last_accessed, media_id as t_media_id, size as t_size, source_hash,
+            source_modified as t_modified, source_size as t_source_size,
+        };
+        let conn = &mut self.connection.establish_connection()?;
+        let existing = match thumbnails_table
+            .select((file_name, t_source_size, t_modified, source_hash))
+            .filter(t_media_id.eq(media_id))
+            .filter(t_size.eq(size as i32))
+            .first::<Thumbnail>(conn)
+        {
+            Ok(val) => Some(val),
+            Err(diesel::NotFound) => None,
+            Err(err) => return Err(Error::DatabaseError(err)),
+        };
+
+        if let Some(thumb) = &existing {
+            if thumb.source_size == source_size
+                && thumb.source_modified == source_modified
+                && thumb.source_hash == file.content_hash
+                && directory.join(&thumb.file_name).is_file()
+            {
+                diesel::update(
+                    thumbnails_table
+                        .filter(t_media_id.eq(media_id))
+                        .filter(t_size.eq(size as i32)),
+                )
+                .set(last_accessed.eq(now_millis()))
+                .execute(conn)?;
+
+                return Ok(directory.join(&thumb.file_name));
+            }
+        }
+
+        let name = match &file.content_hash {
+            Some(content_hash) => format!("{}-{}.png", content_hash, size),
+            None => {
+                let key = format!("{}:{}:{}", source.display(), source_size, source_modified);
+                format!("{}-{}.png", blake3::hash(key.as_bytes()).to_hex(), size)
+            }
+        };
+        let path = directory.join(&name);
+
+        // A thumbnail with the same name may have been rendered for a
+        // duplicate of this image: it can be reused, unless this one is
+        // being invalidated.
+        if existing.is_some() || !path.is_file() {
+            self.render(&media_service, &file, &source, size, &path)?;
+        }
+
+        let bytes = fs::metadata(&path)?.len() as i64;
+        conn.transaction(|conn| {
+            diesel::delete(
+                thumbnails_table
+                    .filter(t_media_id.eq(media_id))
+                    .filter(t_size.eq(size as i32)),
+            )
+            .execute(conn)?;
+
+            diesel::insert_into(thumbnails_table)
+                .values(NewThumbnail {
+                    media_id,
+                    size: size as i32,
+                    file_name: &name,
+                    source_size,
+                    source_modified,
+                    source_hash: file.content_hash.as_deref(),
+                    bytes,
+                    last_accessed: now_millis(),
+                })
+                .execute(conn)
+        })?;
+
+        if let Some(thumb) = existing.filter(|thumb| thumb.file_name != name) {
+            self.remove_unreferenced(&thumb.file_name)?;
+        }
+
+        self.evict()?;
+        Ok(path)
+    }
+
+    /// Renders the thumbnails of the provided media in all the provided
+    /// sizes, so that they are ready when needed, calling `progress` after
+    /// each media.
+    ///
+    /// Media whose thumbnails cannot be rendered are reported in the
+    /// [`GenerationReport`] instead of stopping the generation.
+    pub fn generate(
+        &self,
+        media_ids: impl IntoIterator<Item = i64>,
+        sizes: &[u32],
+        mut progress: impl FnMut(&Progress),
+    ) -> Result<GenerationReport, Error> {
+        if sizes
+            .iter()
+            .any(|size| !(MIN_SIZE..=MAX_SIZE).contains(size))
+        {
+            return Err(Error::InvalidSize);
+        }
+
+        let ids: Vec<i64> = media_ids.into_iter().collect();
+        let mut report = GenerationReport::default();
+        for (i, id) in ids.iter().enumerate() {
+            for size in sizes {
+                match self.get(*id, *size) {
+                    Ok(_) => report.generated += 1,
+                    Err(Error::DatabaseError(err)) => return Err(Error::DatabaseError(err)),
+                    Err(Error::ConnectionError(err)) => return Err(Error::ConnectionError(err)),
+                    Err(_) => {
+                        report.failed.push(*id);
+                        break;
+                    }
+                }
+            }
+
+            progress(&Progress {
+                media_id: *id,
+                done: i + 1,
+                total: ids.len(),
+            });
+        }
+
+        Ok(report)
+    }
+
+    /// Evicts the least recently used thumbnails until the cache fits in its
+    /// maximum size.
+    ///
+    /// It returns the number of bytes that have been freed.
+    pub fn evict(&self) -> Result<u64, Error> {
+        use database::schema::thumbnails::dsl::{bytes, file_name, id, last_accessed};
+        let conn = &mut self.connection.establish_connection()?;
+        let rows = thumbnails_table
+            .select((file_name, bytes, last_accessed))
+            .order((last_accessed.asc(), id.asc()))
+            .load::<(String, i64, i64)>(conn)?;
+
+        // The same file can be shared by more than one media: it is used as
+        // recently as its most recent access.
+        let mut files: HashMap<String, (u64, i64)> = HashMap::new();
+        for (name, size, accessed) in rows {
+            let entry = files.entry(name).or_insert((size as u64, accessed));
+            entry.1 = entry.1.max(accessed);
+        }
+
+        let mut total: u64 = files.values().map(|(size, _)| size).sum();
+        let mut by_access: Vec<(String, u64, i64)> = files
+            .into_iter()
+            .map(|(name, (size, accessed))| (name, size, accessed))
+            .collect();
+        by_access.sort_by(|a, b| a.2.cmp(&b.2).then_with(|| a.0.cmp(&b.0)));
+
+        let mut freed = 0;
+        for (name, size, _) in by_access {
+            if total <= self.max_cache_size {
+                break;
+            }
+
+            diesel::delete(thumbnails_table.filter(file_name.eq(&name))).execute(conn)?;
+            self.remove_file(&name)?;
+            total -= size;
+            freed += size;
+        }
+
+        Ok(freed)
+    }
+
+    /// Removes all the thumbnails of the media with the provided ID.
+    pub fn remove(&self, media_id: i64) -> Result<(), Error> {
+        use database::schema::thumbnails::dsl::{file_name, media_id as t_media_id};
+        let conn = &mut self.connection.establish_connection()?;
+        let names = thumbnails_table
+            .select(file_name)
+            .filter(t_media_id.eq(media_id))
+            .load::<String>(conn)?;
+
+        diesel::delete(thumbnails_table.filter(t_media_id.eq(media_id))).execute(conn)?;
+        for name in names {
+            self.remove_unreferenced(&name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes all the thumbnails from the cache.
+    pub fn clear(&self) -> Result<(), Error> {
+        use database::schema::thumbnails::dsl::file_name;
+        let conn = &mut self.connection.establish_connection()?;
+        let names = thumbnails_table
+            .select(file_name)
+            .distinct()
+            .load::<String>(conn)?;
+
+        diesel::delete(thumbnails_table).execute(conn)?;
+        for name in names {
+            self.remove_file(&name)?;
+        }
+
+        Ok(())
+    }
+
+    fn render(
+        &self,
+        media_service: &Media,
+        file: &MediaFile,
+        source: &Path,
+        size: u32,
+        destination: &Path,
+    ) -> Result<(), Error> {
+        let mut image = match source.to_str() {
+            None => return Err(Error::RenderError("path is not valid UTF-8".into())),
+            Some(p) => raster::open(p)?,
+        };
+
+        if image.width > size as i32 || image.height > size as i32 {
+            raster::transform::resize_fit(&mut image, size as i32, size as i32)?;
+        }
+
+        let orientation = match media_service.get_metadata(file.id) {
+            Ok(metadata) => metadata.orientation,
+            Err(media::Error::MetadataNotFound) => None,
+            Err(err) => return Err(err.into()),
+        };
+        // Mirrored orientations are rotated first, then flipped.
+        let (rotations, flip) = match orientation {
+            Some(2) => (0, Some(TransformMode::Horizontal)),
+            Some(3) => (2, None),
+            Some(4) => (0, Some(TransformMode::Vertical)),
+            Some(5) => (1, Some(TransformMode::Horizontal)),
+            Some(6) => (1, None),
+            Some(7) => (1, Some(TransformMode::Vertical)),
+            Some(8) => (3, None),
+            _ => (0, None),
+        };
+        for _ in 0..rotations {
+            image = rotate_clockwise(&image);
+        }
+        if let Some(mode) = flip {
+            raster::transform::flip(&mut image, mode)?;
+        }
+
+        // Render to a temporary file first, so that a partially written
+        // thumbnail is never served.
+        let temporary = destination.with_extension("tmp.png");
+        match temporary.to_str() {
+            None => return Err(Error::RenderError("path is not valid UTF-8".into())),
+            Some(p) => raster::save(&image, p)?,
+        };
+        fs::rename(temporary, destination)?;
+
+        Ok(())
+    }
+
+    fn cache_directory(&self) -> Result<PathBuf, Error> {
+        let directory = match self.connection.data_directory() {
+            None => return Err(Error::NoCacheDirectory),
+            Some(dir) => dir.join(THUMBNAILS_DIRECTORY_NAME),
+        };
+
+        fs::create_dir_all(&directory)?;
+        Ok(directory)
+    }
+
+    fn remove_unreferenced(&self, name: &str) -> Result<(), Error> {
+        use database::schema::thumbnails::dsl::file_name;
+        let conn = &mut self.connection.establish_connection()?;
+        let references: i64 = thumbnails_table
+            .filter(file_name.eq(name))
+            .count()
+            .get_result(conn)?;
+
+        if references == 0 {
+            self.remove_file(name)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_file(&self, name: &str) -> Result<(), Error> {
+        match fs::remove_file(self.cache_directory()?.join(name)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(Error::IOError(err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The access time is in milliseconds, so that thumbnails rendered in the
+/// same batch can still be told apart when evicting.
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn rotate_clockwise(image: &Image) -> Image {
+    let mut rotated = Image::blank(image.height, image.width);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let from = ((y * image.width + x) * 4) as usize;
+            let to = ((x * rotated.width + (rotated.width - 1 - y)) * 4) as usize;
+            rotated.bytes[to..to + 4].copy_from_slice(&image.bytes[from..from + 4]);
+        }
+    }
+
+    rotated
+}
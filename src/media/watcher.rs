@@ -0,0 +1,435 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use notify::{
+    event::{ModifyKind, RenameMode},
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher as _,
+};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, FileIdMap};
+use thiserror::Error;
+
+use crate::{
+    data::{
+        base_path::BasePath,
+        media_file::{MediaFile, MediaType},
+    },
+    database::connection::DatabaseConnection,
+    media::{
+        archive,
+        base_paths::{self, base_paths},
+        media::{self, CreateMediaFile, Media},
+        scanner,
+    },
+};
+
+const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watcher monitors all registered base paths and keeps the media files in
+/// sync with what happens on disk.
+pub struct Watcher {
+    connection: DatabaseConnection,
+    delete_policy: DeletePolicy,
+    debounce: Duration,
+}
+
+/// This returns a new instance of the `Watcher` struct, that marks deleted
+/// files as missing and debounces events for two seconds.
+pub fn watcher(connection: DatabaseConnection) -> Watcher {
+    Watcher {
+        connection,
+        delete_policy: DeletePolicy::MarkMissing,
+        debounce: DEFAULT_DEBOUNCE,
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The base paths to watch could not be listed.
+    #[error("base path error: {0}")]
+    BasePathsError(#[from] base_paths::Error),
+    /// The file system watcher could not be started.
+    #[error("notify error: {0}")]
+    NotifyError(#[from] notify::Error),
+}
+
+/// What to do with a media file when its file is deleted from disk.
+#[derive(Debug, Clone, Copy)]
+pub enum DeletePolicy {
+    /// Delete the media file. Media files that are still tagged cannot be
+    /// deleted and are marked as missing instead.
+    Remove,
+    /// Keep the media file, marked as missing, so that it can be reviewed.
+    MarkMissing,
+}
+
+/// A change applied by the watcher to the media files.
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    /// A new file has been registered.
+    Created { media_id: i64 },
+    /// A file has been moved or renamed: its relative path, and possibly its
+    /// base path, have been updated.
+    Moved {
+        media_id: i64,
+        from: String,
+        to: String,
+    },
+    /// A file has been deleted and so has its media file.
+    Removed {
+        media_id: i64,
+        relative_path: String,
+    },
+    /// A file has been deleted and its media file marked as missing.
+    MarkedMissing { media_id: i64 },
+    /// A file previously marked as missing is back on disk.
+    Restored { media_id: i64 },
+    /// An event could not be applied.
+    Failed { path: PathBuf, error: String },
+}
+
+/// A watcher that is running in the background. It stops when dropped.
+pub struct RunningWatcher {
+    debouncer: Option<Debouncer<RecommendedWatcher, FileIdMap>>,
+    thread: Option<JoinHandle<()>>,
+    subscribers: Arc<Mutex<Vec<Sender<WatchEvent>>>>,
+}
+
+impl Watcher {
+    /// Sets what to do with media files whose file is deleted.
+    pub fn with_delete_policy(mut self, delete_policy: DeletePolicy) -> Self {
+        self.delete_policy = delete_policy;
+        self
+    }
+
+    /// Sets for how long events are collected before being applied, so that
+    /// e.g. a file being copied is registered only once.
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Starts watching all the base paths that are currently registered and
+    /// mounted, in a background thread.
+    ///
+    /// Base paths registered after the watcher started are not watched: the
+    /// watcher must be restarted.
+    pub fn start(self) -> Result<RunningWatcher, Error> {
+        let watched: Vec<BasePath> = base_paths(self.connection.clone())
+            .list(None::<Vec<_>>)?
+            .into_iter()
+            .filter(|bp| base_paths::status(bp) == base_paths::Status::Online)
+            .collect();
+
+        let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+        let mut debouncer = new_debouncer(self.debounce, None, tx)?;
+        for bp in &watched {
+            debouncer
+                .watcher()
+                .watch(Path::new(&bp.base_path), RecursiveMode::Recursive)?;
+            debouncer
+                .cache()
+                .add_root(Path::new(&bp.base_path), RecursiveMode::Recursive);
+        }
+
+        let subscribers = Arc::new(Mutex::new(vec![]));
+        let handler = EventHandler {
+            media: media::media(self.connection),
+            base_paths: watched,
+            delete_policy: self.delete_policy,
+            subscribers: subscribers.clone(),
+        };
+
+        // The thread ends when the debouncer is stopped, since that drops
+        // the sending side of the channel.
+        let thread = thread::spawn(move || {
+            for result in rx {
+                match result {
+                    Ok(events) => events.iter().for_each(|event| handler.handle(event)),
+                    Err(errors) => errors.into_iter().for_each(|err| {
+                        handler.emit(WatchEvent::Failed {
+                            path: err.paths.first().cloned().unwrap_or_default(),
+                            error: err.to_string(),
+                        })
+                    }),
+                }
+            }
+        });
+
+        Ok(RunningWatcher {
+            debouncer: Some(debouncer),
+            thread: Some(thread),
+            subscribers,
+        })
+    }
+}
+
+impl RunningWatcher {
+    /// Returns a channel that receives all the changes applied by the
+    /// watcher from now on.
+    pub fn subscribe(&self) -> Receiver<WatchEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Stops the watcher, waiting for the pending events to be applied.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(debouncer) = self.debouncer.take() {
+            debouncer.stop();
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for RunningWatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+struct EventHandler {
+    media: Media,
+    base_paths: Vec<BasePath>,
+    delete_policy: DeletePolicy,
+    subscribers: Arc<Mutex<Vec<Sender<WatchEvent>>>>,
+}
+
+impl EventHandler {
+    fn handle(&self, event: &notify::Event) {
+        let result = match (event.kind, event.paths.as_slice()) {
+            (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) => {
+                self.moved(from, to)
+            }
+            (EventKind::Create(_), paths)
+            | (EventKind::Modify(ModifyKind::Name(RenameMode::To)), paths) => {
+                paths.iter().try_for_each(|path| self.created(path))
+            }
+            (EventKind::Remove(_), paths)
+            | (EventKind::Modify(ModifyKind::Name(RenameMode::From)), paths) => {
+                paths.iter().try_for_each(|path| self.removed(path))
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(err) = result {
+            self.emit(WatchEvent::Failed {
+                path: event.paths.first().cloned().unwrap_or_default(),
+                error: err.to_string(),
+            });
+        }
+    }
+
+    fn emit(&self, event: WatchEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Returns the ID of the base path that contains the provided path and
+    /// the path relative to it. Hidden files, i.e. with a component starting
+    /// with a `.`, are ignored like the scanner does.
+    fn locate(&self, path: &Path) -> Option<(&BasePath, String)> {
+        let bp = self
+            .base_paths
+            .iter()
+            .filter(|bp| path.starts_with(&bp.base_path))
+            .max_by_key(|bp| bp.base_path.len())?;
+
+        let relative = path.strip_prefix(&bp.base_path).ok()?;
+        let is_hidden = relative.components().any(|component| match component {
+            Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+            _ => false,
+        });
+
+        match relative.to_str() {
+            Some(rp) if !rp.is_empty() && !is_hidden => Some((bp, rp.to_string())),
+            _ => None,
+        }
+    }
+
+    fn created(&self, path: &Path) -> Result<(), media::Error> {
+        let Some((bp, rp)) = self.locate(path) else {
+            return Ok(());
+        };
+
+        if path.is_dir() {
+            let mut files: Vec<_> = scanner::scan(path)?
+                .into_iter()
+                .map(|file| file.relative_path)
+                .collect();
+            // Archives are listed once per entry.
+            files.dedup();
+            for file in files {
+                self.created(&path.join(file))?;
+            }
+            return Ok(());
+        }
+
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        if archive::is_archive(path) {
+            for entry in archive::list_entries(path)? {
+                self.register(bp, &rp, Some(entry.name), entry.media_type, entry.size)?;
+            }
+            return Ok(());
+        }
+
+        let media_type = MediaType::from_path(path);
+        if matches!(media_type, MediaType::Unknown) {
+            return Ok(());
+        }
+
+        self.register(bp, &rp, None, media_type, fs::metadata(path)?.len())
+    }
+
+    /// Creates the media for a file, or an entry of an archive, unless it is
+    /// already registered, in which case it is restored if it was missing.
+    fn register(
+        &self,
+        bp: &BasePath,
+        rp: &str,
+        archive_entry: Option<String>,
+        media_type: MediaType,
+        size: u64,
+    ) -> Result<(), media::Error> {
+        let existing = match &archive_entry {
+            None => self.media.get_by_relative_path(bp.id, rp),
+            Some(entry) => self.media.get_archive_entry(bp.id, rp, entry),
+        };
+
+        match existing {
+            Ok(existing) if existing.missing => {
+                self.media.set_missing(existing.id, false)?;
+                self.emit(WatchEvent::Restored {
+                    media_id: existing.id,
+                });
+            }
+            Ok(_) => (),
+            Err(media::Error::NotFound) => {
+                let created = self.media.create(CreateMediaFile {
+                    relative_path: rp.to_string(),
+                    base_path_id: bp.id,
+                    width: None,
+                    height: None,
+                    size: size as f64 / 1024.0,
+                    mark: None,
+                    description: String::new(),
+                    media_type,
+                    archive_entry,
+                });
+
+                match created {
+                    Ok(file) => self.emit(WatchEvent::Created { media_id: file.id }),
+                    // Empty files, e.g. still being written, cannot be
+                    // registered.
+                    Err(media::Error::InvalidSize) => (),
+                    Err(err) => return Err(err),
+                }
+            }
+            Err(err) => return Err(err),
+        }
+
+        Ok(())
+    }
+
+    fn removed(&self, path: &Path) -> Result<(), media::Error> {
+        // The file may have been created again while debouncing.
+        if path.exists() {
+            return Ok(());
+        }
+
+        let Some((bp, rp)) = self.locate(path) else {
+            return Ok(());
+        };
+
+        for file in self.files_under(bp.id, &rp)? {
+            let remove = match self.delete_policy {
+                DeletePolicy::Remove => self.media.delete(file.id),
+                DeletePolicy::MarkMissing => Err(media::Error::InUse),
+            };
+
+            match remove {
+                Ok(_) => self.emit(WatchEvent::Removed {
+                    media_id: file.id,
+                    relative_path: file.relative_path,
+                }),
+                Err(media::Error::InUse) => {
+                    if !file.missing {
+                        self.media.set_missing(file.id, true)?;
+                        self.emit(WatchEvent::MarkedMissing { media_id: file.id });
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn moved(&self, from: &Path, to: &Path) -> Result<(), media::Error> {
+        let (Some((from_bp, from_rp)), Some((to_bp, to_rp))) = (self.locate(from), self.locate(to))
+        else {
+            // Moved from or to a path that is not watched.
+            self.removed(from)?;
+            return self.created(to);
+        };
+
+        let files = self.files_under(from_bp.id, &from_rp)?;
+        if files.is_empty() {
+            return self.created(to);
+        }
+
+        for file in files {
+            let new_rp = format!("{}{}", to_rp, &file.relative_path[from_rp.len()..]);
+            self.media.set_location(file.id, to_bp.id, &new_rp)?;
+            if file.missing {
+                self.media.set_missing(file.id, false)?;
+            }
+
+            self.emit(WatchEvent::Moved {
+                media_id: file.id,
+                from: file.relative_path,
+                to: new_rp,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the media file at the provided relative path or, if it is a
+    /// directory, all the media files inside it.
+    fn files_under(
+        &self,
+        base_path_id: i32,
+        relative_path: &str,
+    ) -> Result<Vec<MediaFile>, media::Error> {
+        let prefix = format!("{}/", relative_path);
+        Ok(self
+            .media
+            .list(base_path_id)?
+            .into_iter()
+            .filter(|file| {
+                file.relative_path == relative_path || file.relative_path.starts_with(&prefix)
+            })
+            .collect())
+    }
+}
@@ -1,12 +1,18 @@
-use diesel::{ExpressionMethods, Insertable, QueryDsl, RunQueryDsl};
+use std::collections::HashMap;
+
+use diesel::{dsl::count_star, ExpressionMethods, Insertable, QueryDsl, RunQueryDsl};
 use raster::Color;
+use serde::Serialize;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
     data::tag_category::Category,
     database::{self, connection::DatabaseConnection, schema::tag_categories},
-    tags::tags::tags,
+    tags::{
+        fuzzy::{self, Names},
+        tags::tags,
+    },
 };
 
 const MAX_DESCRIPTION_LENGTH: usize = 300;
@@ -155,6 +161,17 @@ impl From<Category> for CreateTagCategory {
     }
 }
 
+/// A tag category suggested while typing its name.
+#[derive(Debug, Serialize)]
+pub struct CategorySuggestion {
+    pub category: Category,
+    /// How many tags belong to it.
+    pub tags: i64,
+    /// How well it matches, combining the match quality and the number of
+    /// tags: the higher, the better.
+    pub score: f64,
+}
+
 impl TagCategories {
     /// Creates a new tag category.
     ///
@@ -242,6 +259,68 @@ impl TagCategories {
             .collect())
     }
 
+    /// Suggests at most `limit` categories for the provided text, e.g. what
+    /// the user typed so far, from the best one.
+    ///
+    /// It works as [`Tags::autocomplete`](crate::tags::tags::Tags::autocomplete),
+    /// ranking first the categories with more tags.
+    ///
+    /// It returns an error in case the text is empty or if there was an
+    /// error on the database.
+    pub fn autocomplete(
+        &self,
+        text: impl AsRef<str>,
+        limit: usize,
+    ) -> Result<Vec<CategorySuggestion>, Error> {
+        let text = fuzzy::normalize(text.as_ref());
+        if text.is_empty() {
+            return Err(Error::InvalidName);
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        let ids = fuzzy::candidates(conn, Names::Categories, &text)?;
+
+        use database::schema::tag_categories::dsl::{id, tag_categories as tc_table};
+        let matches: Vec<(Category, f64)> = tc_table
+            .filter(id.eq_any(&ids))
+            .load::<Category>(conn)?
+            .into_iter()
+            .filter_map(|category| fuzzy::quality(&text, &category.name).map(|q| (category, q)))
+            .collect();
+
+        let counts: HashMap<i32, i64> = {
+            use database::schema::tags::dsl::{category_id, tags as tags_table};
+            tags_table
+                .filter(category_id.eq_any(matches.iter().map(|(category, _)| category.id)))
+                .group_by(category_id)
+                .select((category_id, count_star()))
+                .load::<(i32, i64)>(conn)?
+                .into_iter()
+                .collect()
+        };
+
+        let max_count = counts.values().copied().max().unwrap_or_default();
+        let mut suggestions: Vec<CategorySuggestion> = matches
+            .into_iter()
+            .map(|(category, quality)| {
+                let tags = counts.get(&category.id).copied().unwrap_or_default();
+                CategorySuggestion {
+                    category,
+                    tags,
+                    score: fuzzy::score(quality, tags, max_count),
+                }
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.category.name.cmp(&b.category.name))
+        });
+        suggestions.truncate(limit);
+        Ok(suggestions)
+    }
+
     /// List all tag categories that are currently being saved on the database.
     ///
     /// Optionally, you can list only some specific IDs with `ids`.
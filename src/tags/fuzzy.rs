@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+
+use diesel::{
+    sql_query,
+    sql_types::{BigInt, Integer, Text},
+    QueryResult, QueryableByName, RunQueryDsl, SqliteConnection,
+};
+
+/// How many candidates are read from each index before ranking them.
+const MAX_CANDIDATES: i64 = 200;
+/// Names whose similarity with the text is lower than this are not
+/// suggested.
+const MIN_SIMILARITY: f64 = 0.7;
+/// How much the usage weighs in the score, the rest being the match
+/// quality.
+const USAGE_WEIGHT: f64 = 0.2;
+
+/// The names that can be autocompleted, along with their trigram and word
+/// indexes.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Names {
+    Tags,
+    Categories,
+}
+
+impl Names {
+    fn tables(&self) -> (&'static str, &'static str) {
+        match self {
+            Names::Tags => ("tag_name_words", "tag_name_trigrams"),
+            Names::Categories => ("category_name_words", "category_name_trigrams"),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct Candidate {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+}
+
+/// Names are compared ignoring the case, and `_` is the same as a space,
+/// as for [`Tags::search_by_name`](crate::tags::tags::Tags::search_by_name).
+pub(crate) fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().replace('_', " ")
+}
+
+/// Returns the IDs of the tags or categories whose name may match the
+/// normalized text: those sharing a trigram with it, plus those with a word
+/// starting with its first two characters to catch typos in short words.
+///
+/// Texts shorter than a trigram only match names with a word starting with
+/// them.
+pub(crate) fn candidates(
+    conn: &mut SqliteConnection,
+    names: Names,
+    text: &str,
+) -> QueryResult<Vec<i32>> {
+    let (words_table, trigrams_table) = names.tables();
+    let mut ids: Vec<i32> = vec![];
+
+    let trigrams = trigrams(text);
+    if !trigrams.is_empty() {
+        let query = trigrams
+            .iter()
+            .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        ids.extend(
+            sql_query(format!(
+                "SELECT rowid AS id FROM {trigrams_table} WHERE {trigrams_table} MATCH ? \
+                 ORDER BY rank LIMIT ?"
+            ))
+            .bind::<Text, _>(query)
+            .bind::<BigInt, _>(MAX_CANDIDATES)
+            .load::<Candidate>(conn)?
+            .into_iter()
+            .map(|c| c.id),
+        );
+    }
+
+    // The word index folds the case of all the letters, unlike `lower()`
+    // which only folds ASCII ones.
+    let prefix: String = text
+        .split(|c: char| !c.is_alphanumeric())
+        .find(|word| !word.is_empty())
+        .unwrap_or_default()
+        .chars()
+        .take(2)
+        .collect();
+    if !prefix.is_empty() {
+        ids.extend(
+            sql_query(format!(
+                "SELECT rowid AS id FROM {words_table} WHERE {words_table} MATCH ? LIMIT ?"
+            ))
+            .bind::<Text, _>(format!("\"{prefix}\" *"))
+            .bind::<BigInt, _>(MAX_CANDIDATES)
+            .load::<Candidate>(conn)?
+            .into_iter()
+            .map(|c| c.id),
+        );
+    }
+
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+    Ok(ids)
+}
+
+/// Returns how well the name matches the normalized text, from `0` to `1`,
+/// or `None` if it does not match.
+///
+/// Exact matches come first, then names starting with the text, names with
+/// a word starting with it and names containing it; names that only look
+/// similar, e.g. because of a typo, come last.
+pub(crate) fn quality(text: &str, name: &str) -> Option<f64> {
+    let name = normalize(name);
+    if name == text {
+        return Some(1.0);
+    }
+    if name.starts_with(text) {
+        return Some(0.9);
+    }
+    if name
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(text))
+    {
+        return Some(0.8);
+    }
+    if name.contains(text) {
+        return Some(0.7);
+    }
+
+    let text_length = text.chars().count();
+    if text_length < 3 {
+        return None;
+    }
+
+    // The text may also be the beginning of the name, while typing.
+    let name_start: String = name.chars().take(text_length).collect();
+    let similarity = [name.as_str(), name_start.as_str()]
+        .into_iter()
+        .map(|other| {
+            let length = text_length.max(other.chars().count());
+            1.0 - edit_distance(text, other) as f64 / length as f64
+        })
+        .fold(trigram_similarity(text, &name), f64::max);
+
+    (similarity >= MIN_SIMILARITY).then_some(0.6 * similarity)
+}
+
+/// Combines the match quality with how much the tag or category is used,
+/// relative to the most used candidate.
+pub(crate) fn score(quality: f64, usage: i64, max_usage: i64) -> f64 {
+    let usage = match max_usage {
+        0 => 0.0,
+        _ => (1.0 + usage as f64).ln() / (1.0 + max_usage as f64).ln(),
+    };
+
+    (1.0 - USAGE_WEIGHT) * quality + USAGE_WEIGHT * usage
+}
+
+fn trigrams(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut trigrams: Vec<String> = chars.windows(3).map(|w| w.iter().collect()).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+/// The Dice coefficient of the trigrams of the two texts.
+fn trigram_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let shared = a.iter().filter(|t| b.contains(t)).count();
+    2.0 * shared as f64 / (a.len() + b.len()) as f64
+}
+
+/// The number of insertions, deletions, substitutions and transpositions of
+/// adjacent characters needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in rows[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
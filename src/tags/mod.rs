@@ -1,2 +1,4 @@
 pub mod category;
+pub(crate) mod fuzzy;
+pub mod related;
 pub mod tags;
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use diesel::{
+    dsl::count_star,
+    sql_query,
+    sql_types::{BigInt, Integer},
+    ExpressionMethods, QueryDsl, QueryableByName, RunQueryDsl, SqliteConnection,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    data::tag::Tag,
+    database::{self, connection::DatabaseConnection, schema::tags::dsl::tags as tags_table},
+    media::{
+        media::{self, media},
+        query::MediaQuery,
+        search,
+    },
+    tags::tags::{self, tags},
+};
+
+/// RelatedTags finds the tags used together, from how media are tagged, e.g.
+/// to suggest `sea` and `summer` for a photo tagged with `beach`.
+///
+/// How strongly two tags are related is measured by their lift: how much
+/// more often they are used together than if they were unrelated. A lift of
+/// 1 means they are independent, above 1 that they tend to go together.
+pub struct RelatedTags {
+    connection: DatabaseConnection,
+    min_count: i64,
+    ranking: Ranking,
+}
+
+/// This returns a new instance of the `RelatedTags` struct, that ignores
+/// tags used together less than twice and ranks them by lift.
+pub fn related_tags(connection: DatabaseConnection) -> RelatedTags {
+    RelatedTags {
+        connection,
+        min_count: 2,
+        ranking: Ranking::Lift,
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The operation could not be performed because the database returned an
+    /// error.
+    #[error("database error {0}")]
+    DatabaseError(#[from] diesel::result::Error),
+    /// It was not possible to establish a connection to the database.
+    #[error("connection error: {0}")]
+    ConnectionError(#[from] database::connection::Error),
+    /// No tags have been provided.
+    #[error("no tags have been provided")]
+    NoTagsProvided,
+    /// A tag could not be found.
+    #[error("tag error: {0}")]
+    TagError(#[from] tags::Error),
+    /// The media could not be found or the query is invalid.
+    #[error("media error: {0}")]
+    MediaError(#[from] media::Error),
+}
+
+/// How related tags and suggestions are ranked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ranking {
+    /// The tags used together the most often come first, even if they are
+    /// used with everything, e.g. `outdoor`.
+    Frequency,
+    /// The tags whose use is the most tied to the provided ones come first.
+    Lift,
+}
+
+/// A tag used together with the provided ones.
+#[derive(Debug, Serialize)]
+pub struct RelatedTag {
+    pub tag: Tag,
+    /// How many media are tagged with it and all the provided tags.
+    pub count: i64,
+    /// The share of the media tagged with all the provided tags that are
+    /// also tagged with it, from `0` to `1`.
+    pub confidence: f64,
+    /// How many times more often it is used with the provided tags than with
+    /// any media.
+    pub lift: f64,
+    /// The pointwise mutual information, i.e. the base 2 logarithm of the
+    /// lift: positive if the tags tend to go together.
+    pub pmi: f64,
+}
+
+/// A tag suggested for a media.
+#[derive(Debug, Serialize)]
+pub struct SuggestedTag {
+    pub tag: Tag,
+    /// The mean confidence of the tag over the tags of the media, from `0` to
+    /// `1`: the higher, the better.
+    pub score: f64,
+    /// The highest lift of the tag with one of the tags of the media.
+    pub lift: f64,
+    /// The tags of the media it is used with, from the most related one.
+    pub because_of: Vec<i32>,
+}
+
+/// A tag that would narrow the results of a query.
+#[derive(Debug, Serialize)]
+pub struct Refinement {
+    pub tag: Tag,
+    /// How many results are tagged with it, i.e. are left when filtering by
+    /// it.
+    pub count: i64,
+    /// The share of the results that are tagged with it, from `0` to `1`.
+    pub fraction: f64,
+}
+
+#[derive(QueryableByName)]
+struct CoOccurrence {
+    #[diesel(sql_type = Integer)]
+    tag_id: i32,
+    #[diesel(sql_type = Integer)]
+    source_id: i32,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+    #[diesel(sql_type = BigInt)]
+    total: i64,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+impl RelatedTags {
+    /// Ignores the tags used together less than `min_count` times, whose lift
+    /// is not meaningful. Setting it to 1 keeps all of them.
+    pub fn with_min_count(mut self, min_count: i64) -> Self {
+        self.min_count = min_count.max(1);
+        self
+    }
+
+    /// Ranks the tags with the provided ranking instead of by lift.
+    pub fn with_ranking(mut self, ranking: Ranking) -> Self {
+        self.ranking = ranking;
+        self
+    }
+
+    /// Returns at most `limit` tags used together with all the provided
+    /// tags, i.e. on the media tagged with all of them, from the best one.
+    ///
+    /// It returns an error in case no tags are provided, a tag was not found
+    /// or if there was an error on the database.
+    pub fn related(
+        &self,
+        tag_ids: impl IntoIterator<Item = i32>,
+        limit: usize,
+    ) -> Result<Vec<RelatedTag>, Error> {
+        let mut tag_ids: Vec<i32> = tag_ids.into_iter().collect();
+        tag_ids.sort_unstable();
+        tag_ids.dedup();
+        if tag_ids.is_empty() {
+            return Err(Error::NoTagsProvided);
+        }
+        for id in &tag_ids {
+            tags(self.connection.clone()).get(*id)?;
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        // The IDs are integers, so they can be written in the query.
+        let ids = join(&tag_ids);
+        let context = format!(
+            "SELECT media_id FROM media_tags WHERE tag_id IN ({ids}) \
+             GROUP BY media_id HAVING COUNT(DISTINCT tag_id) = {}",
+            tag_ids.len()
+        );
+        let matching = sql_query(format!("SELECT COUNT(*) AS count FROM ({context})"))
+            .get_result::<Count>(conn)?
+            .count;
+        let rows = sql_query(format!(
+            "SELECT mt.tag_id, 0 AS source_id, COUNT(*) AS count, \
+             (SELECT COUNT(*) FROM media_tags t WHERE t.tag_id = mt.tag_id) AS total \
+             FROM media_tags mt WHERE mt.media_id IN ({context}) AND mt.tag_id NOT IN ({ids}) \
+             GROUP BY mt.tag_id HAVING COUNT(*) >= ?"
+        ))
+        .bind::<BigInt, _>(self.min_count)
+        .load::<CoOccurrence>(conn)?;
+        let media_count = media_count(conn)?;
+        let mut tags = load_tags(conn, rows.iter().map(|row| row.tag_id))?;
+
+        let mut related: Vec<RelatedTag> = rows
+            .into_iter()
+            .filter_map(|row| {
+                let lift = lift(row.count, matching, row.total, media_count);
+                Some(RelatedTag {
+                    tag: tags.remove(&row.tag_id)?,
+                    count: row.count,
+                    confidence: row.count as f64 / matching as f64,
+                    lift,
+                    pmi: lift.log2(),
+                })
+            })
+            .collect();
+
+        related.sort_by(|a, b| {
+            let (a_key, b_key) = match self.ranking {
+                Ranking::Frequency => (a.count as f64, b.count as f64),
+                Ranking::Lift => (a.lift, b.lift),
+            };
+            b_key
+                .total_cmp(&a_key)
+                .then_with(|| b.count.cmp(&a.count))
+                .then_with(|| a.tag.name.cmp(&b.tag.name))
+        });
+        related.truncate(limit);
+        Ok(related)
+    }
+
+    /// Suggests at most `limit` tags for a media, from the tags used together
+    /// with its current tags, from the best one. A media without tags gets
+    /// no suggestions.
+    ///
+    /// It returns an error in case the media was not found or if there was an
+    /// error on the database.
+    pub fn suggest_for_media(
+        &self,
+        media_id: i64,
+        limit: usize,
+    ) -> Result<Vec<SuggestedTag>, Error> {
+        let current: Vec<i32> = media(self.connection.clone())
+            .list_tags_for_media(media_id)?
+            .into_iter()
+            .map(|tag| tag.id)
+            .collect();
+        if current.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        let ids = join(&current);
+        let rows = sql_query(format!(
+            "SELECT mt.tag_id, source.tag_id AS source_id, COUNT(*) AS count, \
+             (SELECT COUNT(*) FROM media_tags t WHERE t.tag_id = mt.tag_id) AS total \
+             FROM media_tags source JOIN media_tags mt ON mt.media_id = source.media_id \
+             WHERE source.tag_id IN ({ids}) AND mt.tag_id NOT IN ({ids}) \
+             GROUP BY mt.tag_id, source.tag_id"
+        ))
+        .load::<CoOccurrence>(conn)?;
+        let media_count = media_count(conn)?;
+
+        use database::schema::media_tags::dsl::{media_tags, tag_id};
+        let source_totals: HashMap<i32, i64> = media_tags
+            .filter(tag_id.eq_any(&current))
+            .group_by(tag_id)
+            .select((tag_id, count_star()))
+            .load::<(i32, i64)>(conn)?
+            .into_iter()
+            .collect();
+
+        // The confidence and lift of each candidate with each tag of the
+        // media it is used with.
+        let mut candidates: HashMap<i32, Vec<(i32, i64, f64, f64)>> = HashMap::new();
+        for row in rows {
+            let source_total = source_totals.get(&row.source_id).copied().unwrap_or(1);
+            candidates.entry(row.tag_id).or_default().push((
+                row.source_id,
+                row.count,
+                row.count as f64 / source_total as f64,
+                lift(row.count, source_total, row.total, media_count),
+            ));
+        }
+        candidates.retain(|_, sources| sources.iter().any(|s| s.1 >= self.min_count));
+
+        let mut tags = load_tags(conn, candidates.keys().copied())?;
+        let mut suggestions: Vec<SuggestedTag> = candidates
+            .into_iter()
+            .filter_map(|(id, mut sources)| {
+                sources.sort_by(|a, b| b.3.total_cmp(&a.3));
+                let confidence: f64 = sources.iter().map(|s| s.2).sum();
+                Some(SuggestedTag {
+                    tag: tags.remove(&id)?,
+                    score: confidence / current.len() as f64,
+                    lift: sources.first().map(|s| s.3).unwrap_or_default(),
+                    because_of: sources.into_iter().map(|s| s.0).collect(),
+                })
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| {
+            let (a_key, b_key) = match self.ranking {
+                Ranking::Frequency => (a.score, b.score),
+                Ranking::Lift => (a.score * a.lift.ln_1p(), b.score * b.lift.ln_1p()),
+            };
+            b_key
+                .total_cmp(&a_key)
+                .then_with(|| a.tag.name.cmp(&b.tag.name))
+        });
+        suggestions.truncate(limit);
+        Ok(suggestions)
+    }
+
+    /// Lists the tags that would narrow the results of the provided query,
+    /// i.e. that are on some of the matching media but not on all of them,
+    /// along with how many results each one would leave, from the largest.
+    /// Pagination of the query is ignored.
+    ///
+    /// It returns an error in case the query is invalid or if there was an
+    /// error on the database.
+    pub fn refinements(&self, query: &MediaQuery) -> Result<Vec<Refinement>, Error> {
+        query.validate()?;
+        let search = query
+            .search()
+            .map(|text| search::resolve(&self.connection, text))
+            .transpose()
+            .map_err(media::Error::SearchError)?;
+
+        use database::schema::{
+            media::dsl::id,
+            media_tags::dsl::{media_id, media_tags, tag_id},
+        };
+        let conn = &mut self.connection.establish_connection()?;
+        let total = query
+            .filtered(search.as_ref())
+            .count()
+            .get_result::<i64>(conn)?;
+        let rows = media_tags
+            .filter(media_id.eq_any(query.filtered(search.as_ref()).select(id)))
+            .group_by(tag_id)
+            .select((tag_id, count_star()))
+            .load::<(i32, i64)>(conn)?;
+
+        let mut tags = load_tags(conn, rows.iter().map(|row| row.0))?;
+        let mut refinements: Vec<Refinement> = rows
+            .into_iter()
+            .filter(|(_, count)| *count < total)
+            .filter_map(|(refinement_id, count)| {
+                Some(Refinement {
+                    tag: tags.remove(&refinement_id)?,
+                    count,
+                    fraction: count as f64 / total as f64,
+                })
+            })
+            .collect();
+
+        refinements.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.tag.name.cmp(&b.tag.name))
+        });
+        Ok(refinements)
+    }
+}
+
+/// How many times more often two sets of tags are used together than if
+/// they were independent.
+fn lift(together: i64, first: i64, second: i64, media_count: i64) -> f64 {
+    if first == 0 || second == 0 {
+        return 0.0;
+    }
+
+    (together as f64 * media_count as f64) / (first as f64 * second as f64)
+}
+
+fn media_count(conn: &mut SqliteConnection) -> Result<i64, Error> {
+    use database::schema::media::dsl::media;
+    media.count().get_result(conn).map_err(Error::DatabaseError)
+}
+
+fn load_tags(
+    conn: &mut SqliteConnection,
+    ids: impl IntoIterator<Item = i32>,
+) -> Result<HashMap<i32, Tag>, Error> {
+    use database::schema::tags::dsl::id;
+    let ids: Vec<i32> = ids.into_iter().collect();
+    Ok(tags_table
+        .filter(id.eq_any(ids))
+        .load::<Tag>(conn)?
+        .into_iter()
+        .map(|tag| (tag.id, tag))
+        .collect())
+}
+
+fn join(ids: &[i32]) -> String {
+    ids.iter().map(i32::to_string).collect::<Vec<_>>().join(",")
+}
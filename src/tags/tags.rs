@@ -1,16 +1,22 @@
-use diesel::{ExpressionMethods, Insertable, QueryDsl, RunQueryDsl};
+use std::collections::HashMap;
+
+use diesel::{dsl::count_star, ExpressionMethods, Insertable, QueryDsl, RunQueryDsl};
+use serde::Serialize;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    data::tag::Tag,
+    data::{tag::Tag, tag_category::Category},
     database::{
         self,
         connection::DatabaseConnection,
         connection::Error as ConnectionError,
         schema::tags::{self, dsl::tags as tags_table},
     },
-    tags::category::{self, tag_categories},
+    tags::{
+        category::{self, tag_categories},
+        fuzzy::{self, Names},
+    },
 };
 
 const MAX_NAME_LENGTH: usize = 50;
@@ -176,6 +182,19 @@ pub struct CreateTag {
     pub description: String,
 }
 
+/// A tag suggested while typing its name.
+#[derive(Debug, Serialize)]
+pub struct TagSuggestion {
+    pub tag: Tag,
+    /// The category of the tag.
+    pub category: Category,
+    /// How many media are tagged with it.
+    pub usage: i64,
+    /// How well it matches, combining the match quality and the usage: the
+    /// higher, the better.
+    pub score: f64,
+}
+
 impl Tags {
     /// Inserts a new tag on the database.
     ///
@@ -309,6 +328,80 @@ impl Tags {
             .collect())
     }
 
+    /// Suggests at most `limit` tags for the provided text, e.g. what the
+    /// user typed so far, from the best one.
+    ///
+    /// The text can match anywhere in the name and may contain typos, e.g.
+    /// `bech` suggests `beach`; texts shorter than 3 characters only match
+    /// the beginning of names. Better matches and tags used by more media
+    /// come first. Names are looked up in an index, not loaded in memory.
+    ///
+    /// It returns an error in case the text is empty or if there was an
+    /// error on the database.
+    pub fn autocomplete(
+        &self,
+        text: impl AsRef<str>,
+        limit: usize,
+    ) -> Result<Vec<TagSuggestion>, Error> {
+        let text = fuzzy::normalize(text.as_ref());
+        if text.is_empty() {
+            return Err(Error::InvalidName);
+        }
+
+        let conn = &mut self.connection.establish_connection()?;
+        let ids = fuzzy::candidates(conn, Names::Tags, &text)?;
+
+        use database::schema::tags::dsl::id;
+        let matches: Vec<(Tag, f64)> = tags_table
+            .filter(id.eq_any(&ids))
+            .load::<Tag>(conn)?
+            .into_iter()
+            .filter_map(|tag| fuzzy::quality(&text, &tag.name).map(|q| (tag, q)))
+            .collect();
+        if matches.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let usage: HashMap<i32, i64> = {
+            use database::schema::media_tags::dsl::{media_tags, tag_id};
+            media_tags
+                .filter(tag_id.eq_any(matches.iter().map(|(tag, _)| tag.id)))
+                .group_by(tag_id)
+                .select((tag_id, count_star()))
+                .load::<(i32, i64)>(conn)?
+                .into_iter()
+                .collect()
+        };
+
+        let categories: HashMap<i32, Category> = tag_categories(self.connection.clone())
+            .list(Some(matches.iter().map(|(tag, _)| tag.category_id)))?
+            .into_iter()
+            .map(|category| (category.id, category))
+            .collect();
+
+        let max_usage = usage.values().copied().max().unwrap_or_default();
+        let mut suggestions: Vec<TagSuggestion> = matches
+            .into_iter()
+            .filter_map(|(tag, quality)| {
+                let usage = usage.get(&tag.id).copied().unwrap_or_default();
+                Some(TagSuggestion {
+                    category: categories.get(&tag.category_id)?.clone(),
+                    tag,
+                    usage,
+                    score: fuzzy::score(quality, usage, max_usage),
+                })
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.tag.name.cmp(&b.tag.name))
+        });
+        suggestions.truncate(limit);
+        Ok(suggestions)
+    }
+
     /// Deletes the tag with the provided id
     ///
     /// Returns the same errors as the `get` function.